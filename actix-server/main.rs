@@ -1,8 +1,11 @@
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{delete, get, post, web, App, HttpResponse, HttpServer, Responder};
 use std::env;
 
 // Import our DB module; adjust the path as needed based on your project structure.
 use db::NetworkDB;
+use suppress::SuppressionRule;
+use alerts::AlertState;
+use assets::AssetTags;
 
 // Existing endpoints
 #[get("/")]
@@ -37,6 +40,151 @@ async fn api_suspicious(db: web::Data<NetworkDB>) -> impl Responder {
     }
 }
 
+// API endpoint that reports today's LLM token usage and budget per provider
+#[get("/api/llm/usage")]
+async fn api_llm_usage(db: web::Data<NetworkDB>) -> impl Responder {
+    match db.get_llm_budget_status(&enrich::provider_names()).await {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// API endpoint that lists the most queried registrable domains
+#[get("/api/dns/domains")]
+async fn api_dns_domains(db: web::Data<NetworkDB>) -> impl Responder {
+    match db.get_dns_domain_summary(100).await {
+        Ok(domains) => HttpResponse::Ok().json(domains),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// API endpoints that list, add and delete alert suppression rules
+#[get("/api/suppressions")]
+async fn api_suppressions(db: web::Data<NetworkDB>) -> impl Responder {
+    match db.list_suppressions().await {
+        Ok(rules) => HttpResponse::Ok().json(rules),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/api/suppressions")]
+async fn api_add_suppression(db: web::Data<NetworkDB>, rule: web::Json<SuppressionRule>) -> impl Responder {
+    match db.add_suppression(rule.into_inner()).await {
+        Ok(id) => HttpResponse::Created().json(serde_json::json!({ "id": id.to_hex() })),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+#[delete("/api/suppressions/{id}")]
+async fn api_delete_suppression(db: web::Data<NetworkDB>, id: web::Path<String>) -> impl Responder {
+    match db.delete_suppression(&id).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct AlertQuery {
+    state: Option<AlertState>,
+    limit: Option<i64>,
+}
+
+// API endpoint that lists alerts, most recently seen first
+#[get("/api/alerts")]
+async fn api_alerts(db: web::Data<NetworkDB>, query: web::Query<AlertQuery>) -> impl Responder {
+    match db.list_alerts(query.state, query.limit.unwrap_or(100)).await {
+        Ok(alerts) => HttpResponse::Ok().json(alerts),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct StateRequest {
+    state: AlertState,
+    by: String,
+}
+
+// API endpoints that move an alert through its lifecycle, assign it and add notes
+#[post("/api/alerts/{id}/state")]
+async fn api_alert_state(db: web::Data<NetworkDB>, id: web::Path<String>, item: web::Json<StateRequest>) -> impl Responder {
+    match db.set_alert_state(&id, item.state, &item.by).await {
+        Ok(alert) => HttpResponse::Ok().json(alert),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct AssignRequest {
+    assignee: Option<String>,
+}
+
+#[post("/api/alerts/{id}/assign")]
+async fn api_alert_assign(db: web::Data<NetworkDB>, id: web::Path<String>, item: web::Json<AssignRequest>) -> impl Responder {
+    match db.assign_alert(&id, item.assignee.as_deref()).await {
+        Ok(alert) => HttpResponse::Ok().json(alert),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct NoteRequest {
+    author: String,
+    text: String,
+}
+
+#[post("/api/alerts/{id}/notes")]
+async fn api_alert_note(db: web::Data<NetworkDB>, id: web::Path<String>, item: web::Json<NoteRequest>) -> impl Responder {
+    match db.add_alert_note(&id, &item.author, &item.text).await {
+        Ok(alert) => HttpResponse::Ok().json(alert),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+// API endpoints that list the asset inventory and tag an asset with its owner, criticality and tags
+#[get("/api/assets")]
+async fn api_assets(db: web::Data<NetworkDB>) -> impl Responder {
+    match db.list_assets().await {
+        Ok(assets) => HttpResponse::Ok().json(assets),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/api/assets/{mac}/tags")]
+async fn api_asset_tags(db: web::Data<NetworkDB>, mac: web::Path<String>, item: web::Json<AssetTags>) -> impl Responder {
+    match db.tag_asset(&mac, item.into_inner()).await {
+        Ok(asset) => HttpResponse::Ok().json(asset),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+// API endpoints that list correlated incidents and show one with its alerts
+#[get("/api/incidents")]
+async fn api_incidents(db: web::Data<NetworkDB>) -> impl Responder {
+    match db.list_incidents(100).await {
+        Ok(incidents) => HttpResponse::Ok().json(incidents),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/api/incidents/{id}")]
+async fn api_incident(db: web::Data<NetworkDB>, id: web::Path<String>) -> impl Responder {
+    match db.get_incident(&id).await {
+        Ok(Some((incident, alerts))) => HttpResponse::Ok().json(serde_json::json!({ "incident": incident, "alerts": alerts })),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+// API endpoint that reports ATT&CK technique coverage and alert counts by tactic
+#[get("/api/attack/coverage")]
+async fn api_attack_coverage(db: web::Data<NetworkDB>) -> impl Responder {
+    match db.get_attack_coverage().await {
+        Ok(coverage) => HttpResponse::Ok().json(coverage),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
 #[derive(serde::Deserialize)]
 struct ReportRequest {
     event_id: String,
@@ -61,6 +209,20 @@ async fn main() -> std::io::Result<()> {
             .service(echo)
             .service(api_events)
             .service(api_suspicious)
+            .service(api_llm_usage)
+            .service(api_dns_domains)
+            .service(api_alerts)
+            .service(api_alert_state)
+            .service(api_alert_assign)
+            .service(api_alert_note)
+            .service(api_assets)
+            .service(api_asset_tags)
+            .service(api_incidents)
+            .service(api_attack_coverage)
+            .service(api_incident)
+            .service(api_suppressions)
+            .service(api_add_suppression)
+            .service(api_delete_suppression)
             .service(api_report)
             .route("/hey", web::get().to(manual_hello))
     })
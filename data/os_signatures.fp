@@ -0,0 +1,95 @@
+; Passive OS fingerprints for client SYN packets, in p0f 3 signature syntax.
+; Only the [tcp:request] section is read; a full p0f.fp at OS_SIGNATURES
+; replaces this file.
+;
+;   label = s|g:class:name:flavor     (s specific, g generic fallback)
+;   sig   = ver:ittl:olen:mss:wsize,scale:olayout:quirks:pclass
+;
+; ver 4, 6 or *; ittl the initial TTL; olen IPv4 option bytes; wsize a
+; number, *, mss*N, mtu*N or %N; olayout the TCP options in order (mss, nop,
+; ws, sok, sack, ts, eol+N); quirks from df, id+, id-, ecn, flow, seq-,
+; ack+, ts1-, ts2+, exws; pclass 0 for no payload, + for some, * for either.
+; Signatures are tried in order and specific ones come before generic ones.
+
+[tcp:request]
+
+; Linux and Android, ChromeOS and most embedded Linux
+label = s:unix:Linux:4.x and newer
+sig   = *:64:0:*:mss*44,7:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*44,8:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*45,7:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*44,7:mss,sok,ts,nop,ws:df,id-:0
+sig   = *:64:0:*:mss*44,7:mss,sok,ts,nop,ws:df:0
+
+label = s:unix:Linux:3.11 and newer
+sig   = *:64:0:*:mss*20,10:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*20,7:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*20,8:mss,sok,ts,nop,ws:df,id+:0
+
+label = s:unix:Linux:3.1-3.10
+sig   = *:64:0:*:mss*10,4:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*10,5:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*10,6:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*10,7:mss,sok,ts,nop,ws:df,id+:0
+
+label = s:unix:Linux:2.6.x
+sig   = *:64:0:*:mss*4,6:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*4,7:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*4,8:mss,sok,ts,nop,ws:df,id+:0
+
+label = s:unix:Linux:without timestamps
+sig   = *:64:0:*:mss*44,7:mss,nop,nop,sok,nop,ws:df,id+:0
+sig   = *:64:0:*:mss*20,7:mss,nop,nop,sok,nop,ws:df,id+:0
+
+; Windows
+label = s:win:Windows:10 and newer
+sig   = *:128:0:*:64240,8:mss,nop,ws,nop,nop,sok:df,id+:0
+sig   = *:128:0:*:65535,8:mss,nop,ws,nop,nop,sok:df,id+:0
+sig   = *:128:0:*:64800,8:mss,nop,ws,nop,nop,sok:df,id+:0
+sig   = *:128:0:*:mss*44,8:mss,nop,ws,nop,nop,sok:df,id+:0
+
+label = s:win:Windows:7 or 8
+sig   = *:128:0:*:8192,8:mss,nop,ws,nop,nop,sok:df,id+:0
+sig   = *:128:0:*:8192,2:mss,nop,ws,nop,nop,sok:df,id+:0
+sig   = *:128:0:*:8192,0:mss,nop,nop,sok:df,id+:0
+
+label = s:win:Windows:XP
+sig   = *:128:0:*:65535,0:mss,nop,nop,sok:df,id+:0
+sig   = *:128:0:*:64240,0:mss,nop,nop,sok:df,id+:0
+sig   = *:128:0:*:16384,0:mss,nop,nop,sok:df,id+:0
+
+; Apple
+label = s:unix:Mac OS X:10.x
+sig   = *:64:0:*:65535,1:mss,nop,ws,nop,nop,ts,sok,eol+1:df,id+:0
+sig   = *:64:0:*:65535,3:mss,nop,ws,nop,nop,ts,sok,eol+1:df,id+:0
+sig   = *:64:0:*:65535,4:mss,nop,ws,nop,nop,ts,sok,eol+1:df,id+:0
+sig   = *:64:0:*:65535,5:mss,nop,ws,nop,nop,ts,sok,eol+1:df,id+:0
+
+label = s:unix:macOS:11 and newer, or iOS
+sig   = *:64:0:*:65535,6:mss,nop,ws,nop,nop,ts,sok,eol+1:df,id+:0
+sig   = *:64:0:*:65535,6:mss,nop,ws,nop,nop,ts,sok,eol+1:df,id-:0
+sig   = *:64:0:*:65535,6:mss,nop,ws,nop,nop,ts,sok,eol+1:df:0
+
+; BSD
+label = s:unix:FreeBSD:9.x and newer
+sig   = *:64:0:*:65535,6:mss,nop,ws,sok,ts:df,id+:0
+sig   = *:64:0:*:65535,9:mss,nop,ws,sok,ts:df,id+:0
+
+label = s:unix:OpenBSD:5.x and newer
+sig   = *:64:0:*:16384,3:mss,nop,nop,sok,nop,ws,nop,nop,ts:df,id+:0
+sig   = *:64:0:*:16384,6:mss,nop,nop,sok,nop,ws,nop,nop,ts:df,id+:0
+
+; Generic fallbacks by option layout alone
+label = g:unix:Linux:
+sig   = *:64:0:*:*,*:mss,sok,ts,nop,ws:df,id+:0
+sig   = *:64:0:*:*,*:mss,nop,nop,sok,nop,ws:df,id+:0
+
+label = g:win:Windows:
+sig   = *:128:0:*:*,*:mss,nop,ws,nop,nop,sok:df,id+:0
+sig   = *:128:0:*:*,*:mss,nop,nop,sok:df,id+:0
+
+label = g:unix:Apple:
+sig   = *:64:0:*:*,*:mss,nop,ws,nop,nop,ts,sok,eol+1:df,id+:0
+
+label = g:unix:BSD:
+sig   = *:64:0:*:*,*:mss,nop,ws,sok,ts:df,id+:0
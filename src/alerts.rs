@@ -0,0 +1,358 @@
+// Alert lifecycle.
+//
+// Each alert in `sus_events` is one document per fingerprint (activity type,
+// source and indicator). A re-detection updates it in place: the latest
+// details, severity, score, confidence, evidence and timestamp, the largest
+// magnitude seen, last_seen and the occurrence count. Alerts start open,
+// analysts move them to acknowledged or resolved, and a resolved alert that
+// is detected again is reopened. Every state change is kept in the alert's
+// history; alerts can also be assigned to an analyst and carry notes. Alerts
+// also carry the ATT&CK techniques and tactics their activity maps to.
+
+use crate::attack::AttackMapping;
+use crate::detection::SuspiciousActivity;
+use futures::StreamExt;
+use mongodb::bson::{self, doc, oid::ObjectId, Document};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::IndexOptions;
+use mongodb::{Collection, IndexModel};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+const SYSTEM_ACTOR: &str = "system";
+// MongoDB's error code for a unique index violation
+const DUPLICATE_KEY: i32 = 11000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Open,
+    Acknowledged,
+    Resolved,
+    Reopened,
+}
+
+impl AlertState {
+    /// Whether an analyst may move an alert from this state to `to`.
+    pub fn can_transition_to(self, to: AlertState) -> bool {
+        use AlertState::*;
+        matches!(
+            (self, to),
+            (Open | Reopened, Acknowledged | Resolved) | (Acknowledged, Resolved | Open) | (Resolved, Reopened)
+        )
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertState::Open => "open",
+            AlertState::Acknowledged => "acknowledged",
+            AlertState::Resolved => "resolved",
+            AlertState::Reopened => "reopened",
+        }
+    }
+}
+
+impl fmt::Display for AlertState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateChange {
+    pub from: Option<AlertState>,
+    pub to: AlertState,
+    pub by: String,
+    pub timestamp: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertNote {
+    pub author: String,
+    pub text: String,
+    pub timestamp: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Alert {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(flatten)]
+    pub activity: SuspiciousActivity,
+    pub fingerprint: String,
+    pub first_seen: f64,
+    pub last_seen: f64,
+    pub occurrences: i64,
+    pub state: AlertState,
+    #[serde(default)]
+    pub assignee: Option<String>,
+    #[serde(default)]
+    pub notes: Vec<AlertNote>,
+    #[serde(default)]
+    pub history: Vec<StateChange>,
+    #[serde(default)]
+    pub llm_inference_id: Option<ObjectId>,
+    #[serde(default)]
+    pub incident_id: Option<ObjectId>,
+    /// ATT&CK technique IDs and their tactics
+    #[serde(default)]
+    pub techniques: Vec<String>,
+    #[serde(default)]
+    pub tactics: Vec<String>,
+}
+
+/// Indexes the alert fingerprint, which every recorded detection looks up.
+/// The index is unique so two writers recording the same new alert cannot
+/// both insert it; a non-unique index left by earlier versions is replaced.
+pub async fn create_indexes(collection: &Collection<Document>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut indexes = collection.list_indexes().await?;
+    while let Some(index) = indexes.next().await {
+        let index = index?;
+        let options = index.options.unwrap_or_default();
+        if index.keys == doc! { "fingerprint": 1 } && options.unique != Some(true) {
+            collection.drop_index(options.name.as_deref().unwrap_or("fingerprint_1")).await?;
+        }
+    }
+    let options = IndexOptions::builder().unique(true).build();
+    collection.create_index(IndexModel::builder().keys(doc! { "fingerprint": 1 }).options(options).build()).await?;
+    Ok(())
+}
+
+/// Records a detection: updates the alert with the same fingerprint,
+/// reopening it if it was resolved, or inserts a new open alert.
+pub async fn record(collection: &Collection<Document>, activity: &SuspiciousActivity, attack: &AttackMapping) -> Result<ObjectId, Box<dyn Error + Send + Sync>> {
+    let fingerprint = activity.fingerprint();
+    if let Some(existing) = collection.find_one(doc! { "fingerprint": &fingerprint }).await? {
+        return update_existing(collection, &existing, activity, attack).await;
+    }
+
+    let alert = new_alert(activity, &fingerprint, attack)?;
+    match collection.insert_one(alert).await {
+        Ok(result) => result.inserted_id.as_object_id().ok_or_else(|| "alert insert returned no ObjectId".into()),
+        // Another writer inserted the same alert since the lookup
+        Err(e) if is_duplicate_key(&e) => {
+            let existing = collection.find_one(doc! { "fingerprint": &fingerprint }).await?.ok_or(e)?;
+            update_existing(collection, &existing, activity, attack).await
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn update_existing(collection: &Collection<Document>, existing: &Document, activity: &SuspiciousActivity, attack: &AttackMapping) -> Result<ObjectId, Box<dyn Error + Send + Sync>> {
+    let id = existing.get_object_id("_id")?;
+    let now = activity.timestamp;
+    let mut set = doc! {
+        "details": &activity.details,
+        "severity": activity.severity.to_string(),
+        "score": activity.score,
+        "confidence": activity.confidence,
+        "evidence": bson::to_bson(&activity.evidence)?,
+        "rule_id": &activity.rule_id,
+        "timestamp": now,
+        "last_seen": now,
+        "techniques": attack.techniques.clone(),
+        "tactics": attack.tactics.clone(),
+    };
+    let mut update = doc! {
+        "$inc": { "occurrences": 1_i64 },
+        "$max": { "magnitude": activity.magnitude },
+    };
+    if existing.get_str("state").ok() == Some(AlertState::Resolved.as_str()) {
+        set.insert("state", AlertState::Reopened.as_str());
+        update.insert("$push", doc! { "history": bson::to_bson(&reopened(now))? });
+    }
+    update.insert("$set", set);
+    collection.update_one(doc! { "_id": id }, update).await?;
+    Ok(id)
+}
+
+/// The document for a first detection: an open alert with one history entry.
+fn new_alert(activity: &SuspiciousActivity, fingerprint: &str, attack: &AttackMapping) -> Result<Document, bson::ser::Error> {
+    let now = activity.timestamp;
+    let mut alert = bson::to_document(activity)?;
+    alert.insert("fingerprint", fingerprint);
+    alert.insert("first_seen", now);
+    alert.insert("last_seen", now);
+    alert.insert("occurrences", 1_i64);
+    alert.insert("techniques", attack.techniques.clone());
+    alert.insert("tactics", attack.tactics.clone());
+    alert.insert("state", AlertState::Open.as_str());
+    alert.insert("notes", bson::Array::new());
+    alert.insert("history", vec![bson::to_bson(&StateChange {
+        from: None,
+        to: AlertState::Open,
+        by: SYSTEM_ACTOR.to_string(),
+        timestamp: now,
+    })?]);
+    Ok(alert)
+}
+
+/// The history entry for a resolved alert that was detected again.
+fn reopened(now: f64) -> StateChange {
+    StateChange {
+        from: Some(AlertState::Resolved),
+        to: AlertState::Reopened,
+        by: SYSTEM_ACTOR.to_string(),
+        timestamp: now,
+    }
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        &*error.kind,
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == DUPLICATE_KEY
+    )
+}
+
+/// Alerts, most recently seen first, optionally only those in `state`.
+pub async fn list(collection: &Collection<Alert>, state: Option<AlertState>, limit: i64) -> Result<Vec<Alert>, Box<dyn Error + Send + Sync>> {
+    let filter = match state {
+        Some(state) => doc! { "state": state.as_str() },
+        None => doc! {},
+    };
+    let mut cursor = collection.find(filter).sort(doc! { "last_seen": -1 }).limit(limit).await?;
+    let mut alerts = Vec::new();
+    while let Some(alert) = cursor.next().await {
+        alerts.push(alert?);
+    }
+    Ok(alerts)
+}
+
+/// Moves an alert to `to`, rejecting transitions the lifecycle doesn't allow.
+pub async fn set_state(collection: &Collection<Alert>, id: ObjectId, to: AlertState, by: &str, now: f64) -> Result<Alert, Box<dyn Error + Send + Sync>> {
+    let alert = collection.find_one(doc! { "_id": id }).await?.ok_or("no such alert")?;
+    if !alert.state.can_transition_to(to) {
+        return Err(format!("cannot move a {} alert to {}", alert.state, to).into());
+    }
+    let change = StateChange {
+        from: Some(alert.state),
+        to,
+        by: by.to_string(),
+        timestamp: now,
+    };
+    update(collection, id, doc! {
+        "$set": { "state": to.as_str() },
+        "$push": { "history": bson::to_bson(&change)? },
+    }).await
+}
+
+pub async fn assign(collection: &Collection<Alert>, id: ObjectId, assignee: Option<&str>) -> Result<Alert, Box<dyn Error + Send + Sync>> {
+    update(collection, id, doc! { "$set": { "assignee": assignee } }).await
+}
+
+pub async fn add_note(collection: &Collection<Alert>, id: ObjectId, note: AlertNote) -> Result<Alert, Box<dyn Error + Send + Sync>> {
+    if note.text.trim().is_empty() {
+        return Err("note text is empty".into());
+    }
+    update(collection, id, doc! { "$push": { "notes": bson::to_bson(&note)? } }).await
+}
+
+async fn update(collection: &Collection<Alert>, id: ObjectId, update: Document) -> Result<Alert, Box<dyn Error + Send + Sync>> {
+    collection
+        .find_one_and_update(doc! { "_id": id }, update)
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await?
+        .ok_or_else(|| "no such alert".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::Evidence;
+    use mongodb::error::WriteError;
+    use sniff::llm::verdict::Severity;
+
+    fn activity() -> SuspiciousActivity {
+        SuspiciousActivity {
+            activity_type: "Port Scan".to_string(),
+            source: "10.0.0.5".to_string(),
+            details: "scanned 40 ports".to_string(),
+            indicator: "10.0.0.9".to_string(),
+            magnitude: 40.0,
+            severity: Severity::Medium,
+            score: 0.0,
+            confidence: 0.7,
+            evidence: Evidence::default(),
+            rule_id: "port_scan".to_string(),
+            timestamp: 1000.0,
+        }
+    }
+
+    fn attack() -> AttackMapping {
+        AttackMapping { techniques: vec!["T1046".to_string()], tactics: vec!["discovery".to_string()] }
+    }
+
+    fn write_error(code: i32) -> mongodb::error::Error {
+        let error: WriteError = bson::from_document(doc! { "code": code, "errmsg": "E11000 duplicate key" }).unwrap();
+        ErrorKind::Write(WriteFailure::WriteError(error)).into()
+    }
+
+    #[test]
+    fn analyst_transitions() {
+        use AlertState::*;
+        let allowed = [
+            (Open, Acknowledged),
+            (Open, Resolved),
+            (Reopened, Acknowledged),
+            (Reopened, Resolved),
+            (Acknowledged, Resolved),
+            (Acknowledged, Open),
+            (Resolved, Reopened),
+        ];
+        for from in [Open, Acknowledged, Resolved, Reopened] {
+            for to in [Open, Acknowledged, Resolved, Reopened] {
+                assert_eq!(from.can_transition_to(to), allowed.contains(&(from, to)), "{} -> {}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn new_alerts_start_open() {
+        let activity = activity();
+        let mut document = new_alert(&activity, &activity.fingerprint(), &attack()).unwrap();
+        document.insert("_id", ObjectId::new());
+        let alert: Alert = bson::from_document(document).unwrap();
+
+        assert_eq!(alert.fingerprint, "Port Scan|10.0.0.5|10.0.0.9");
+        assert_eq!(alert.state, AlertState::Open);
+        assert_eq!((alert.first_seen, alert.last_seen, alert.occurrences), (1000.0, 1000.0, 1));
+        assert_eq!(alert.activity.details, "scanned 40 ports");
+        assert_eq!(alert.techniques, vec!["T1046"]);
+        assert_eq!(alert.tactics, vec!["discovery"]);
+        assert_eq!(alert.assignee, None);
+        assert!(alert.notes.is_empty());
+        assert_eq!(alert.history.len(), 1);
+        assert_eq!((alert.history[0].from, alert.history[0].to), (None, AlertState::Open));
+        assert_eq!(alert.history[0].by, SYSTEM_ACTOR);
+    }
+
+    #[test]
+    fn redetection_reopens_resolved_alerts() {
+        let change = reopened(2000.0);
+        assert_eq!((change.from, change.to), (Some(AlertState::Resolved), AlertState::Reopened));
+        assert_eq!((change.by.as_str(), change.timestamp), (SYSTEM_ACTOR, 2000.0));
+        // Analysts can then work the reopened alert like a new one
+        assert!(AlertState::Reopened.can_transition_to(AlertState::Acknowledged));
+    }
+
+    #[test]
+    fn history_and_notes_round_trip() {
+        let change = StateChange { from: Some(AlertState::Open), to: AlertState::Acknowledged, by: "alice".to_string(), timestamp: 1500.0 };
+        let bson = bson::to_bson(&change).unwrap();
+        assert_eq!(bson.as_document().unwrap().get_str("to"), Ok("acknowledged"));
+        let change: StateChange = bson::from_bson(bson).unwrap();
+        assert_eq!(change.from, Some(AlertState::Open));
+
+        let note = AlertNote { author: "alice".to_string(), text: "known scanner".to_string(), timestamp: 1600.0 };
+        let note: AlertNote = bson::from_bson(bson::to_bson(&note).unwrap()).unwrap();
+        assert_eq!(note.text, "known scanner");
+    }
+
+    #[test]
+    fn recognizes_duplicate_key_errors() {
+        assert!(is_duplicate_key(&write_error(DUPLICATE_KEY)));
+        assert!(!is_duplicate_key(&write_error(121)));
+        assert!(!is_duplicate_key(&mongodb::error::Error::custom("other")));
+    }
+}
@@ -0,0 +1,603 @@
+// Stateful ARP monitoring.
+//
+// Keeps an IP-to-MAC binding table built from every ARP packet (first/last
+// seen per pair), persisted in the arp_bindings collection so a restart does
+// not forget who owned which address. Changed bindings are written back every
+// FLUSH_SECS rather than on every packet. Flags:
+//   - a known IP suddenly claimed by a different MAC, or any MAC other than
+//     the trusted one for a statically configured IP (e.g. the gateway)
+//   - an IP flip-flopping between MACs
+//   - floods of gratuitous ARP from one MAC
+//   - replies nobody asked for
+//   - one MAC claiming many IPs
+//
+// A randomized (locally administered) MAC taking over an address whose owner
+// has gone quiet for ROTATION_QUIET_SECS is a device rotating its private
+// address, not spoofing; repeated changes still count towards flip-flopping.
+// MACs in alerts are shown with their vendor.
+//
+// Trusted bindings are read from ARP_TRUSTED_FILE (default arp_trusted.txt),
+// one "IP MAC" pair per line.
+
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::sniff::oui;
+use crate::sniff::{ArpInfo, NetworkEvent};
+use crate::stream::StreamDetector;
+use futures::StreamExt;
+use mongodb::{bson::doc, Collection, Database};
+use serde::{Deserialize, Serialize};
+use sniff::llm::verdict::Severity;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::Ipv4Addr;
+use tokio::sync::mpsc;
+
+const DEFAULT_TRUSTED_FILE: &str = "arp_trusted.txt";
+// A binding unused for this long is treated as a released lease, so a new
+// MAC taking the IP over is not reported as a change
+const BINDING_STALE_SECS: f64 = 3600.0;
+// A previous owner silent this long before a randomized MAC takes over
+const ROTATION_QUIET_SECS: f64 = 60.0;
+const FLIP_FLOP_WINDOW: f64 = 300.0;
+const FLIP_FLOP_THRESHOLD: usize = 3;
+const GRATUITOUS_WINDOW: f64 = 60.0;
+const GRATUITOUS_THRESHOLD: usize = 20;
+// How long a request stays open for a matching reply
+const REQUEST_TIMEOUT: f64 = 5.0;
+const UNSOLICITED_WINDOW: f64 = 60.0;
+const UNSOLICITED_THRESHOLD: usize = 5;
+const MULTI_IP_WINDOW: f64 = 300.0;
+const MULTI_IP_THRESHOLD: usize = 5;
+// Alerts for the same key are not repeated more often than this
+const ALERT_COOLDOWN: f64 = 300.0;
+const FLUSH_SECS: f64 = 30.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArpBinding {
+    pub ip: String,
+    pub mac: String,
+    pub first_seen: f64,
+    pub last_seen: f64,
+    pub packets: u64,
+}
+
+pub struct ArpMonitor {
+    // IP -> every MAC that has claimed it
+    bindings: HashMap<Ipv4Addr, Vec<ArpBinding>>,
+    trusted: HashMap<Ipv4Addr, String>,
+    // IP -> (time, MAC) of recent changes of owner
+    changes: HashMap<Ipv4Addr, VecDeque<(f64, String)>>,
+    // MAC -> times of gratuitous packets
+    gratuitous: HashMap<String, VecDeque<f64>>,
+    // (requester IP, requested IP) -> time of request
+    pending_requests: HashMap<(Ipv4Addr, Ipv4Addr), f64>,
+    // MAC -> times of unsolicited replies
+    unsolicited: HashMap<String, VecDeque<f64>>,
+    // MAC -> IP -> last time claimed
+    claims: HashMap<String, HashMap<Ipv4Addr, f64>>,
+    last_alert: HashMap<String, f64>,
+    // (IP, MAC) bindings changed since the last flush
+    dirty: HashSet<(Ipv4Addr, String)>,
+    last_flush: f64,
+    writer: Option<mpsc::UnboundedSender<ArpBinding>>,
+}
+
+impl ArpMonitor {
+    pub fn new(bindings: Vec<ArpBinding>, trusted: HashMap<Ipv4Addr, String>) -> Self {
+        let mut table: HashMap<Ipv4Addr, Vec<ArpBinding>> = HashMap::new();
+        for binding in bindings {
+            if let Ok(ip) = binding.ip.parse() {
+                table.entry(ip).or_default().push(binding);
+            }
+        }
+
+        ArpMonitor {
+            bindings: table,
+            trusted,
+            changes: HashMap::new(),
+            gratuitous: HashMap::new(),
+            pending_requests: HashMap::new(),
+            unsolicited: HashMap::new(),
+            claims: HashMap::new(),
+            last_alert: HashMap::new(),
+            dirty: HashSet::new(),
+            last_flush: 0.0,
+            writer: None,
+        }
+    }
+
+    /// Loads persisted bindings and the trusted list, and starts a task that
+    /// writes binding updates back to `arp_bindings`.
+    pub async fn load(db: &Database) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let collection: Collection<ArpBinding> = db.collection("arp_bindings");
+        let mut cursor = collection.find(doc! {}).await?;
+        let mut bindings = Vec::new();
+        while let Some(binding) = cursor.next().await {
+            bindings.push(binding?);
+        }
+
+        let path = env::var("ARP_TRUSTED_FILE").unwrap_or_else(|_| DEFAULT_TRUSTED_FILE.to_string());
+        let trusted = match fs::read_to_string(&path) {
+            Ok(contents) => parse_trusted(&contents),
+            Err(_) => HashMap::new(),
+        };
+        println!("Loaded {} ARP bindings and {} trusted entries", bindings.len(), trusted.len());
+
+        let mut monitor = Self::new(bindings, trusted);
+        monitor.writer = Some(spawn_binding_writer(collection));
+        Ok(monitor)
+    }
+
+    fn observe_arp(&mut self, arp: &ArpInfo, now: f64, detections: &mut Vec<Detection>) {
+        if arp.operation == ArpInfo::REQUEST && !arp.is_gratuitous() {
+            self.pending_requests.insert((arp.sender_ip, arp.target_ip), now);
+        }
+
+        // Probes (sender 0.0.0.0) announce nothing about the sender
+        if arp.sender_ip.is_unspecified() {
+            return;
+        }
+
+        self.check_trusted(arp, now, detections);
+        self.update_binding(arp, now, detections);
+
+        if arp.is_gratuitous() {
+            self.check_gratuitous(arp, now, detections);
+        } else if arp.operation == ArpInfo::REPLY {
+            self.check_unsolicited(arp, now, detections);
+        }
+        self.check_multiple_ips(arp, now, detections);
+    }
+
+    fn flush(&mut self, now: f64) {
+        self.last_flush = now;
+        let Some(writer) = &self.writer else {
+            self.dirty.clear();
+            return;
+        };
+        for (ip, mac) in self.dirty.drain() {
+            if let Some(binding) = self.bindings.get(&ip).and_then(|entries| entries.iter().find(|b| b.mac == mac)) {
+                let _ = writer.send(binding.clone());
+            }
+        }
+    }
+
+    fn check_trusted(&mut self, arp: &ArpInfo, now: f64, detections: &mut Vec<Detection>) {
+        let trusted_mac = match self.trusted.get(&arp.sender_ip) {
+            Some(mac) if !mac.eq_ignore_ascii_case(&arp.sender_mac) => mac.clone(),
+            _ => return,
+        };
+        self.alert(
+            detections,
+            now,
+            "ARP Spoofing",
+            Severity::Critical,
+            0.95,
+            &arp.sender_ip.to_string(),
+            &arp.sender_mac,
+            1.0,
+            format!(
+                "{} claimed trusted address {} (trusted MAC {})",
+                oui::describe(&arp.sender_mac), arp.sender_ip, oui::describe(&trusted_mac)
+            ),
+        );
+    }
+
+    fn update_binding(&mut self, arp: &ArpInfo, now: f64, detections: &mut Vec<Detection>) {
+        let entries = self.bindings.entry(arp.sender_ip).or_default();
+        let current_owner = entries
+            .iter()
+            .filter(|b| now - b.last_seen < BINDING_STALE_SECS)
+            .max_by(|a, b| a.last_seen.total_cmp(&b.last_seen))
+            .map(|b| (b.mac.clone(), b.last_seen));
+
+        match entries.iter_mut().find(|b| b.mac == arp.sender_mac) {
+            Some(binding) => {
+                binding.last_seen = now;
+                binding.packets += 1;
+            }
+            None => entries.push(ArpBinding {
+                ip: arp.sender_ip.to_string(),
+                mac: arp.sender_mac.clone(),
+                first_seen: now,
+                last_seen: now,
+                packets: 1,
+            }),
+        }
+        self.dirty.insert((arp.sender_ip, arp.sender_mac.clone()));
+
+        let (previous, previous_seen) = match current_owner {
+            Some((previous, seen)) if previous != arp.sender_mac => (previous, seen),
+            _ => return,
+        };
+        let rotated = oui::is_randomized(&arp.sender_mac) && now - previous_seen >= ROTATION_QUIET_SECS;
+
+        let changes = self.changes.entry(arp.sender_ip).or_default();
+        changes.push_back((now, arp.sender_mac.clone()));
+        while changes.front().is_some_and(|(t, _)| now - t > FLIP_FLOP_WINDOW) {
+            changes.pop_front();
+        }
+        let change_count = changes.len();
+
+        if change_count >= FLIP_FLOP_THRESHOLD {
+            self.alert(
+                detections,
+                now,
+                "ARP Flip-Flop",
+                Severity::High,
+                0.8,
+                &arp.sender_ip.to_string(),
+                "",
+                change_count as f64,
+                format!(
+                    "{} changed owner {} times in {} seconds (now {}, was {})",
+                    arp.sender_ip, change_count, FLIP_FLOP_WINDOW, oui::describe(&arp.sender_mac), oui::describe(&previous)
+                ),
+            );
+        } else if !rotated {
+            self.alert(
+                detections,
+                now,
+                "ARP Spoofing",
+                Severity::High,
+                0.7,
+                &arp.sender_ip.to_string(),
+                &arp.sender_mac,
+                1.0,
+                format!("{} moved from {} to {}", arp.sender_ip, oui::describe(&previous), oui::describe(&arp.sender_mac)),
+            );
+        }
+    }
+
+    fn check_gratuitous(&mut self, arp: &ArpInfo, now: f64, detections: &mut Vec<Detection>) {
+        let count = record(self.gratuitous.entry(arp.sender_mac.clone()).or_default(), now, GRATUITOUS_WINDOW);
+        if count > GRATUITOUS_THRESHOLD {
+            self.alert(
+                detections,
+                now,
+                "Gratuitous ARP Flood",
+                Severity::Medium,
+                0.6,
+                &arp.sender_mac,
+                "",
+                count as f64,
+                format!("{} gratuitous ARP packets from {} in {} seconds", count, oui::describe(&arp.sender_mac), GRATUITOUS_WINDOW),
+            );
+        }
+    }
+
+    fn check_unsolicited(&mut self, arp: &ArpInfo, now: f64, detections: &mut Vec<Detection>) {
+        self.pending_requests.retain(|_, asked| now - *asked <= REQUEST_TIMEOUT);
+        if self.pending_requests.remove(&(arp.target_ip, arp.sender_ip)).is_some() {
+            return;
+        }
+
+        let count = record(self.unsolicited.entry(arp.sender_mac.clone()).or_default(), now, UNSOLICITED_WINDOW);
+        if count > UNSOLICITED_THRESHOLD {
+            self.alert(
+                detections,
+                now,
+                "Unsolicited ARP Reply",
+                Severity::Medium,
+                0.5,
+                &arp.sender_mac,
+                &arp.sender_ip.to_string(),
+                count as f64,
+                format!(
+                    "{} sent {} ARP replies without a request in {} seconds (latest claims {})",
+                    oui::describe(&arp.sender_mac), count, UNSOLICITED_WINDOW, arp.sender_ip
+                ),
+            );
+        }
+    }
+
+    fn check_multiple_ips(&mut self, arp: &ArpInfo, now: f64, detections: &mut Vec<Detection>) {
+        let is_trusted = self.trusted.values().any(|mac| mac.eq_ignore_ascii_case(&arp.sender_mac));
+        let claims = self.claims.entry(arp.sender_mac.clone()).or_default();
+        claims.insert(arp.sender_ip, now);
+        claims.retain(|_, seen| now - *seen <= MULTI_IP_WINDOW);
+        let count = claims.len();
+
+        if count > MULTI_IP_THRESHOLD && !is_trusted {
+            let mut ips: Vec<String> = claims.keys().map(|ip| ip.to_string()).collect();
+            ips.sort();
+            self.alert(
+                detections,
+                now,
+                "ARP MAC Claims Many IPs",
+                Severity::High,
+                0.6,
+                &arp.sender_mac,
+                "",
+                count as f64,
+                format!("{} claimed {} addresses: {}", oui::describe(&arp.sender_mac), count, ips.join(", ")),
+            );
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn alert(
+        &mut self,
+        detections: &mut Vec<Detection>,
+        now: f64,
+        activity_type: &str,
+        severity: Severity,
+        confidence: f64,
+        source: &str,
+        indicator: &str,
+        magnitude: f64,
+        details: String,
+    ) {
+        let key = format!("{}|{}|{}", activity_type, source, indicator);
+        if self.last_alert.get(&key).is_some_and(|t| now - t < ALERT_COOLDOWN) {
+            return;
+        }
+        self.last_alert.insert(key, now);
+
+        detections.push(Detection {
+            activity: SuspiciousActivity {
+                activity_type: activity_type.to_string(),
+                source: source.to_string(),
+                details,
+                indicator: indicator.to_string(),
+                magnitude,
+                severity,
+                score: 0.0,
+                confidence,
+                evidence: Evidence {
+                    count: Some(magnitude),
+                    peers: [source, indicator].iter().filter(|p| !p.is_empty()).map(|p| p.to_string()).collect(),
+                    ..Evidence::default()
+                },
+                rule_id: "arp_monitor".to_string(),
+                timestamp: now,
+            },
+            actions: Vec::new(),
+            vars: HashMap::new(),
+        });
+    }
+}
+
+impl StreamDetector for ArpMonitor {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        if let Some(arp) = &event.arp {
+            self.observe_arp(arp, event.timestamp, detections);
+        }
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        self.pending_requests.retain(|_, asked| now - *asked <= REQUEST_TIMEOUT);
+        self.changes.retain(|_, changes| changes.back().is_some_and(|(t, _)| now - t <= FLIP_FLOP_WINDOW));
+        self.gratuitous.retain(|_, times| times.back().is_some_and(|t| now - t <= GRATUITOUS_WINDOW));
+        self.unsolicited.retain(|_, times| times.back().is_some_and(|t| now - t <= UNSOLICITED_WINDOW));
+        self.claims.retain(|_, ips| ips.values().any(|seen| now - *seen <= MULTI_IP_WINDOW));
+        self.last_alert.retain(|_, t| now - *t < ALERT_COOLDOWN);
+        if now - self.last_flush >= FLUSH_SECS {
+            self.flush(now);
+        }
+    }
+}
+
+/// Adds `now` to a list of event times and returns how many fall in `window`.
+fn record(times: &mut VecDeque<f64>, now: f64, window: f64) -> usize {
+    times.push_back(now);
+    while times.front().is_some_and(|t| now - t > window) {
+        times.pop_front();
+    }
+    times.len()
+}
+
+fn parse_trusted(contents: &str) -> HashMap<Ipv4Addr, String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let ip = parts.next()?.parse().ok()?;
+            let mac = parts.next()?.to_uppercase().replace('-', ":");
+            Some((ip, mac))
+        })
+        .collect()
+}
+
+fn spawn_binding_writer(collection: Collection<ArpBinding>) -> mpsc::UnboundedSender<ArpBinding> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ArpBinding>();
+    tokio::spawn(async move {
+        while let Some(binding) = rx.recv().await {
+            let result = collection
+                .update_one(
+                    doc! { "ip": &binding.ip, "mac": &binding.mac },
+                    doc! {
+                        "$min": { "first_seen": binding.first_seen },
+                        "$max": { "last_seen": binding.last_seen },
+                        "$set": { "packets": binding.packets as i64 },
+                    },
+                )
+                .upsert(true)
+                .await;
+            if let Err(e) = result {
+                eprintln!("Error storing ARP binding: {}", e);
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "00:11:22:33:44:55";
+    const OTHER: &str = "00:AA:BB:CC:DD:EE";
+    // Locally administered, as phones use for private addresses
+    const RANDOMIZED: &str = "DA:A1:19:00:00:01";
+
+    fn packet(operation: u16, sender_mac: &str, sender_ip: &str, target_ip: &str) -> ArpInfo {
+        ArpInfo {
+            operation,
+            sender_mac: sender_mac.to_string(),
+            sender_ip: sender_ip.parse().unwrap(),
+            target_mac: "00:00:00:00:00:00".to_string(),
+            target_ip: target_ip.parse().unwrap(),
+        }
+    }
+
+    /// A request from `mac` for the gateway, which reveals the sender's binding.
+    fn request(mac: &str, ip: &str) -> ArpInfo {
+        packet(ArpInfo::REQUEST, mac, ip, "10.0.0.1")
+    }
+
+    /// OWNER is the trusted MAC of the gateway, 10.0.0.1.
+    fn gateway() -> HashMap<Ipv4Addr, String> {
+        HashMap::from([("10.0.0.1".parse().unwrap(), OWNER.to_string())])
+    }
+
+    fn run(monitor: &mut ArpMonitor, packets: &[(f64, ArpInfo)]) -> Vec<Detection> {
+        let mut detections = Vec::new();
+        for (now, arp) in packets {
+            monitor.observe_arp(arp, *now, &mut detections);
+        }
+        detections
+    }
+
+    fn types(detections: &[Detection]) -> Vec<&str> {
+        detections.iter().map(|d| d.activity.activity_type.as_str()).collect()
+    }
+
+    #[test]
+    fn trusted_address_claimed_by_another_mac() {
+        let mut monitor = ArpMonitor::new(Vec::new(), gateway());
+        let owner = packet(ArpInfo::REPLY, &OWNER.to_lowercase(), "10.0.0.1", "10.0.0.1");
+        assert!(run(&mut monitor, &[(0.0, owner)]).is_empty());
+
+        let mut monitor = ArpMonitor::new(Vec::new(), gateway());
+        let detections = run(&mut monitor, &[(0.0, packet(ArpInfo::REPLY, OTHER, "10.0.0.1", "10.0.0.7"))]);
+        assert_eq!(types(&detections), vec!["ARP Spoofing"]);
+        let activity = &detections[0].activity;
+        assert_eq!(activity.severity, Severity::Critical);
+        assert_eq!((activity.source.as_str(), activity.indicator.as_str()), ("10.0.0.1", OTHER));
+    }
+
+    #[test]
+    fn binding_change_is_spoofing() {
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let detections = run(&mut monitor, &[(0.0, request(OWNER, "10.0.0.7")), (10.0, request(OTHER, "10.0.0.7"))]);
+        assert_eq!(types(&detections), vec!["ARP Spoofing"]);
+        let activity = &detections[0].activity;
+        assert_eq!(activity.severity, Severity::High);
+        assert_eq!((activity.source.as_str(), activity.indicator.as_str()), ("10.0.0.7", OTHER));
+        assert_eq!(monitor.bindings[&"10.0.0.7".parse().unwrap()].len(), 2);
+    }
+
+    #[test]
+    fn persisted_bindings_are_remembered() {
+        let binding = ArpBinding { ip: "10.0.0.7".to_string(), mac: OWNER.to_string(), first_seen: 0.0, last_seen: 100.0, packets: 9 };
+        let mut monitor = ArpMonitor::new(vec![binding], HashMap::new());
+        assert_eq!(types(&run(&mut monitor, &[(110.0, request(OTHER, "10.0.0.7"))])), vec!["ARP Spoofing"]);
+    }
+
+    #[test]
+    fn stale_bindings_can_be_taken_over() {
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let detections = run(&mut monitor, &[(0.0, request(OWNER, "10.0.0.7")), (BINDING_STALE_SECS + 1.0, request(OTHER, "10.0.0.7"))]);
+        assert!(detections.is_empty());
+    }
+
+    #[test]
+    fn flip_flop_between_macs() {
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let packets: Vec<(f64, ArpInfo)> = [OWNER, OTHER, OWNER, OTHER]
+            .iter()
+            .enumerate()
+            .map(|(i, mac)| (i as f64 * 10.0, request(mac, "10.0.0.7")))
+            .collect();
+        let detections = run(&mut monitor, &packets);
+        assert_eq!(types(&detections), vec!["ARP Spoofing", "ARP Spoofing", "ARP Flip-Flop"]);
+        let flip_flop = &detections[2].activity;
+        assert_eq!(flip_flop.source, "10.0.0.7");
+        assert_eq!(flip_flop.magnitude, FLIP_FLOP_THRESHOLD as f64);
+    }
+
+    #[test]
+    fn randomized_mac_rotation_is_not_spoofing() {
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let detections = run(&mut monitor, &[(0.0, request(OWNER, "10.0.0.7")), (ROTATION_QUIET_SECS, request(RANDOMIZED, "10.0.0.7"))]);
+        assert!(detections.is_empty());
+
+        // Taking over from an owner that is still talking is spoofing
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let detections = run(&mut monitor, &[(0.0, request(OWNER, "10.0.0.7")), (5.0, request(RANDOMIZED, "10.0.0.7"))]);
+        assert_eq!(types(&detections), vec!["ARP Spoofing"]);
+
+        // Rotating again and again still counts towards flip-flopping
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let detections = run(
+            &mut monitor,
+            &[
+                (0.0, request(OWNER, "10.0.0.7")),
+                (100.0, request(RANDOMIZED, "10.0.0.7")),
+                (200.0, request("DA:A1:19:00:00:02", "10.0.0.7")),
+                (270.0, request("DA:A1:19:00:00:03", "10.0.0.7")),
+            ],
+        );
+        assert_eq!(types(&detections), vec!["ARP Flip-Flop"]);
+    }
+
+    #[test]
+    fn gratuitous_flood() {
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let packets: Vec<(f64, ArpInfo)> = (0..30)
+            .map(|i| (i as f64, packet(ArpInfo::REQUEST, OWNER, "10.0.0.7", "10.0.0.7")))
+            .collect();
+        let detections = run(&mut monitor, &packets);
+        // Alerts once, on the packet over the threshold, then cools down
+        assert_eq!(types(&detections), vec!["Gratuitous ARP Flood"]);
+        assert_eq!(detections[0].activity.magnitude, (GRATUITOUS_THRESHOLD + 1) as f64);
+        assert_eq!(detections[0].activity.timestamp, GRATUITOUS_THRESHOLD as f64);
+    }
+
+    #[test]
+    fn unsolicited_replies() {
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let answered: Vec<(f64, ArpInfo)> = (0..10)
+            .flat_map(|i| {
+                let t = i as f64 * 2.0;
+                [
+                    (t, packet(ArpInfo::REQUEST, OTHER, "10.0.0.2", "10.0.0.9")),
+                    (t + 0.1, packet(ArpInfo::REPLY, OWNER, "10.0.0.9", "10.0.0.2")),
+                ]
+            })
+            .collect();
+        assert!(run(&mut monitor, &answered).is_empty());
+
+        let unasked: Vec<(f64, ArpInfo)> = (0..UNSOLICITED_THRESHOLD + 1)
+            .map(|i| (30.0 + i as f64, packet(ArpInfo::REPLY, OWNER, "10.0.0.9", "10.0.0.2")))
+            .collect();
+        let detections = run(&mut monitor, &unasked);
+        assert_eq!(types(&detections), vec!["Unsolicited ARP Reply"]);
+        assert_eq!((detections[0].activity.source.as_str(), detections[0].activity.indicator.as_str()), (OWNER, "10.0.0.9"));
+    }
+
+    #[test]
+    fn one_mac_claiming_many_addresses() {
+        let mut monitor = ArpMonitor::new(Vec::new(), HashMap::new());
+        let packets: Vec<(f64, ArpInfo)> = (0..=MULTI_IP_THRESHOLD)
+            .map(|i| (i as f64, request(OWNER, &format!("10.0.0.{}", 10 + i))))
+            .collect();
+        assert_eq!(types(&run(&mut monitor, &packets)), vec!["ARP MAC Claims Many IPs"]);
+
+        // Unless it is a trusted router answering for its addresses
+        let mut monitor = ArpMonitor::new(Vec::new(), gateway());
+        assert!(run(&mut monitor, &packets).is_empty());
+    }
+
+    #[test]
+    fn parses_trusted_file() {
+        let trusted = parse_trusted("# gateway\n10.0.0.1 00-11-22-aa-bb-cc  # router\n\nnot-an-ip 00:11:22:33:44:55\n10.0.0.2\n");
+        assert_eq!(trusted.len(), 1);
+        assert_eq!(trusted[&"10.0.0.1".parse().unwrap()], "00:11:22:AA:BB:CC");
+    }
+}
@@ -0,0 +1,721 @@
+// Passive asset inventory.
+//
+// One record per MAC address, built from what devices reveal on the LAN:
+//   - ARP: the MAC and its IPv4 address
+//   - mDNS announcements: hostname (name.local A/AAAA records) and advertised
+//     services (_ipp._tcp...)
+//   - NetBIOS name registrations and answers: hostname
+//   - DNS PTR answers for internal addresses: hostname
+//   - DHCP: hostname (option 12 or the v6 client FQDN) and vendor class from
+//     requests, and the address from the server's ACK
+//   - traffic internal hosts send from well-known ports: services
+//   - SYNs internal hosts open connections with: the OS, by fingerprint
+// Addresses are kept with their own first/last seen so an asset's address
+// history survives lease changes. Records are kept in the `assets` collection
+// and written back every FLUSH_SECS; owner, criticality and tags are set by
+// analysts and never overwritten by the monitor.
+//
+// A MAC not in the inventory raises "New Device", unless it is a randomized
+// MAC taking over an address another randomized MAC had, which is a device
+// rotating its private address. When the inventory starts out empty the
+// first ASSET_LEARNING_SECS (default 3600) seconds of traffic are learned
+// without alerts. Tagged criticalities are reloaded every minute
+// and used by alert scoring.
+//
+// Only SYNs that crossed no router are fingerprinted, since a routed one
+// arrives with the router's MAC. A host whose SYNs match a different OS
+// OS_CHANGE_CONFIRMATIONS times in a row raises "OS Fingerprint Changed":
+// a reinstall, a dual boot, or another machine using its MAC. Generic and
+// fuzzy matches fill in an unknown OS but never count as a change.
+
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::sniff::dhcp::DhcpMessage;
+use crate::sniff::dns::{self, DnsMessage};
+use crate::sniff::fingerprint::{self, OsMatch, TcpSyn};
+use crate::sniff::netbios::NetbiosName;
+use crate::sniff::oui;
+use crate::sniff::NetworkEvent;
+use crate::stream::{split_endpoint, StreamDetector};
+use ::sniff::llm::redact::is_internal_ip;
+use ::sniff::llm::verdict::Severity;
+use futures::StreamExt;
+use mongodb::bson::{self, doc};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const DEFAULT_LEARNING_SECS: f64 = 3600.0;
+const FLUSH_SECS: f64 = 30.0;
+const CRITICALITY_REFRESH: Duration = Duration::from_secs(60);
+const MAX_ADDRESSES: usize = 32;
+const MAX_SERVICES: usize = 64;
+const OS_CHANGE_CONFIRMATIONS: u32 = 3;
+// Server ports above 1023 worth recording; anything below counts too
+const SERVICE_PORTS: [u16; 12] = [1433, 1883, 3306, 3389, 5432, 5900, 6379, 8080, 8443, 9100, 9200, 27017];
+// DHCP clients send from these, which says nothing about what they serve
+const CLIENT_PORTS: [u16; 2] = [68, 546];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetAddress {
+    pub ip: String,
+    pub first_seen: f64,
+    pub last_seen: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Asset {
+    pub mac: String,
+    /// Organization the MAC's block is assigned to
+    #[serde(default)]
+    pub vendor: Option<String>,
+    /// Locally administered MAC, likely a private address a device rotates
+    #[serde(default)]
+    pub randomized: bool,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Where the hostname came from: mdns, netbios, ptr or dhcp
+    #[serde(default)]
+    pub hostname_source: Option<String>,
+    /// DHCP vendor class, e.g. "MSFT 5.0" or "android-dhcp-13"
+    #[serde(default)]
+    pub vendor_class: Option<String>,
+    /// Best OS match for the host's SYNs
+    #[serde(default)]
+    pub os: Option<OsMatch>,
+    /// The latest SYN in p0f signature syntax, matched or not
+    #[serde(default)]
+    pub os_signature: Option<String>,
+    #[serde(default)]
+    pub addresses: Vec<AssetAddress>,
+    /// "tcp/443", "udp/123", or mDNS service types such as "_ipp._tcp"
+    #[serde(default)]
+    pub services: Vec<String>,
+    pub first_seen: f64,
+    pub last_seen: f64,
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// 0-1, how much an alert involving this asset matters
+    #[serde(default)]
+    pub criticality: Option<f64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Asset {
+    fn new(mac: &str, now: f64) -> Self {
+        Asset {
+            mac: mac.to_string(),
+            vendor: oui::vendor(mac).map(str::to_string),
+            randomized: oui::is_randomized(mac),
+            hostname: None,
+            hostname_source: None,
+            vendor_class: None,
+            os: None,
+            os_signature: None,
+            addresses: Vec::new(),
+            services: Vec::new(),
+            first_seen: now,
+            last_seen: now,
+            owner: None,
+            criticality: None,
+            tags: Vec::new(),
+        }
+    }
+
+    /// The address the asset was seen with most recently.
+    fn current_address(&self) -> Option<&AssetAddress> {
+        self.addresses.iter().max_by(|a, b| a.last_seen.total_cmp(&b.last_seen))
+    }
+
+    fn touch_address(&mut self, ip: IpAddr, now: f64) {
+        let ip = ip.to_string();
+        match self.addresses.iter_mut().find(|a| a.ip == ip) {
+            Some(address) => address.last_seen = now,
+            None => {
+                self.addresses.push(AssetAddress { ip, first_seen: now, last_seen: now });
+                if self.addresses.len() > MAX_ADDRESSES {
+                    self.addresses.sort_by(|a, b| b.last_seen.total_cmp(&a.last_seen));
+                    self.addresses.truncate(MAX_ADDRESSES);
+                }
+            }
+        }
+    }
+
+    fn add_service(&mut self, service: String) {
+        if !self.services.contains(&service) && self.services.len() < MAX_SERVICES {
+            self.services.push(service);
+        }
+    }
+
+    /// Whether the hostname changed.
+    fn set_hostname(&mut self, hostname: &str, source: &str) -> bool {
+        if self.hostname.as_deref() == Some(hostname) {
+            return false;
+        }
+        self.hostname = Some(hostname.to_string());
+        self.hostname_source = Some(source.to_string());
+        true
+    }
+}
+
+/// Analyst-set fields, for `assets tag` and /api/assets/{mac}/tags. Omitted
+/// fields are left as they are.
+#[derive(Debug, Default, Deserialize)]
+pub struct AssetTags {
+    pub owner: Option<String>,
+    pub criticality: Option<f64>,
+    pub tags: Option<Vec<String>>,
+}
+
+pub struct AssetInventory {
+    assets: HashMap<String, Asset>,
+    // Latest MAC seen with each address
+    owners: HashMap<IpAddr, String>,
+    // MAC -> a different OS its SYNs matched, and how many times in a row
+    os_candidates: HashMap<String, (OsMatch, u32)>,
+    dirty: HashSet<String>,
+    last_flush: f64,
+    learning_secs: f64,
+    // Set on the first event when the inventory started out empty
+    learning_until: Option<f64>,
+    learning: bool,
+    writer: Option<mpsc::UnboundedSender<Asset>>,
+}
+
+impl AssetInventory {
+    pub fn new(assets: Vec<Asset>) -> Self {
+        let learning_secs = env::var("ASSET_LEARNING_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_LEARNING_SECS);
+        let mut owners = HashMap::new();
+        for asset in &assets {
+            if let Some(ip) = asset.current_address().and_then(|a| a.ip.parse().ok()) {
+                owners.insert(ip, asset.mac.clone());
+            }
+        }
+        AssetInventory {
+            learning: assets.is_empty(),
+            assets: assets.into_iter().map(|a| (a.mac.clone(), a)).collect(),
+            owners,
+            os_candidates: HashMap::new(),
+            dirty: HashSet::new(),
+            last_flush: 0.0,
+            learning_secs,
+            learning_until: None,
+            writer: None,
+        }
+    }
+
+    /// Loads the inventory and starts the tasks that write it back to
+    /// `assets` and refresh tagged criticalities.
+    pub async fn load(db: &Database) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let collection: Collection<Asset> = db.collection("assets");
+        let assets = list(&collection).await?;
+        println!("Loaded {} assets", assets.len());
+
+        let mut inventory = Self::new(assets);
+        inventory.writer = Some(spawn_asset_writer(collection.clone()));
+        spawn_criticality_refresh(collection);
+        Ok(inventory)
+    }
+
+    /// The asset for `mac`, creating it (and raising "New Device") if unseen.
+    fn asset(&mut self, mac: &str, ip: Option<IpAddr>, now: f64, detections: &mut Vec<Detection>) -> &mut Asset {
+        if !self.assets.contains_key(mac) {
+            let learning = self.is_learning(now);
+            let rotated = oui::is_randomized(mac)
+                && ip.and_then(|ip| self.owners.get(&ip)).is_some_and(|previous| oui::is_randomized(previous));
+            if !learning && !rotated {
+                detections.push(new_device(mac, ip, now));
+            }
+            self.assets.insert(mac.to_string(), Asset::new(mac, now));
+        }
+        self.dirty.insert(mac.to_string());
+        let asset = self.assets.get_mut(mac).expect("asset was just inserted");
+        asset.last_seen = now;
+        asset
+    }
+
+    fn is_learning(&self, now: f64) -> bool {
+        self.learning && self.learning_until.is_none_or(|until| now < until)
+    }
+
+    /// Records that `mac` uses `ip`, if the address is one of ours.
+    fn observe_address(&mut self, mac: &str, ip: IpAddr, now: f64, detections: &mut Vec<Detection>) {
+        if !is_lan_address(ip) || !is_unicast_mac(mac) {
+            return;
+        }
+        self.asset(mac, Some(ip), now, detections).touch_address(ip, now);
+        if self.owners.get(&ip).map(String::as_str) != Some(mac) {
+            self.owners.insert(ip, mac.to_string());
+        }
+    }
+
+    /// Sets the hostname of whichever asset currently has `ip`.
+    fn name_address(&mut self, ip: IpAddr, hostname: &str, source: &str) {
+        let hostname = hostname.trim_end_matches('.');
+        if hostname.is_empty() {
+            return;
+        }
+        let Some(mac) = self.owners.get(&ip) else { return };
+        if let Some(asset) = self.assets.get_mut(mac) {
+            if asset.set_hostname(hostname, source) {
+                self.dirty.insert(mac.clone());
+            }
+        }
+    }
+
+    fn observe_service(&mut self, event: &NetworkEvent, mac: &str, ip: IpAddr, now: f64, detections: &mut Vec<Detection>) {
+        let Some((_, port)) = split_endpoint(&event.source) else { return };
+        if CLIENT_PORTS.contains(&port) || (port >= 1024 && !SERVICE_PORTS.contains(&port)) {
+            return;
+        }
+        let service = format!("{}/{}", event.protocol.to_lowercase(), port);
+        self.asset(mac, Some(ip), now, detections).add_service(service);
+    }
+
+    fn observe_mdns(&mut self, message: &DnsMessage, mac: &str, ip: IpAddr, now: f64, detections: &mut Vec<Detection>) {
+        if !message.is_response {
+            return;
+        }
+        for record in &message.answers {
+            match record.rtype {
+                dns::TYPE_A | dns::TYPE_AAAA => {
+                    let (Some(host), Ok(address)) = (record.name.strip_suffix(".local"), record.data.parse::<IpAddr>()) else { continue };
+                    // Responders announce their own names
+                    if address == ip || self.owners.contains_key(&address) {
+                        self.name_address(address, host, "mdns");
+                    }
+                }
+                dns::TYPE_PTR => {
+                    if let Some(service) = mdns_service(&record.name) {
+                        self.asset(mac, Some(ip), now, detections).add_service(service.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn observe_netbios(&mut self, name: &NetbiosName, ip: IpAddr) {
+        if name.is_claim() && name.is_host_name() {
+            let address = name.address.map(IpAddr::V4).filter(|a| !a.is_unspecified()).unwrap_or(ip);
+            self.name_address(address, &name.name, "netbios");
+        }
+    }
+
+    fn observe_ptr(&mut self, message: &DnsMessage) {
+        if !message.is_response {
+            return;
+        }
+        for record in message.answers.iter().filter(|r| r.rtype == dns::TYPE_PTR) {
+            if let Some(ip) = dns::ptr_address(&record.name).filter(|ip| is_lan_address(*ip)) {
+                self.name_address(ip, &record.data, "ptr");
+            }
+        }
+    }
+
+    fn observe_dhcp(&mut self, message: &DhcpMessage, sender: &str, now: f64, detections: &mut Vec<Detection>) {
+        let Some(client) = message.client_mac.as_deref().filter(|mac| is_unicast_mac(mac)) else { return };
+        if message.is_request() {
+            // Starvation tools forge the client MAC; only believe clients
+            // speaking for themselves (v6 relays aside, the DUID is theirs)
+            if message.version == 4 && !client.eq_ignore_ascii_case(sender) {
+                return;
+            }
+            let hint = message.client_ip.or(message.requested_ip);
+            let asset = self.asset(client, hint, now, detections);
+            if let Some(hostname) = &message.hostname {
+                asset.set_hostname(hostname.trim_end_matches('.'), "dhcp");
+            }
+            if message.vendor_class.is_some() {
+                asset.vendor_class = message.vendor_class.clone();
+            }
+        } else if message.is_ack() {
+            if let Some(ip) = message.assigned_ip.filter(|ip| is_lan_address(*ip)) {
+                self.observe_address(client, ip, now, detections);
+            }
+        }
+    }
+
+    fn observe_syn(&mut self, syn: &TcpSyn, mac: &str, ip: IpAddr, now: f64, detections: &mut Vec<Detection>) {
+        if syn.distance() != 0 {
+            return;
+        }
+        let signature = syn.signature();
+        let os = fingerprint::identify(syn);
+        let asset = self.asset(mac, Some(ip), now, detections);
+        asset.os_signature = Some(signature.clone());
+        let Some(os) = os else { return };
+        let Some(current) = asset.os.clone() else {
+            asset.os = Some(os);
+            return;
+        };
+
+        if same_os(&current, &os) {
+            // A specific match refines a generic or fuzzy one; the flavor
+            // moves on with upgrades
+            if !os.generic && !os.fuzzy {
+                asset.os = Some(os);
+            }
+            self.os_candidates.remove(mac);
+            return;
+        }
+        if os.generic || os.fuzzy {
+            return;
+        }
+        let candidate = self.os_candidates.entry(mac.to_string()).or_insert_with(|| (os.clone(), 0));
+        if !same_os(&candidate.0, &os) {
+            *candidate = (os.clone(), 0);
+        }
+        candidate.1 += 1;
+        if candidate.1 < OS_CHANGE_CONFIRMATIONS {
+            return;
+        }
+        self.os_candidates.remove(mac);
+        if let Some(asset) = self.assets.get_mut(mac) {
+            asset.os = Some(os.clone());
+        }
+        if !self.is_learning(now) {
+            detections.push(os_changed(mac, ip, &current, &os, &signature, now));
+        }
+    }
+
+    fn flush(&mut self, now: f64) {
+        self.last_flush = now;
+        let Some(writer) = &self.writer else {
+            self.dirty.clear();
+            return;
+        };
+        for mac in self.dirty.drain() {
+            if let Some(asset) = self.assets.get(&mac) {
+                let _ = writer.send(asset.clone());
+            }
+        }
+    }
+}
+
+impl StreamDetector for AssetInventory {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        let now = event.timestamp;
+        if self.learning && self.learning_until.is_none() {
+            self.learning_until = Some(now + self.learning_secs);
+        }
+
+        if let Some(arp) = &event.arp {
+            if !arp.sender_ip.is_unspecified() {
+                self.observe_address(&arp.sender_mac, IpAddr::V4(arp.sender_ip), now, detections);
+            } else if is_unicast_mac(&arp.sender_mac) {
+                // Probes from 0.0.0.0 still tell us the MAC is here
+                self.asset(&arp.sender_mac, None, now, detections);
+            }
+            return;
+        }
+
+        let Some(mac) = event.source_mac.as_deref() else { return };
+        // Clients without a lease send from 0.0.0.0, so before the address check
+        if let Some(message) = &event.dhcp {
+            self.observe_dhcp(message, mac, now, detections);
+        }
+        let source = split_endpoint(&event.source).map(|(ip, _)| ip).unwrap_or(&event.source);
+        let Ok(ip) = source.parse::<IpAddr>() else { return };
+        if !is_lan_address(ip) || !is_unicast_mac(mac) {
+            return;
+        }
+        self.observe_address(mac, ip, now, detections);
+        self.observe_service(event, mac, ip, now, detections);
+        if let Some(message) = &event.mdns {
+            self.observe_mdns(message, mac, ip, now, detections);
+        }
+        if let Some(name) = &event.netbios {
+            self.observe_netbios(name, ip);
+        }
+        if let Some(message) = &event.dns {
+            self.observe_ptr(message);
+        }
+        if let Some(syn) = &event.tcp_syn {
+            self.observe_syn(syn, mac, ip, now, detections);
+        }
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        if now - self.last_flush >= FLUSH_SECS {
+            self.flush(now);
+        }
+    }
+}
+
+fn new_device(mac: &str, ip: Option<IpAddr>, now: f64) -> Detection {
+    let ip = ip.map(|ip| ip.to_string());
+    let mut vars = HashMap::from([("mac".to_string(), mac.to_string())]);
+    if let Some(ip) = &ip {
+        vars.insert("ip".to_string(), ip.clone());
+    }
+    if let Some(vendor) = oui::vendor(mac) {
+        vars.insert("vendor".to_string(), vendor.to_string());
+    }
+    Detection {
+        activity: SuspiciousActivity {
+            activity_type: "New Device".to_string(),
+            source: ip.clone().unwrap_or_else(|| mac.to_string()),
+            details: match &ip {
+                Some(ip) => format!("New device {} joined the network as {}", oui::describe(mac), ip),
+                None => format!("New device {} joined the network", oui::describe(mac)),
+            },
+            indicator: mac.to_string(),
+            magnitude: 1.0,
+            severity: Severity::Low,
+            score: 0.0,
+            // Most new devices are phones and guests
+            confidence: 0.3,
+            evidence: Evidence {
+                peers: vec![mac.to_string()],
+                ..Evidence::default()
+            },
+            rule_id: "asset_inventory".to_string(),
+            timestamp: now,
+        },
+        actions: Vec::new(),
+        vars,
+    }
+}
+
+fn os_changed(mac: &str, ip: IpAddr, previous: &OsMatch, os: &OsMatch, signature: &str, now: f64) -> Detection {
+    let vars = HashMap::from([
+        ("mac".to_string(), mac.to_string()),
+        ("ip".to_string(), ip.to_string()),
+        ("previous_os".to_string(), previous.to_string()),
+        ("os".to_string(), os.to_string()),
+    ]);
+    Detection {
+        activity: SuspiciousActivity {
+            activity_type: "OS Fingerprint Changed".to_string(),
+            source: ip.to_string(),
+            details: format!(
+                "{} at {} now fingerprints as {} instead of {} (SYN signature {})",
+                oui::describe(mac), ip, os, previous, signature
+            ),
+            indicator: mac.to_string(),
+            magnitude: OS_CHANGE_CONFIRMATIONS as f64,
+            severity: Severity::Medium,
+            score: 0.0,
+            // Reinstalls and dual boots are the usual reasons
+            confidence: 0.5,
+            evidence: Evidence {
+                count: Some(OS_CHANGE_CONFIRMATIONS as f64),
+                peers: vec![mac.to_string()],
+                ..Evidence::default()
+            },
+            rule_id: "asset_inventory".to_string(),
+            timestamp: now,
+        },
+        actions: Vec::new(),
+        vars,
+    }
+}
+
+/// The same OS family, whatever the version.
+fn same_os(a: &OsMatch, b: &OsMatch) -> bool {
+    a.class == b.class && a.name == b.name
+}
+
+/// Internal unicast addresses; remote hosts arrive with the router's MAC.
+fn is_lan_address(ip: IpAddr) -> bool {
+    let special = match ip {
+        IpAddr::V4(v4) => v4.is_broadcast() || v4.is_unspecified() || v4.is_multicast() || v4.is_loopback(),
+        IpAddr::V6(v6) => v6.is_unspecified() || v6.is_multicast() || v6.is_loopback(),
+    };
+    !special && is_internal_ip(&ip)
+}
+
+/// Individual addresses have the low bit of the first octet clear.
+fn is_unicast_mac(mac: &str) -> bool {
+    mac.get(..2)
+        .and_then(|octet| u8::from_str_radix(octet, 16).ok())
+        .is_some_and(|octet| octet & 0x01 == 0 && mac != "00:00:00:00:00:00")
+}
+
+/// "_ipp._tcp" from "_ipp._tcp.local", skipping the service enumeration name.
+fn mdns_service(name: &str) -> Option<&str> {
+    let service = name.strip_suffix(".local")?;
+    let valid = service.starts_with('_') && (service.ends_with("._tcp") || service.ends_with("._udp"));
+    (valid && service != "_services._dns-sd._udp").then_some(service)
+}
+
+/// An "AA:BB:CC:DD:EE:FF" MAC from common spellings (dashes, lowercase).
+pub fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_uppercase().replace('-', ":")
+}
+
+/// The inventory, most recently seen first.
+pub async fn list(collection: &Collection<Asset>) -> Result<Vec<Asset>, Box<dyn Error + Send + Sync>> {
+    let mut cursor = collection.find(doc! {}).sort(doc! { "last_seen": -1 }).await?;
+    let mut assets = Vec::new();
+    while let Some(asset) = cursor.next().await {
+        assets.push(asset?);
+    }
+    Ok(assets)
+}
+
+/// Sets an asset's owner, criticality and tags.
+pub async fn tag(collection: &Collection<Asset>, mac: &str, tags: AssetTags) -> Result<Asset, Box<dyn Error + Send + Sync>> {
+    let mut set = doc! {};
+    if let Some(owner) = tags.owner {
+        set.insert("owner", (!owner.is_empty()).then_some(owner));
+    }
+    if let Some(criticality) = tags.criticality {
+        if !(0.0..=1.0).contains(&criticality) {
+            return Err("criticality must be between 0 and 1".into());
+        }
+        set.insert("criticality", criticality);
+    }
+    if let Some(tags) = tags.tags {
+        set.insert("tags", tags);
+    }
+    if set.is_empty() {
+        return Err("nothing to set; give an owner, criticality or tags".into());
+    }
+    collection
+        .find_one_and_update(doc! { "mac": normalize_mac(mac) }, doc! { "$set": set })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await?
+        .ok_or_else(|| "no such asset".into())
+}
+
+fn criticalities() -> &'static RwLock<HashMap<IpAddr, f64>> {
+    static CRITICALITIES: OnceLock<RwLock<HashMap<IpAddr, f64>>> = OnceLock::new();
+    CRITICALITIES.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// The criticality analysts gave the asset with `ip`, if any.
+pub fn tagged_criticality(ip: IpAddr) -> Option<f64> {
+    criticalities().read().ok()?.get(&ip).copied()
+}
+
+fn spawn_criticality_refresh(collection: Collection<Asset>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CRITICALITY_REFRESH);
+        loop {
+            interval.tick().await;
+            let tagged = match list_tagged(&collection).await {
+                Ok(tagged) => tagged,
+                Err(e) => {
+                    eprintln!("Error loading asset criticalities: {}", e);
+                    continue;
+                }
+            };
+            if let Ok(mut criticalities) = criticalities().write() {
+                *criticalities = tagged;
+            }
+        }
+    });
+}
+
+async fn list_tagged(collection: &Collection<Asset>) -> Result<HashMap<IpAddr, f64>, Box<dyn Error + Send + Sync>> {
+    let mut cursor = collection.find(doc! { "criticality": { "$type": "number" } }).await?;
+    let mut assets = Vec::new();
+    while let Some(asset) = cursor.next().await {
+        assets.push(asset?);
+    }
+    Ok(tagged_addresses(&assets))
+}
+
+/// Criticality by the address each tagged asset holds now. Addresses an asset
+/// held before are skipped, since DHCP may have handed them to another host;
+/// when two assets report the same address, the later sighting wins.
+fn tagged_addresses(assets: &[Asset]) -> HashMap<IpAddr, f64> {
+    let mut current: HashMap<IpAddr, (f64, f64)> = HashMap::new();
+    for asset in assets {
+        let Some(criticality) = asset.criticality else { continue };
+        let Some(address) = asset.current_address() else { continue };
+        let Ok(ip) = address.ip.parse() else { continue };
+        match current.get(&ip) {
+            Some(&(last_seen, _)) if last_seen >= address.last_seen => {}
+            _ => {
+                current.insert(ip, (address.last_seen, criticality));
+            }
+        }
+    }
+    current.into_iter().map(|(ip, (_, criticality))| (ip, criticality)).collect()
+}
+
+fn spawn_asset_writer(collection: Collection<Asset>) -> mpsc::UnboundedSender<Asset> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Asset>();
+    tokio::spawn(async move {
+        while let Some(asset) = rx.recv().await {
+            let addresses = match bson::to_bson(&asset.addresses) {
+                Ok(addresses) => addresses,
+                Err(e) => {
+                    eprintln!("Error encoding asset {}: {}", asset.mac, e);
+                    continue;
+                }
+            };
+            let os = match bson::to_bson(&asset.os) {
+                Ok(os) => os,
+                Err(e) => {
+                    eprintln!("Error encoding asset {}: {}", asset.mac, e);
+                    continue;
+                }
+            };
+            let result = collection
+                .update_one(
+                    doc! { "mac": &asset.mac },
+                    doc! {
+                        "$min": { "first_seen": asset.first_seen },
+                        "$max": { "last_seen": asset.last_seen },
+                        "$set": {
+                            "vendor": asset.vendor.clone(),
+                            "randomized": asset.randomized,
+                            "hostname": asset.hostname.clone(),
+                            "hostname_source": asset.hostname_source.clone(),
+                            "vendor_class": asset.vendor_class.clone(),
+                            "os": os,
+                            "os_signature": asset.os_signature.clone(),
+                            "addresses": addresses,
+                            "services": asset.services.clone(),
+                        },
+                    },
+                )
+                .upsert(true)
+                .await;
+            if let Err(e) = result {
+                eprintln!("Error storing asset {}: {}", asset.mac, e);
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(mac: &str, criticality: f64, addresses: &[(&str, f64)]) -> Asset {
+        let mut asset = Asset::new(mac, 0.0);
+        asset.criticality = Some(criticality);
+        asset.addresses = addresses
+            .iter()
+            .map(|&(ip, last_seen)| AssetAddress { ip: ip.to_string(), first_seen: 0.0, last_seen })
+            .collect();
+        asset
+    }
+
+    #[test]
+    fn criticality_follows_the_current_address() {
+        let server = tagged("02:00:00:00:00:01", 0.9, &[("10.0.0.5", 100.0), ("10.0.0.7", 200.0)]);
+        let laptop = tagged("02:00:00:00:00:02", 0.2, &[("10.0.0.5", 300.0)]);
+        let untagged = Asset::new("02:00:00:00:00:03", 0.0);
+
+        let map = tagged_addresses(&[server, laptop, untagged]);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&"10.0.0.7".parse().unwrap()), Some(&0.9));
+        assert_eq!(map.get(&"10.0.0.5".parse().unwrap()), Some(&0.2));
+    }
+}
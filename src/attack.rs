@@ -0,0 +1,208 @@
+// MITRE ATT&CK technique catalog.
+//
+// data/attack.yaml lists the techniques the detectors can evidence and maps
+// activity types and rule ids to technique IDs. Alerts are stored with the
+// technique IDs and their tactics; a detection rule's own `techniques` list
+// takes precedence over the catalog mapping. ATTACK_MAPPING_FILE adds
+// techniques and replaces mappings by key.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::sync::OnceLock;
+
+const BUILTIN_CATALOG: &str = include_str!("../data/attack.yaml");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Technique {
+    pub id: String,
+    pub name: String,
+    pub tactics: Vec<String>,
+}
+
+/// The ATT&CK context stored on an alert.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AttackMapping {
+    pub techniques: Vec<String>,
+    pub tactics: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TechniqueCoverage {
+    pub id: String,
+    pub name: String,
+    /// Whether any detector or rule maps to the technique
+    pub mapped: bool,
+    pub alerts: i64,
+}
+
+/// Per-tactic detection coverage and alert counts, for /api/attack/coverage.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TacticCoverage {
+    pub tactic: String,
+    pub alerts: i64,
+    pub techniques: Vec<TechniqueCoverage>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AttackCatalog {
+    #[serde(default)]
+    techniques: Vec<Technique>,
+    /// Activity type or rule id -> technique IDs
+    #[serde(default)]
+    mappings: HashMap<String, Vec<String>>,
+}
+
+impl AttackCatalog {
+    pub fn builtin() -> Self {
+        serde_yaml::from_str(BUILTIN_CATALOG).expect("built-in ATT&CK catalog is valid YAML")
+    }
+
+    /// The built-in catalog merged with ATTACK_MAPPING_FILE, if set.
+    pub fn from_env() -> Self {
+        let mut catalog = Self::builtin();
+        if let Ok(path) = env::var("ATTACK_MAPPING_FILE") {
+            if let Err(e) = catalog.merge_file(&path) {
+                eprintln!("Error loading ATT&CK mapping {}: {}", path, e);
+            }
+        }
+        catalog
+    }
+
+    fn merge_file(&mut self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let extra: AttackCatalog = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+        for technique in extra.techniques {
+            self.techniques.retain(|t| t.id != technique.id);
+            self.techniques.push(technique);
+        }
+        self.mappings.extend(extra.mappings);
+        Ok(())
+    }
+
+    pub fn technique(&self, id: &str) -> Option<&Technique> {
+        self.techniques.iter().find(|t| t.id.eq_ignore_ascii_case(id))
+    }
+
+    /// Techniques for an alert: the rule's override, else the catalog entry for
+    /// the rule id, else the one for the activity type.
+    pub fn map(&self, activity_type: &str, rule_id: &str, rule_techniques: &[String]) -> AttackMapping {
+        let techniques: Vec<String> = if !rule_techniques.is_empty() {
+            rule_techniques.to_vec()
+        } else {
+            self.mappings
+                .get(rule_id)
+                .or_else(|| self.mappings.get(activity_type))
+                .cloned()
+                .unwrap_or_default()
+        };
+        let mut tactics = Vec::new();
+        for tactic in techniques.iter().filter_map(|id| self.technique(id)).flat_map(|t| &t.tactics) {
+            if !tactics.contains(tactic) {
+                tactics.push(tactic.clone());
+            }
+        }
+        AttackMapping { techniques, tactics }
+    }
+
+    /// Coverage of each tactic in the catalog, given alert counts per
+    /// technique and per tactic and the technique IDs rules override with.
+    pub fn coverage(&self, technique_alerts: &HashMap<String, i64>, tactic_alerts: &HashMap<String, i64>, rule_techniques: &[String]) -> Vec<TacticCoverage> {
+        let mapped = self.mapped_techniques(rule_techniques.iter());
+        let mut tactics: Vec<String> = Vec::new();
+        for tactic in self.techniques.iter().flat_map(|t| &t.tactics) {
+            if !tactics.contains(tactic) {
+                tactics.push(tactic.clone());
+            }
+        }
+        tactics
+            .into_iter()
+            .map(|tactic| TacticCoverage {
+                alerts: tactic_alerts.get(&tactic).copied().unwrap_or(0),
+                techniques: self
+                    .techniques
+                    .iter()
+                    .filter(|t| t.tactics.contains(&tactic))
+                    .map(|t| TechniqueCoverage {
+                        id: t.id.clone(),
+                        name: t.name.clone(),
+                        mapped: mapped.contains(t.id.as_str()),
+                        alerts: technique_alerts.get(&t.id).copied().unwrap_or(0),
+                    })
+                    .collect(),
+                tactic,
+            })
+            .collect()
+    }
+
+    /// Technique IDs some detector maps to, counting rule overrides.
+    fn mapped_techniques<'a>(&'a self, rule_techniques: impl Iterator<Item = &'a String>) -> BTreeSet<&'a str> {
+        self.mappings.values().flatten().chain(rule_techniques).map(String::as_str).collect()
+    }
+}
+
+/// The catalog loaded at first use.
+pub fn catalog() -> &'static AttackCatalog {
+    static CATALOG: OnceLock<AttackCatalog> = OnceLock::new();
+    CATALOG.get_or_init(AttackCatalog::from_env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleSet;
+
+    // Activity types raised by the detectors outside the YAML rules
+    const DETECTOR_ACTIVITY_TYPES: [&str; 17] = [
+        "ARP Spoofing",
+        "ARP Flip-Flop",
+        "Unsolicited ARP Reply",
+        "Gratuitous ARP Flood",
+        "ARP MAC Claims Many IPs",
+        "Beaconing",
+        "Baseline Anomaly",
+        "DNS Tunneling",
+        "NXDOMAIN Storm",
+        "DNS SERVFAIL Storm",
+        "DGA Domain Resolved",
+        "Fast Flux",
+        "Threat Intel Match",
+        "New Device",
+        "OS Fingerprint Changed",
+        "Rogue DHCP Server",
+        "DHCP Starvation",
+    ];
+
+    #[test]
+    fn every_activity_type_is_mapped() {
+        let catalog = AttackCatalog::builtin();
+        let rules = RuleSet::builtin();
+        let rule_types = rules.rules().iter().map(|r| (r.name.as_str(), r.id.as_str(), r.techniques.as_slice()));
+        let detector_types = DETECTOR_ACTIVITY_TYPES.iter().map(|t| (*t, "", &[][..]));
+        for (activity_type, rule_id, rule_techniques) in rule_types.chain(detector_types) {
+            let mapping = catalog.map(activity_type, rule_id, rule_techniques);
+            assert!(!mapping.techniques.is_empty(), "{} has no techniques", activity_type);
+            assert!(!mapping.tactics.is_empty(), "{} has no tactics", activity_type);
+        }
+    }
+
+    #[test]
+    fn mapped_techniques_are_in_the_catalog() {
+        let catalog = AttackCatalog::builtin();
+        for id in catalog.mapped_techniques(std::iter::empty()) {
+            assert!(catalog.technique(id).is_some(), "{} is not in the catalog", id);
+        }
+    }
+
+    #[test]
+    fn rule_techniques_win() {
+        let catalog = AttackCatalog::builtin();
+        let mapping = catalog.map("Port Scanning", "port_scan", &["T1571".to_string()]);
+        assert_eq!(mapping.techniques, ["T1571"]);
+        assert_eq!(mapping.tactics, ["Command and Control"]);
+
+        let mapping = catalog.map("Large Data Transfer", "", &[]);
+        assert_eq!(mapping.tactics, ["Exfiltration"]);
+    }
+}
@@ -0,0 +1,478 @@
+// Per-host behavioural baselines.
+//
+// Every internal host's traffic is summed per minute (packets, bytes,
+// distinct peers, distinct destination ports, DNS queries) and compared with
+// what that host normally does in the same hour of the week. Baselines are an
+// exponentially weighted mean and variance per (host, hour-of-week, metric),
+// persisted in the host_baselines collection. A slot only alerts after a
+// learning period, and only on increases: a quiet minute is not suspicious.
+//
+// Only minutes in which the host sent something are sampled, so the baseline
+// describes the host's rate while active rather than including idle time.
+//
+// BASELINE_Z_THRESHOLD (default 4) sets the z-score that alerts and
+// BASELINE_LEARNING_SAMPLES (default 30) how many minutes a slot must have
+// seen first.
+
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::sniff::NetworkEvent;
+use crate::stream::{split_endpoint, StreamDetector};
+use ::sniff::llm::redact::is_internal_ip;
+use ::sniff::llm::verdict::Severity;
+use futures::StreamExt;
+use mongodb::{bson::doc, Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::net::IpAddr;
+use tokio::sync::mpsc;
+
+const INTERVAL_SECS: f64 = 60.0;
+const HOURS_PER_WEEK: i64 = 168;
+// EWMA weight of the newest sample; ~0.1 remembers the last few dozen samples
+const ALPHA: f64 = 0.1;
+const DEFAULT_Z_THRESHOLD: f64 = 4.0;
+const DEFAULT_LEARNING_SAMPLES: u64 = 30;
+// Floor on the standard deviation, relative to the mean and absolute, so a
+// perfectly steady host does not alert on the first extra packet
+const MIN_RELATIVE_STDDEV: f64 = 0.1;
+const MIN_STDDEV: f64 = 1.0;
+const DNS_PORT: u16 = 53;
+// Unusual volume is not necessarily malicious
+const BASELINE_CONFIDENCE: f64 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Metric {
+    Packets,
+    Bytes,
+    Peers,
+    Ports,
+    DnsQueries,
+}
+
+impl Metric {
+    const ALL: [Metric; 5] = [Metric::Packets, Metric::Bytes, Metric::Peers, Metric::Ports, Metric::DnsQueries];
+
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Packets => "packets",
+            Metric::Bytes => "bytes",
+            Metric::Peers => "distinct_peers",
+            Metric::Ports => "distinct_ports",
+            Metric::DnsQueries => "dns_queries",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name() == name)
+    }
+
+    /// Below this a deviation is not worth an alert, whatever the z-score.
+    fn min_alert_value(self) -> f64 {
+        match self {
+            Metric::Packets => 100.0,
+            Metric::Bytes => 100_000.0,
+            Metric::Peers => 10.0,
+            Metric::Ports => 10.0,
+            Metric::DnsQueries => 30.0,
+        }
+    }
+}
+
+/// Learned state for one host, hour of the week and metric, as persisted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostBaseline {
+    pub host: String,
+    /// Hour of the week, 0-167, counted from the Unix epoch in UTC
+    pub slot: i64,
+    pub metric: String,
+    pub mean: f64,
+    pub variance: f64,
+    pub samples: u64,
+    pub updated_at: f64,
+}
+
+impl HostBaseline {
+    fn stddev(&self) -> f64 {
+        self.variance.sqrt().max(self.mean * MIN_RELATIVE_STDDEV).max(MIN_STDDEV)
+    }
+
+    fn z_score(&self, value: f64) -> f64 {
+        (value - self.mean) / self.stddev()
+    }
+
+    fn update(&mut self, value: f64, now: f64) {
+        if self.samples == 0 {
+            self.mean = value;
+            self.variance = 0.0;
+        } else {
+            let diff = value - self.mean;
+            let increment = ALPHA * diff;
+            self.mean += increment;
+            self.variance = (1.0 - ALPHA) * (self.variance + diff * increment);
+        }
+        self.samples += 1;
+        self.updated_at = now;
+    }
+}
+
+#[derive(Default)]
+struct HostInterval {
+    packets: u64,
+    bytes: u64,
+    peers: HashSet<String>,
+    ports: HashSet<u16>,
+    dns_queries: u64,
+}
+
+impl HostInterval {
+    fn value(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::Packets => self.packets as f64,
+            Metric::Bytes => self.bytes as f64,
+            Metric::Peers => self.peers.len() as f64,
+            Metric::Ports => self.ports.len() as f64,
+            Metric::DnsQueries => self.dns_queries as f64,
+        }
+    }
+}
+
+pub struct BaselineDetector {
+    baselines: HashMap<(String, i64, Metric), HostBaseline>,
+    current: HashMap<String, HostInterval>,
+    interval_start: Option<f64>,
+    z_threshold: f64,
+    learning_samples: u64,
+    writer: Option<mpsc::UnboundedSender<HostBaseline>>,
+}
+
+impl BaselineDetector {
+    pub fn new(baselines: Vec<HostBaseline>, z_threshold: f64, learning_samples: u64) -> Self {
+        let baselines = baselines
+            .into_iter()
+            .filter_map(|b| {
+                let metric = Metric::from_name(&b.metric)?;
+                Some(((b.host.clone(), b.slot, metric), b))
+            })
+            .collect();
+
+        BaselineDetector {
+            baselines,
+            current: HashMap::new(),
+            interval_start: None,
+            z_threshold,
+            learning_samples,
+            writer: None,
+        }
+    }
+
+    /// Loads persisted baselines and starts a task that writes updated slots
+    /// back to `host_baselines`.
+    pub async fn load(db: &Database) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let collection: Collection<HostBaseline> = db.collection("host_baselines");
+        let mut cursor = collection.find(doc! {}).await?;
+        let mut baselines = Vec::new();
+        while let Some(baseline) = cursor.next().await {
+            baselines.push(baseline?);
+        }
+        println!("Loaded {} host baseline slots", baselines.len());
+
+        let z_threshold = env::var("BASELINE_Z_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_Z_THRESHOLD);
+        let learning_samples = env::var("BASELINE_LEARNING_SAMPLES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_LEARNING_SAMPLES);
+
+        let mut detector = Self::new(baselines, z_threshold, learning_samples);
+        detector.writer = Some(spawn_baseline_writer(collection));
+        Ok(detector)
+    }
+
+    fn close_interval(&mut self, start: f64, detections: &mut Vec<Detection>) {
+        let slot = hour_of_week(start);
+        let now = start + INTERVAL_SECS;
+
+        for (host, interval) in std::mem::take(&mut self.current) {
+            for metric in Metric::ALL {
+                let value = interval.value(metric);
+                let baseline = self
+                    .baselines
+                    .entry((host.clone(), slot, metric))
+                    .or_insert_with(|| HostBaseline {
+                        host: host.clone(),
+                        slot,
+                        metric: metric.name().to_string(),
+                        mean: 0.0,
+                        variance: 0.0,
+                        samples: 0,
+                        updated_at: now,
+                    });
+
+                let mut sample = value;
+                if baseline.samples >= self.learning_samples {
+                    let z = baseline.z_score(value);
+                    if z >= self.z_threshold && value >= metric.min_alert_value() {
+                        detections.push(anomaly(&host, slot, metric, value, baseline, z, self.z_threshold, now));
+                        // Learn from the spike only up to the alert line, so
+                        // one burst does not teach the baseline to expect it
+                        sample = baseline.mean + self.z_threshold * baseline.stddev();
+                    }
+                }
+                baseline.update(sample, now);
+
+                if let Some(writer) = &self.writer {
+                    let _ = writer.send(baseline.clone());
+                }
+            }
+        }
+    }
+}
+
+impl StreamDetector for BaselineDetector {
+    fn observe(&mut self, event: &NetworkEvent, _detections: &mut Vec<Detection>) {
+        let (src_ip, _) = match split_endpoint(&event.source) {
+            Some(endpoint) => endpoint,
+            None => return,
+        };
+        match src_ip.parse::<IpAddr>() {
+            Ok(ip) if is_internal_ip(&ip) => {}
+            _ => return,
+        }
+
+        self.interval_start.get_or_insert(interval_of(event.timestamp));
+        let interval = self.current.entry(src_ip.to_string()).or_default();
+        interval.packets += 1;
+        interval.bytes += event.payload_size as u64;
+        if let Some((dest_ip, dest_port)) = split_endpoint(&event.destination) {
+            interval.peers.insert(dest_ip.to_string());
+            interval.ports.insert(dest_port);
+            if event.protocol == "DNS" && dest_port == DNS_PORT {
+                interval.dns_queries += 1;
+            }
+        }
+    }
+
+    fn tick(&mut self, now: f64, detections: &mut Vec<Detection>) {
+        match self.interval_start {
+            Some(start) if now >= start + INTERVAL_SECS => {
+                self.close_interval(start, detections);
+                self.interval_start = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn anomaly(host: &str, slot: i64, metric: Metric, value: f64, baseline: &HostBaseline, z: f64, z_threshold: f64, now: f64) -> Detection {
+    let severity = if z >= 2.0 * z_threshold { Severity::High } else { Severity::Medium };
+    Detection {
+        activity: SuspiciousActivity {
+            activity_type: "Baseline Anomaly".to_string(),
+            source: host.to_string(),
+            details: format!(
+                "{} {} in {} seconds, baseline for hour {} of the week is {:.1} ± {:.1} (z = {:.1})",
+                value, metric.name(), INTERVAL_SECS, slot, baseline.mean, baseline.stddev(), z
+            ),
+            indicator: metric.name().to_string(),
+            magnitude: z,
+            severity,
+            score: 0.0,
+            confidence: BASELINE_CONFIDENCE,
+            evidence: Evidence {
+                count: Some(value),
+                metrics: BTreeMap::from([
+                    ("baseline_mean".to_string(), baseline.mean),
+                    ("baseline_stddev".to_string(), baseline.stddev()),
+                    ("z_score".to_string(), z),
+                ]),
+                ..Evidence::default()
+            },
+            rule_id: "host_baseline".to_string(),
+            timestamp: now,
+        },
+        actions: Vec::new(),
+        vars: HashMap::new(),
+    }
+}
+
+fn interval_of(timestamp: f64) -> f64 {
+    (timestamp / INTERVAL_SECS).floor() * INTERVAL_SECS
+}
+
+fn hour_of_week(timestamp: f64) -> i64 {
+    (timestamp as i64).div_euclid(3600).rem_euclid(HOURS_PER_WEEK)
+}
+
+fn spawn_baseline_writer(collection: Collection<HostBaseline>) -> mpsc::UnboundedSender<HostBaseline> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<HostBaseline>();
+    tokio::spawn(async move {
+        while let Some(baseline) = rx.recv().await {
+            let result = collection
+                .update_one(
+                    doc! { "host": &baseline.host, "slot": baseline.slot, "metric": &baseline.metric },
+                    doc! { "$set": {
+                        "mean": baseline.mean,
+                        "variance": baseline.variance,
+                        "samples": baseline.samples as i64,
+                        "updated_at": baseline.updated_at,
+                    }},
+                )
+                .upsert(true)
+                .await;
+            if let Err(e) = result {
+                eprintln!("Error storing host baseline: {}", e);
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Monday 1970-01-05 10:00 UTC, hour 106 of the week
+    const START: f64 = 4.0 * 86_400.0 + 10.0 * 3600.0;
+    const HOST: &str = "10.0.0.5";
+
+    fn packet(source: &str, timestamp: f64) -> NetworkEvent {
+        NetworkEvent {
+            protocol: "TCP".to_string(),
+            source: source.to_string(),
+            destination: "10.0.0.1:443".to_string(),
+            payload_size: 100,
+            timestamp,
+            source_mac: None,
+            arp: None,
+            dns: None,
+            mdns: None,
+            netbios: None,
+            dhcp: None,
+            tcp_syn: None,
+        }
+    }
+
+    /// Feeds one minute of `packets` packets from HOST and closes it.
+    fn minute(detector: &mut BaselineDetector, index: u32, packets: u32) -> Vec<Detection> {
+        let start = START + index as f64 * INTERVAL_SECS;
+        let mut detections = Vec::new();
+        for _ in 0..packets {
+            detector.observe(&packet(&format!("{}:50000", HOST), start + 1.0), &mut detections);
+        }
+        detector.tick(start + INTERVAL_SECS, &mut detections);
+        detections
+    }
+
+    fn packets_baseline(detector: &BaselineDetector) -> &HostBaseline {
+        &detector.baselines[&(HOST.to_string(), hour_of_week(START), Metric::Packets)]
+    }
+
+    fn baseline(mean: f64, variance: f64) -> HostBaseline {
+        HostBaseline {
+            host: HOST.to_string(),
+            slot: 0,
+            metric: "packets".to_string(),
+            mean,
+            variance,
+            samples: 10,
+            updated_at: 0.0,
+        }
+    }
+
+    #[test]
+    fn ewma_update() {
+        let mut b = baseline(0.0, 0.0);
+        b.samples = 0;
+        b.update(10.0, 60.0);
+        assert_eq!((b.mean, b.variance, b.samples, b.updated_at), (10.0, 0.0, 1, 60.0));
+
+        b.update(20.0, 120.0);
+        assert!((b.mean - 11.0).abs() < 1e-9);
+        assert!((b.variance - 9.0).abs() < 1e-9);
+        assert_eq!(b.samples, 2);
+    }
+
+    #[test]
+    fn stddev_is_floored() {
+        assert_eq!(baseline(0.0, 0.0).stddev(), MIN_STDDEV);
+        assert_eq!(baseline(100.0, 0.0).stddev(), 10.0);
+        assert_eq!(baseline(100.0, 400.0).stddev(), 20.0);
+        assert_eq!(baseline(100.0, 400.0).z_score(180.0), 4.0);
+        assert_eq!(baseline(100.0, 400.0).z_score(60.0), -2.0);
+    }
+
+    #[test]
+    fn alerts_only_after_learning() {
+        let mut detector = BaselineDetector::new(Vec::new(), 4.0, 3);
+        assert!(minute(&mut detector, 0, 10).is_empty());
+        assert!(minute(&mut detector, 1, 10).is_empty());
+        // Still learning: the spike is absorbed without an alert
+        assert!(minute(&mut detector, 2, 500).is_empty());
+        assert_eq!(packets_baseline(&detector).samples, 3);
+
+        let mut detector = BaselineDetector::new(Vec::new(), 4.0, 3);
+        for i in 0..3 {
+            assert!(minute(&mut detector, i, 10).is_empty());
+        }
+        let detections = minute(&mut detector, 3, 500);
+        assert_eq!(detections.len(), 1);
+        let activity = &detections[0].activity;
+        assert_eq!(activity.activity_type, "Baseline Anomaly");
+        assert_eq!(activity.source, HOST);
+        assert_eq!(activity.indicator, "packets");
+        assert_eq!(activity.severity, Severity::High);
+        assert_eq!(activity.evidence.count, Some(500.0));
+    }
+
+    #[test]
+    fn learns_spikes_only_up_to_the_alert_line() {
+        let mut detector = BaselineDetector::new(Vec::new(), 4.0, 3);
+        for i in 0..3 {
+            minute(&mut detector, i, 10);
+        }
+        assert_eq!(minute(&mut detector, 3, 500).len(), 1);
+        // The spike is learned as mean + 4 * stddev = 14, not 500
+        let b = packets_baseline(&detector);
+        assert!((b.mean - 10.4).abs() < 1e-9);
+        assert_eq!(b.samples, 4);
+    }
+
+    #[test]
+    fn quiet_minutes_and_small_spikes_do_not_alert() {
+        let mut detector = BaselineDetector::new(Vec::new(), 4.0, 3);
+        for i in 0..3 {
+            minute(&mut detector, i, 50);
+        }
+        assert!(minute(&mut detector, 3, 1).is_empty());
+        // High z-score but below the packet floor worth alerting on
+        let mut detector = BaselineDetector::new(Vec::new(), 4.0, 3);
+        for i in 0..3 {
+            minute(&mut detector, i, 5);
+        }
+        assert!(minute(&mut detector, 3, 90).is_empty());
+    }
+
+    #[test]
+    fn external_hosts_are_not_baselined() {
+        let mut detector = BaselineDetector::new(Vec::new(), 4.0, 0);
+        let mut detections = Vec::new();
+        detector.observe(&packet("8.8.8.8:53", START), &mut detections);
+        detector.tick(START + INTERVAL_SECS, &mut detections);
+        assert!(detector.baselines.is_empty());
+    }
+
+    #[test]
+    fn hour_of_week_wraps() {
+        assert_eq!(hour_of_week(0.0), 0);
+        assert_eq!(hour_of_week(START + 59.0 * 60.0), 106);
+        assert_eq!(hour_of_week(167.0 * 3600.0 + 5.0), 167);
+        assert_eq!(hour_of_week(168.0 * 3600.0), 0);
+        assert_eq!(hour_of_week(-1.0), 167);
+        assert_eq!(interval_of(START + 125.0), START + 120.0);
+    }
+}
@@ -0,0 +1,335 @@
+// C2 beaconing detection.
+//
+// Implants typically call home on a timer, so for each (internal host,
+// external destination) pair we look at when connections start and how much
+// they send. Events are packets, not connections: a connection starts with
+// the first outbound packet after SESSION_GAP_SECS of silence on the pair.
+//
+// Over the last few hours of connection starts the detector scores
+//   - periodicity: 1 - coefficient of variation of the inter-arrival times
+//   - regularity: share of intervals within JITTER_TOLERANCE of the median,
+//     a one-bin histogram around the period that tolerates the odd missed
+//     or extra check-in that wrecks the CV
+//   - size consistency: 1 - coefficient of variation of bytes sent
+// and raises "Beaconing" when the weighted confidence is high enough.
+
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::sniff::NetworkEvent;
+use crate::stream::{split_endpoint, StreamDetector};
+use ::sniff::llm::redact::is_internal_ip;
+use ::sniff::llm::verdict::Severity;
+use std::collections::BTreeMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+
+const SESSION_GAP_SECS: f64 = 5.0;
+const WINDOW_SECS: f64 = 6.0 * 3600.0;
+const MAX_SESSIONS: usize = 128;
+const MIN_SESSIONS: usize = 8;
+// Anything faster is streaming or polling within one conversation
+const MIN_PERIOD_SECS: f64 = 10.0;
+const JITTER_TOLERANCE: f64 = 0.2;
+const PERIODICITY_WEIGHT: f64 = 0.45;
+const REGULARITY_WEIGHT: f64 = 0.35;
+const SIZE_WEIGHT: f64 = 0.2;
+const MIN_CONFIDENCE: f64 = 0.8;
+const HIGH_CONFIDENCE: f64 = 0.9;
+const ALERT_COOLDOWN: f64 = 3600.0;
+// Bounds memory on hosts that talk to a large part of the internet
+const MAX_PAIRS: usize = 50_000;
+// DNS and NTP are periodic by design
+const IGNORED_PORTS: [u16; 2] = [53, 123];
+
+struct Session {
+    start: f64,
+    bytes: u64,
+}
+
+#[derive(Default)]
+struct PairHistory {
+    sessions: VecDeque<Session>,
+    last_packet: f64,
+    last_alert: Option<f64>,
+}
+
+struct BeaconScore {
+    period: f64,
+    jitter: f64,
+    confidence: f64,
+}
+
+#[derive(Default)]
+pub struct BeaconDetector {
+    // (internal host, destination ip:port) -> connection history
+    pairs: HashMap<(String, String), PairHistory>,
+}
+
+impl BeaconDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StreamDetector for BeaconDetector {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        if event.protocol != "TCP" && event.protocol != "UDP" {
+            return;
+        }
+        let (src_ip, dest_ip, dest_port) = match (split_endpoint(&event.source), split_endpoint(&event.destination)) {
+            (Some((src_ip, _)), Some((dest_ip, dest_port))) => (src_ip, dest_ip, dest_port),
+            _ => return,
+        };
+        if IGNORED_PORTS.contains(&dest_port) || !is_outbound(src_ip, dest_ip) {
+            return;
+        }
+
+        let key = (src_ip.to_string(), event.destination.clone());
+        if !self.pairs.contains_key(&key) && self.pairs.len() >= MAX_PAIRS {
+            return;
+        }
+        let history = self.pairs.entry(key).or_default();
+        let now = event.timestamp;
+
+        let continues_session = history.sessions.back().is_some() && now - history.last_packet < SESSION_GAP_SECS;
+        history.last_packet = now;
+        if continues_session {
+            if let Some(session) = history.sessions.back_mut() {
+                session.bytes += event.payload_size as u64;
+            }
+            return;
+        }
+
+        history.sessions.push_back(Session { start: now, bytes: event.payload_size as u64 });
+        while history.sessions.len() > MAX_SESSIONS || history.sessions.front().is_some_and(|s| now - s.start > WINDOW_SECS) {
+            history.sessions.pop_front();
+        }
+        if history.last_alert.is_some_and(|t| now - t < ALERT_COOLDOWN) {
+            return;
+        }
+
+        let score = match score(&history.sessions) {
+            Some(score) if score.confidence >= MIN_CONFIDENCE => score,
+            _ => return,
+        };
+        history.last_alert = Some(now);
+
+        let count = history.sessions.len();
+        let severity = if score.confidence >= HIGH_CONFIDENCE { Severity::High } else { Severity::Medium };
+        detections.push(Detection {
+            activity: SuspiciousActivity {
+                activity_type: "Beaconing".to_string(),
+                source: src_ip.to_string(),
+                details: format!(
+                    "{} connections to {} about every {:.0}s (jitter {:.0}%, confidence {:.2})",
+                    count,
+                    event.destination,
+                    score.period,
+                    score.jitter * 100.0,
+                    score.confidence
+                ),
+                indicator: event.destination.clone(),
+                magnitude: score.confidence,
+                severity,
+                score: 0.0,
+                confidence: score.confidence,
+                evidence: Evidence {
+                    count: Some(count as f64),
+                    peers: vec![event.destination.clone()],
+                    metrics: BTreeMap::from([
+                        ("period_secs".to_string(), score.period),
+                        ("jitter".to_string(), score.jitter),
+                    ]),
+                    ..Evidence::default()
+                },
+                rule_id: "beaconing".to_string(),
+                timestamp: now,
+            },
+            actions: Vec::new(),
+            vars: HashMap::from([
+                ("period".to_string(), format!("{:.0}", score.period)),
+                ("connections".to_string(), count.to_string()),
+            ]),
+        });
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        self.pairs.retain(|_, history| now - history.last_packet <= WINDOW_SECS);
+    }
+}
+
+fn is_outbound(src_ip: &str, dest_ip: &str) -> bool {
+    match (src_ip.parse::<IpAddr>(), dest_ip.parse::<IpAddr>()) {
+        (Ok(src), Ok(dest)) => is_internal_ip(&src) && !is_internal_ip(&dest),
+        _ => false,
+    }
+}
+
+fn score(sessions: &VecDeque<Session>) -> Option<BeaconScore> {
+    if sessions.len() < MIN_SESSIONS {
+        return None;
+    }
+
+    let starts: Vec<f64> = sessions.iter().map(|s| s.start).collect();
+    let intervals: Vec<f64> = starts.windows(2).map(|w| w[1] - w[0]).collect();
+    let period = median(&intervals);
+    if period < MIN_PERIOD_SECS {
+        return None;
+    }
+
+    let jitter = coefficient_of_variation(&intervals);
+    let regular = intervals
+        .iter()
+        .filter(|i| (*i - period).abs() <= period * JITTER_TOLERANCE)
+        .count() as f64
+        / intervals.len() as f64;
+    let sizes: Vec<f64> = sessions.iter().map(|s| s.bytes as f64).collect();
+    let size_consistency = 1.0 - coefficient_of_variation(&sizes).min(1.0);
+
+    let confidence = PERIODICITY_WEIGHT * (1.0 - jitter.min(1.0))
+        + REGULARITY_WEIGHT * regular
+        + SIZE_WEIGHT * size_consistency;
+    Some(BeaconScore { period, jitter, confidence })
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+fn coefficient_of_variation(values: &[f64]) -> f64 {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    if mean == 0.0 {
+        return 0.0;
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    variance.sqrt() / mean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEST: &str = "203.0.113.9:443";
+
+    fn packet(timestamp: f64, payload_size: usize) -> NetworkEvent {
+        NetworkEvent {
+            protocol: "TCP".to_string(),
+            source: "10.0.0.5:50000".to_string(),
+            destination: DEST.to_string(),
+            payload_size,
+            timestamp,
+            source_mac: None,
+            arp: None,
+            dns: None,
+            mdns: None,
+            netbios: None,
+            dhcp: None,
+            tcp_syn: None,
+        }
+    }
+
+    fn sessions(starts: &[f64]) -> VecDeque<Session> {
+        starts.iter().map(|&start| Session { start, bytes: 512 }).collect()
+    }
+
+    /// Connection starts separated by `intervals`, beginning at 1000.
+    fn starts(intervals: &[f64]) -> Vec<f64> {
+        let mut t = 1000.0;
+        let mut starts = vec![t];
+        for interval in intervals {
+            t += interval;
+            starts.push(t);
+        }
+        starts
+    }
+
+    #[test]
+    fn median_and_cv() {
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), 2.5);
+        assert_eq!(coefficient_of_variation(&[5.0, 5.0, 5.0]), 0.0);
+        assert_eq!(coefficient_of_variation(&[0.0, 0.0]), 0.0);
+        assert_eq!(coefficient_of_variation(&[1.0, 3.0]), 0.5);
+    }
+
+    #[test]
+    fn jittered_period_scores_high() {
+        let intervals = [55.0, 65.0, 58.0, 62.0, 57.0, 63.0, 60.0, 61.0, 59.0, 60.0];
+        let score = score(&sessions(&starts(&intervals))).unwrap();
+        assert_eq!(score.period, 60.0);
+        assert!(score.jitter < 0.1);
+        assert!(score.confidence >= HIGH_CONFIDENCE);
+    }
+
+    #[test]
+    fn one_missed_check_in_still_alerts() {
+        let mut intervals = [60.0; 11];
+        intervals[5] = 120.0;
+        let score = score(&sessions(&starts(&intervals))).unwrap();
+        assert_eq!(score.period, 60.0);
+        assert!(score.jitter > 0.2);
+        assert!(score.confidence >= MIN_CONFIDENCE);
+    }
+
+    #[test]
+    fn irregular_or_short_histories_do_not_score() {
+        assert!(score(&sessions(&starts(&[60.0; 6]))).is_none());
+        // Faster than MIN_PERIOD_SECS is one conversation, not a beacon
+        assert!(score(&sessions(&starts(&[8.0; 20]))).is_none());
+
+        let intervals = [20.0, 300.0, 45.0, 900.0, 15.0, 130.0, 600.0, 70.0, 33.0, 400.0];
+        assert!(score(&sessions(&starts(&intervals))).unwrap().confidence < MIN_CONFIDENCE);
+    }
+
+    #[test]
+    fn packets_within_the_gap_share_a_session() {
+        let mut detector = BeaconDetector::new();
+        let mut detections = Vec::new();
+        for t in [100.0, 101.0, 104.0, 108.5, 120.0] {
+            detector.observe(&packet(t, 100), &mut detections);
+        }
+        let history = &detector.pairs[&("10.0.0.5".to_string(), DEST.to_string())];
+        let sessions: Vec<(f64, u64)> = history.sessions.iter().map(|s| (s.start, s.bytes)).collect();
+        assert_eq!(sessions, vec![(100.0, 400), (120.0, 100)]);
+    }
+
+    #[test]
+    fn alerts_once_per_cooldown() {
+        let mut detector = BeaconDetector::new();
+        let mut detections = Vec::new();
+        for i in 0..20 {
+            let start = 1000.0 + i as f64 * 60.0;
+            detector.observe(&packet(start, 300), &mut detections);
+            detector.observe(&packet(start + 0.5, 200), &mut detections);
+        }
+        assert_eq!(detections.len(), 1);
+        let activity = &detections[0].activity;
+        assert_eq!(activity.activity_type, "Beaconing");
+        assert_eq!(activity.source, "10.0.0.5");
+        assert_eq!(activity.indicator, DEST);
+        assert_eq!(activity.severity, Severity::High);
+        assert_eq!(activity.evidence.count, Some(MIN_SESSIONS as f64));
+        assert_eq!(detections[0].vars["period"], "60");
+    }
+
+    #[test]
+    fn ignores_internal_and_dns_traffic() {
+        let mut detector = BeaconDetector::new();
+        let mut detections = Vec::new();
+        for i in 0..20 {
+            let mut event = packet(1000.0 + i as f64 * 60.0, 100);
+            event.destination = "10.0.0.1:443".to_string();
+            detector.observe(&event, &mut detections);
+            event.destination = "8.8.8.8:53".to_string();
+            detector.observe(&event, &mut detections);
+        }
+        assert!(detections.is_empty());
+        assert!(detector.pairs.is_empty());
+    }
+}
@@ -1,13 +1,31 @@
 use mongodb::{
     Client, Collection, Database,
     options::ClientOptions,
-    bson::doc,
+    bson::{doc, oid::ObjectId},
 };
 use crate::sniff::NetworkEvent;
 use crate::llm::LlmInference;
+use crate::llm::budget::{BudgetStatus, TokenBudget};
+use crate::llm::verdict::Severity;
+use crate::suppress::{self, SuppressionRule};
+use crate::alerts::{self, Alert, AlertNote, AlertState};
+use crate::assets::{self, Asset, AssetTags};
+use crate::incidents::Incident;
+use crate::attack::{self, TacticCoverage};
+use crate::rules::RuleSet;
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 
+/// Query volume for one registrable domain.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DomainSummary {
+    pub registered_domain: String,
+    pub queries: i64,
+    pub distinct_names: i64,
+    pub clients: i64,
+}
+
 #[derive(Clone)]
 pub struct NetworkDB {
     database: Database,
@@ -16,7 +34,6 @@ pub struct NetworkDB {
     arp_collection: Collection<NetworkEvent>,
     dns_collection: Collection<NetworkEvent>,
     sus_collection: Collection<NetworkEvent>,
-    dns_mapping: Collection<NetworkEvent>,
     llm_inference_collection: Collection<LlmInference>,
 }
 
@@ -35,7 +52,6 @@ impl NetworkDB {
             arp_collection: db.collection("arp_events"),
             dns_collection: db.collection("dns_events"),
             sus_collection: db.collection("sus_events"),
-            dns_mapping: db.collection("dns_mappings"),
             llm_inference_collection: db.collection("llm_inferences")
         })
     }
@@ -57,13 +73,183 @@ impl NetworkDB {
         Ok(())
     }
 
-    pub async fn store_suspicious_event(&self, event: NetworkEvent) -> Result<(), Box<dyn Error>> {
-        self.sus_collection.insert_one(event).await?;
-        Ok(())
+    /// Returns stored LLM inferences whose verdict is at or above `min_severity`.
+    pub async fn get_inferences_by_severity(&self, min_severity: Severity) -> Result<Vec<LlmInference>, Box<dyn Error>> {
+        let severities: Vec<String> = [Severity::Low, Severity::Medium, Severity::High, Severity::Critical]
+            .iter()
+            .filter(|s| **s >= min_severity)
+            .map(|s| s.to_string())
+            .collect();
+
+        let mut cursor = self.llm_inference_collection
+            .find(doc! { "verdict.severity": { "$in": severities } })
+            .await?;
+
+        let mut inferences = Vec::new();
+        while let Some(inference) = cursor.next().await {
+            inferences.push(inference?);
+        }
+        Ok(inferences)
+    }
+
+    /// Today's token usage and remaining budget for each of `providers`.
+    pub async fn get_llm_budget_status(&self, providers: &[String]) -> Result<Vec<BudgetStatus>, Box<dyn Error + Send + Sync>> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs_f64();
+        let providers: Vec<&str> = providers.iter().map(String::as_str).collect();
+        let budget = TokenBudget::load(self.database.collection("llm_usage"), &providers, now).await?;
+        Ok(providers.iter().map(|p| budget.status(p, now)).collect())
+    }
+
+    /// The most queried registrable domains in the stored DNS events.
+    pub async fn get_dns_domain_summary(&self, limit: i64) -> Result<Vec<DomainSummary>, Box<dyn Error + Send + Sync>> {
+        let pipeline = vec![
+            doc! { "$match": { "dns.is_response": false } },
+            doc! { "$addFields": {
+                "question": { "$arrayElemAt": ["$dns.questions", 0] },
+                "_src": { "$regexFind": { "input": "$source", "regex": r"^(.*):(\d+)$" } },
+            }},
+            doc! { "$addFields": { "client": { "$arrayElemAt": ["$_src.captures", 0] } } },
+            doc! { "$match": { "question.registered_domain": { "$exists": true } } },
+            doc! { "$group": {
+                "_id": "$question.registered_domain",
+                "queries": { "$sum": 1 },
+                "names": { "$addToSet": "$question.name" },
+                "clients": { "$addToSet": "$client" },
+            }},
+            doc! { "$project": {
+                "_id": 0,
+                "registered_domain": "$_id",
+                "queries": 1,
+                "distinct_names": { "$size": "$names" },
+                "clients": { "$size": "$clients" },
+            }},
+            doc! { "$sort": { "queries": -1 } },
+            doc! { "$limit": limit },
+        ];
+
+        let mut cursor = self.dns_collection.aggregate(pipeline).await?;
+        let mut summaries = Vec::new();
+        while let Some(document) = cursor.next().await {
+            summaries.push(mongodb::bson::from_document(document?)?);
+        }
+        Ok(summaries)
+    }
+
+    /// Every suppression rule, expired ones included, with its hit count.
+    pub async fn list_suppressions(&self) -> Result<Vec<SuppressionRule>, Box<dyn Error + Send + Sync>> {
+        suppress::list(&self.database.collection("suppression_rules"), None).await
+    }
+
+    /// Validates and stores a suppression rule, returning its id.
+    pub async fn add_suppression(&self, mut rule: SuppressionRule) -> Result<ObjectId, Box<dyn Error + Send + Sync>> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs_f64();
+        rule.validate(now)?;
+        rule.id = None;
+        rule.created_at = now;
+        rule.hits = 0;
+        rule.last_hit = None;
+        let collection: Collection<SuppressionRule> = self.database.collection("suppression_rules");
+        let result = collection.insert_one(rule).await?;
+        result.inserted_id.as_object_id().ok_or_else(|| "suppression insert returned no ObjectId".into())
+    }
+
+    /// Deletes a suppression rule by id, returning whether it existed.
+    pub async fn delete_suppression(&self, id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let id = ObjectId::parse_str(id)?;
+        let collection: Collection<SuppressionRule> = self.database.collection("suppression_rules");
+        let result = collection.delete_one(doc! { "_id": id }).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    /// Alerts, most recently seen first, optionally only those in `state`.
+    pub async fn list_alerts(&self, state: Option<AlertState>, limit: i64) -> Result<Vec<Alert>, Box<dyn Error + Send + Sync>> {
+        alerts::list(&self.sus_collection.clone_with_type(), state, limit).await
+    }
+
+    pub async fn set_alert_state(&self, id: &str, state: AlertState, by: &str) -> Result<Alert, Box<dyn Error + Send + Sync>> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs_f64();
+        alerts::set_state(&self.sus_collection.clone_with_type(), ObjectId::parse_str(id)?, state, by, now).await
+    }
+
+    pub async fn assign_alert(&self, id: &str, assignee: Option<&str>) -> Result<Alert, Box<dyn Error + Send + Sync>> {
+        alerts::assign(&self.sus_collection.clone_with_type(), ObjectId::parse_str(id)?, assignee).await
+    }
+
+    pub async fn add_alert_note(&self, id: &str, author: &str, text: &str) -> Result<Alert, Box<dyn Error + Send + Sync>> {
+        let note = AlertNote {
+            author: author.to_string(),
+            text: text.to_string(),
+            timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs_f64(),
+        };
+        alerts::add_note(&self.sus_collection.clone_with_type(), ObjectId::parse_str(id)?, note).await
+    }
+
+    /// The asset inventory, most recently seen first.
+    pub async fn list_assets(&self) -> Result<Vec<Asset>, Box<dyn Error + Send + Sync>> {
+        assets::list(&self.database.collection("assets")).await
+    }
+
+    pub async fn tag_asset(&self, mac: &str, tags: AssetTags) -> Result<Asset, Box<dyn Error + Send + Sync>> {
+        assets::tag(&self.database.collection("assets"), mac, tags).await
+    }
+
+    /// Incidents, highest score first.
+    pub async fn list_incidents(&self, limit: i64) -> Result<Vec<Incident>, Box<dyn Error + Send + Sync>> {
+        let collection: Collection<Incident> = self.database.collection("incidents");
+        let mut cursor = collection.find(doc! {}).sort(doc! { "score": -1, "last_seen": -1 }).limit(limit).await?;
+        let mut incidents = Vec::new();
+        while let Some(incident) = cursor.next().await {
+            incidents.push(incident?);
+        }
+        Ok(incidents)
+    }
+
+    /// An incident with its alerts.
+    pub async fn get_incident(&self, id: &str) -> Result<Option<(Incident, Vec<Alert>)>, Box<dyn Error + Send + Sync>> {
+        let collection: Collection<Incident> = self.database.collection("incidents");
+        let incident = match collection.find_one(doc! { "_id": ObjectId::parse_str(id)? }).await? {
+            Some(incident) => incident,
+            None => return Ok(None),
+        };
+        let alert_ids: Vec<ObjectId> = incident.alerts.iter().map(|a| a.alert_id).collect();
+        let alerts_collection: Collection<Alert> = self.sus_collection.clone_with_type();
+        let mut cursor = alerts_collection.find(doc! { "_id": { "$in": alert_ids } }).sort(doc! { "first_seen": 1 }).await?;
+        let mut alerts = Vec::new();
+        while let Some(alert) = cursor.next().await {
+            alerts.push(alert?);
+        }
+        Ok(Some((incident, alerts)))
+    }
+
+    /// ATT&CK coverage by tactic: which catalog techniques some detector maps
+    /// to and how many stored alerts carry each technique and tactic.
+    pub async fn get_attack_coverage(&self) -> Result<Vec<TacticCoverage>, Box<dyn Error + Send + Sync>> {
+        let technique_alerts = self.count_alerts_by("techniques").await?;
+        let tactic_alerts = self.count_alerts_by("tactics").await?;
+        let rule_techniques: Vec<String> = RuleSet::from_env().rules().iter().flat_map(|r| r.techniques.clone()).collect();
+        Ok(attack::catalog().coverage(&technique_alerts, &tactic_alerts, &rule_techniques))
+    }
+
+    /// Alert counts per value of an array field.
+    async fn count_alerts_by(&self, field: &str) -> Result<std::collections::HashMap<String, i64>, Box<dyn Error + Send + Sync>> {
+        let pipeline = vec![
+            doc! { "$unwind": format!("${}", field) },
+            doc! { "$group": { "_id": format!("${}", field), "count": { "$sum": 1_i64 } } },
+        ];
+        let mut cursor = self.sus_collection.aggregate(pipeline).await?;
+        let mut counts = std::collections::HashMap::new();
+        while let Some(document) = cursor.next().await {
+            let document = document?;
+            if let (Ok(key), Ok(count)) = (document.get_str("_id"), document.get_i64("count")) {
+                counts.insert(key.to_string(), count);
+            }
+        }
+        Ok(counts)
     }
 
     pub async fn refresh_logs(&self) -> Result<(), Box<dyn Error>> {
-        let collections = [&self.tcp_collection, &self.udp_collection, &self.arp_collection, &self.dns_collection, &self.sus_collection];
+        // Alerts are kept; they carry analyst state
+        let collections = [&self.tcp_collection, &self.udp_collection, &self.arp_collection, &self.dns_collection];
         for collection in collections {
             collection.delete_many(doc! {}).await?;
         }
@@ -0,0 +1,297 @@
+// Scored classifier for algorithmically generated domains (DGA).
+//
+// A DGA name is judged on its registrable label, the part just left of the
+// public suffix ("x7kq2p" in www.x7kq2p.co.uk), from a handful of features
+// that each contribute 0..1 times their weight to the score:
+//   - character bigram likelihood under a model trained on the embedded
+//     benign list (data/benign_domains.txt); pronounceable names score low
+//   - Shannon entropy, scaled by how much the label's length allows
+//   - the longest run of consonants
+//   - the share of digits
+//   - length
+// Labels shorter than SHORT_LABEL chars carry too little signal and are
+// damped towards zero; punycode (xn--) labels are not scored.
+
+use crate::sniff::domain::DomainName;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const BENIGN_DOMAINS: &str = include_str!("../data/benign_domains.txt");
+
+const START: char = '^';
+const END: char = '$';
+// a-z, 0-9, '-' and the end marker a bigram can lead to
+const ALPHABET_SIZE: f64 = 38.0;
+
+const SHORT_LABEL: usize = 6;
+const NGRAM_WEIGHT: f64 = 0.4;
+const ENTROPY_WEIGHT: f64 = 0.15;
+const CONSONANT_WEIGHT: f64 = 0.15;
+const DIGIT_WEIGHT: f64 = 0.15;
+const LENGTH_WEIGHT: f64 = 0.15;
+// Average bits per bigram: benign names sit around the first, random strings
+// around the second
+const NGRAM_BENIGN_BITS: f64 = -3.8;
+const NGRAM_RANDOM_BITS: f64 = -5.4;
+const MEDIUM_SCORE: f64 = 0.45;
+const HIGH_SCORE: f64 = 0.65;
+
+#[derive(Debug, Clone)]
+pub struct DgaFeature {
+    pub name: &'static str,
+    /// The raw measurement, e.g. bits per bigram or a ratio
+    pub value: f64,
+    /// What it added to the score
+    pub contribution: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DgaScore {
+    pub label: String,
+    /// 0 (benign-looking) to 1 (generated-looking)
+    pub score: f64,
+    pub features: Vec<DgaFeature>,
+}
+
+impl DgaScore {
+    /// The score as the Low/Medium/High level rules classify on.
+    pub fn level(&self) -> &'static str {
+        if self.score >= HIGH_SCORE {
+            "High"
+        } else if self.score >= MEDIUM_SCORE {
+            "Medium"
+        } else {
+            "Low"
+        }
+    }
+
+    /// Features in order of contribution, e.g.
+    /// "bigram likelihood -5.1 bits (+0.33), entropy 3.58 (+0.12)".
+    pub fn explanation(&self) -> String {
+        let mut features: Vec<&DgaFeature> = self.features.iter().filter(|f| f.contribution > 0.0).collect();
+        features.sort_by(|a, b| b.contribution.total_cmp(&a.contribution));
+        if features.is_empty() {
+            return format!("\"{}\" looks like a normal name", self.label);
+        }
+        features
+            .iter()
+            .map(|f| format!("{} {} (+{:.2})", f.name, format_value(f), f.contribution))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+pub struct DgaClassifier {
+    // Bigram counts and the counts of each bigram's first character
+    bigrams: HashMap<(char, char), f64>,
+    unigrams: HashMap<char, f64>,
+}
+
+impl DgaClassifier {
+    /// Trains the character model on newline-separated domains; `#` starts a
+    /// comment.
+    pub fn train(domains: &str) -> Self {
+        let mut classifier = DgaClassifier {
+            bigrams: HashMap::new(),
+            unigrams: HashMap::new(),
+        };
+        for line in domains.lines() {
+            let domain = line.split('#').next().unwrap_or("").trim().to_lowercase();
+            if domain.is_empty() {
+                continue;
+            }
+            let domain = DomainName::parse(&domain);
+            let labels = domain.subdomain.split('.').chain(domain.registrable_label());
+            for label in labels.filter(|l| !l.is_empty()) {
+                for pair in bigrams(label) {
+                    *classifier.bigrams.entry(pair).or_default() += 1.0;
+                    *classifier.unigrams.entry(pair.0).or_default() += 1.0;
+                }
+            }
+        }
+        classifier
+    }
+
+    pub fn score(&self, domain: &str) -> DgaScore {
+        let domain = DomainName::parse(domain);
+        let label = domain.registrable_label().unwrap_or(&domain.name).to_string();
+        let length = label.chars().count();
+
+        let mut features = Vec::new();
+        // Punycode IDN labels are not drawn from the model's alphabet
+        if length == 0 || label.starts_with("xn--") {
+            return DgaScore { label, score: 0.0, features };
+        }
+
+        let bits = self.bits_per_bigram(&label);
+        features.push(DgaFeature {
+            name: "bigram likelihood",
+            value: bits,
+            contribution: NGRAM_WEIGHT * scale(NGRAM_BENIGN_BITS - bits, NGRAM_BENIGN_BITS - NGRAM_RANDOM_BITS),
+        });
+
+        // The most entropy a label of this length can have, capped by the alphabet
+        let max_entropy = (length as f64).log2().min(ALPHABET_SIZE.log2());
+        let entropy = entropy(&label);
+        features.push(DgaFeature {
+            name: "entropy",
+            value: entropy,
+            contribution: ENTROPY_WEIGHT * if max_entropy > 0.0 { scale(entropy / max_entropy - 0.75, 0.25) } else { 0.0 },
+        });
+
+        let run = longest_consonant_run(&label);
+        features.push(DgaFeature {
+            name: "consonant run",
+            value: run as f64,
+            contribution: CONSONANT_WEIGHT * scale(run as f64 - 3.0, 3.0),
+        });
+
+        let digits = label.chars().filter(char::is_ascii_digit).count() as f64 / length as f64;
+        features.push(DgaFeature {
+            name: "digit ratio",
+            value: digits,
+            contribution: DIGIT_WEIGHT * scale(digits, 0.3),
+        });
+
+        features.push(DgaFeature {
+            name: "length",
+            value: length as f64,
+            contribution: LENGTH_WEIGHT * scale(length as f64 - 12.0, 12.0),
+        });
+
+        let damping = (length as f64 / SHORT_LABEL as f64).min(1.0);
+        for feature in &mut features {
+            feature.contribution *= damping;
+        }
+        let score = features.iter().map(|f| f.contribution).sum::<f64>().min(1.0);
+        DgaScore { label, score, features }
+    }
+
+    /// Average log2 probability of the label's bigrams, add-one smoothed.
+    fn bits_per_bigram(&self, label: &str) -> f64 {
+        let pairs = bigrams(label);
+        let total: f64 = pairs
+            .iter()
+            .map(|pair| {
+                let count = self.bigrams.get(pair).copied().unwrap_or(0.0);
+                let from = self.unigrams.get(&pair.0).copied().unwrap_or(0.0);
+                ((count + 1.0) / (from + ALPHABET_SIZE)).log2()
+            })
+            .sum();
+        total / pairs.len() as f64
+    }
+}
+
+/// The classifier trained on the embedded benign list.
+pub fn classifier() -> &'static DgaClassifier {
+    static CLASSIFIER: OnceLock<DgaClassifier> = OnceLock::new();
+    CLASSIFIER.get_or_init(|| DgaClassifier::train(BENIGN_DOMAINS))
+}
+
+pub fn entropy(s: &str) -> f64 {
+    let len = s.chars().count() as f64;
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in s.chars() {
+        *counts.entry(c).or_insert(0) += 1;
+    }
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn bigrams(label: &str) -> Vec<(char, char)> {
+    let chars: Vec<char> = std::iter::once(START).chain(label.chars()).chain(std::iter::once(END)).collect();
+    chars.windows(2).map(|w| (w[0], w[1])).collect()
+}
+
+fn longest_consonant_run(label: &str) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in label.chars() {
+        if c.is_ascii_alphabetic() && !"aeiouy".contains(c) {
+            run += 1;
+            longest = longest.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    longest
+}
+
+/// Maps `value` onto 0..1 over `range`.
+fn scale(value: f64, range: f64) -> f64 {
+    (value / range).clamp(0.0, 1.0)
+}
+
+fn format_value(feature: &DgaFeature) -> String {
+    match feature.name {
+        "bigram likelihood" => format!("{:.1} bits", feature.value),
+        "digit ratio" => format!("{:.0}%", feature.value * 100.0),
+        "consonant run" | "length" => format!("{}", feature.value),
+        _ => format!("{:.2}", feature.value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benign_names_score_low() {
+        for domain in ["google.com", "www.wikipedia.org", "news.bbc.co.uk", "stackoverflow.com", "mail.protonmail.ch", "github.io"] {
+            let score = classifier().score(domain);
+            assert_eq!(score.level(), "Low", "{} scored {:.2}: {}", domain, score.score, score.explanation());
+        }
+    }
+
+    #[test]
+    fn generated_names_score_high() {
+        for domain in ["xjw9kq2vbz7tplm.com", "qwxkzhrtpvbnmc.net", "a3f9c2e71b4d8e60.info", "www.kq7zxv0pwj3r.co.uk"] {
+            let score = classifier().score(domain);
+            assert_eq!(score.level(), "High", "{} scored {:.2}: {}", domain, score.score, score.explanation());
+        }
+    }
+
+    #[test]
+    fn scores_the_registrable_label() {
+        let score = classifier().score("www.kq7zxv0pwj3r.co.uk");
+        assert_eq!(score.label, "kq7zxv0pwj3r");
+        assert!(score.score <= 1.0);
+        assert_eq!(classifier().score("KQ7ZXV0PWJ3R.co.uk").score, score.score);
+    }
+
+    #[test]
+    fn short_and_punycode_labels_are_damped() {
+        assert!(classifier().score("x7q.com").score < MEDIUM_SCORE);
+        let punycode = classifier().score("xn--bcher-kva.de");
+        assert_eq!(punycode.score, 0.0);
+        assert!(punycode.features.is_empty());
+        assert_eq!(classifier().score("").score, 0.0);
+    }
+
+    #[test]
+    fn explains_contributions_largest_first() {
+        let score = classifier().score("xjw9kq2vbz7tplm.com");
+        let explanation = score.explanation();
+        assert!(explanation.starts_with("bigram likelihood"), "{}", explanation);
+        assert!(explanation.contains("bits (+"));
+        assert_eq!(classifier().score("google.com").explanation(), "\"google\" looks like a normal name");
+    }
+
+    #[test]
+    fn feature_measurements() {
+        assert_eq!(entropy("aaaa"), 0.0);
+        assert_eq!(entropy("abab"), 1.0);
+        assert_eq!(longest_consonant_run("strengths"), 5);
+        assert_eq!(longest_consonant_run("rhythm"), 3);
+        assert_eq!(bigrams("ab"), vec![(START, 'a'), ('a', 'b'), ('b', END)]);
+
+        // A model trained on one name prefers it over a random one
+        let model = DgaClassifier::train("# comment\nexample.com\n\n");
+        assert!(model.bits_per_bigram("example") > model.bits_per_bigram("qzxvkj"));
+    }
+}
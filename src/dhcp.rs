@@ -0,0 +1,493 @@
+// DHCP server and starvation monitoring.
+//
+// Every DHCP server message (OFFER/ACK/NAK, or v6 ADVERTISE/REPLY) is
+// checked against the servers the network is expected to have, keyed by
+// server identifier (the option 54 address for v4, the server DUID for v6)
+// and the Ethernet address the messages come from. Expected servers are
+// read from DHCP_TRUSTED_FILE (default dhcp_trusted.txt), one "SERVER_ID
+// [MAC]" per line. Without that file the servers answering in the first
+// DHCP_LEARNING_SECS after the first one is seen are learned, persisted in
+// the dhcp_servers collection, and every other one is flagged.
+//
+// Many DISCOVERs (or SOLICITs) from distinct client MACs in a short window
+// are flagged as starvation: tools exhausting a pool make up a new MAC for
+// each, usually randomized or from no known vendor, and often send them all
+// from their own Ethernet address.
+
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::sniff::dhcp::DhcpMessage;
+use crate::sniff::oui;
+use crate::sniff::NetworkEvent;
+use crate::stream::{split_endpoint, StreamDetector};
+use futures::StreamExt;
+use mongodb::{bson::doc, Collection, Database};
+use serde::{Deserialize, Serialize};
+use sniff::llm::verdict::Severity;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use tokio::sync::mpsc;
+
+const DEFAULT_TRUSTED_FILE: &str = "dhcp_trusted.txt";
+const DEFAULT_LEARNING_SECS: f64 = 600.0;
+const STARVATION_WINDOW: f64 = 60.0;
+const STARVATION_THRESHOLD: usize = 50;
+// Alerts for the same key are not repeated more often than this
+const ALERT_COOLDOWN: f64 = 900.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DhcpServer {
+    pub server_id: String,
+    pub mac: String,
+    #[serde(default)]
+    pub address: String,
+    pub first_seen: f64,
+    pub last_seen: f64,
+}
+
+pub struct DhcpMonitor {
+    // Server identifier -> MACs its messages may come from (none: any)
+    trusted: HashMap<String, Vec<String>>,
+    // Server identifier -> learned MACs, when nothing is trusted
+    learned: HashMap<String, Vec<DhcpServer>>,
+    learning_secs: f64,
+    learning_started: Option<f64>,
+    // Client MAC -> (last DISCOVER, Ethernet source)
+    discovers: HashMap<String, (f64, String)>,
+    last_alert: HashMap<String, f64>,
+    writer: Option<mpsc::UnboundedSender<DhcpServer>>,
+}
+
+impl DhcpMonitor {
+    pub fn new(servers: Vec<DhcpServer>, trusted: HashMap<String, Vec<String>>, learning_secs: f64) -> Self {
+        let mut learned: HashMap<String, Vec<DhcpServer>> = HashMap::new();
+        for server in servers {
+            learned.entry(server.server_id.clone()).or_default().push(server);
+        }
+        // Servers learned on an earlier run mean learning is over
+        let learning_started = (!learned.is_empty()).then_some(f64::MIN);
+
+        DhcpMonitor {
+            trusted,
+            learned,
+            learning_secs,
+            learning_started,
+            discovers: HashMap::new(),
+            last_alert: HashMap::new(),
+            writer: None,
+        }
+    }
+
+    /// Loads learned servers and the trusted list, and starts a task that
+    /// writes newly learned servers to `dhcp_servers`.
+    pub async fn load(db: &Database) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let collection: Collection<DhcpServer> = db.collection("dhcp_servers");
+        let mut cursor = collection.find(doc! {}).await?;
+        let mut servers = Vec::new();
+        while let Some(server) = cursor.next().await {
+            servers.push(server?);
+        }
+
+        let path = env::var("DHCP_TRUSTED_FILE").unwrap_or_else(|_| DEFAULT_TRUSTED_FILE.to_string());
+        let trusted = match fs::read_to_string(&path) {
+            Ok(contents) => parse_trusted(&contents),
+            Err(_) => HashMap::new(),
+        };
+        let learning_secs = env::var("DHCP_LEARNING_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_LEARNING_SECS);
+        println!("Loaded {} learned DHCP servers and {} trusted entries", servers.len(), trusted.len());
+
+        let mut monitor = Self::new(servers, trusted, learning_secs);
+        monitor.writer = Some(spawn_server_writer(collection));
+        Ok(monitor)
+    }
+
+    fn check_server(&mut self, event: &NetworkEvent, dhcp: &DhcpMessage, detections: &mut Vec<Detection>) {
+        let now = event.timestamp;
+        let Some(mac) = event.source_mac.as_deref() else { return };
+        // v4 NAKs may omit the identifier; the sender's address stands in
+        let server_id = match &dhcp.server_id {
+            Some(server_id) => server_id.clone(),
+            None => match split_endpoint(&event.source) {
+                Some((ip, _)) => normalize_server_id(ip),
+                None => normalize_server_id(&event.source),
+            },
+        };
+        let offered = dhcp.assigned_ip.map(|ip| format!(", offering {}", ip)).unwrap_or_default();
+
+        if !self.trusted.is_empty() {
+            match self.trusted.get(&server_id) {
+                None => self.alert(
+                    detections,
+                    now,
+                    Severity::Critical,
+                    0.9,
+                    event,
+                    mac,
+                    format!(
+                        "DHCP {} from untrusted server {} ({}){}",
+                        dhcp.message_name(), server_id, oui::describe(mac), offered
+                    ),
+                ),
+                Some(macs) if !macs.is_empty() && !macs.iter().any(|m| m.eq_ignore_ascii_case(mac)) => self.alert(
+                    detections,
+                    now,
+                    Severity::High,
+                    0.8,
+                    event,
+                    mac,
+                    format!(
+                        "DHCP {} with trusted server identifier {} sent from unexpected MAC {}{}",
+                        dhcp.message_name(), server_id, oui::describe(mac), offered
+                    ),
+                ),
+                Some(_) => {}
+            }
+            return;
+        }
+
+        let started = *self.learning_started.get_or_insert(now);
+        let known = self.learned.get(&server_id);
+        let known_id = known.is_some();
+        let known_mac = known.is_some_and(|servers| servers.iter().any(|s| s.mac.eq_ignore_ascii_case(mac)));
+        if now - started <= self.learning_secs {
+            if !known_mac {
+                let server = DhcpServer {
+                    server_id: server_id.clone(),
+                    mac: mac.to_string(),
+                    address: event.source.clone(),
+                    first_seen: now,
+                    last_seen: now,
+                };
+                if let Some(writer) = &self.writer {
+                    let _ = writer.send(server.clone());
+                }
+                println!("Learned DHCP server {} at {}", server_id, oui::describe(mac));
+                self.learned.entry(server_id).or_default().push(server);
+            }
+            return;
+        }
+
+        if !known_id {
+            self.alert(
+                detections,
+                now,
+                Severity::High,
+                0.7,
+                event,
+                mac,
+                format!(
+                    "DHCP {} from unknown server {} ({}){}",
+                    dhcp.message_name(), server_id, oui::describe(mac), offered
+                ),
+            );
+        } else if !known_mac {
+            self.alert(
+                detections,
+                now,
+                Severity::High,
+                0.65,
+                event,
+                mac,
+                format!(
+                    "DHCP {} for known server {} sent from unknown MAC {}{}",
+                    dhcp.message_name(), server_id, oui::describe(mac), offered
+                ),
+            );
+        }
+    }
+
+    fn check_starvation(&mut self, event: &NetworkEvent, dhcp: &DhcpMessage, detections: &mut Vec<Detection>) {
+        let now = event.timestamp;
+        let (Some(client), Some(sender)) = (dhcp.client_mac.as_deref(), event.source_mac.as_deref()) else { return };
+        self.discovers.insert(client.to_string(), (now, sender.to_string()));
+        self.discovers.retain(|_, (seen, _)| now - *seen <= STARVATION_WINDOW);
+        let clients = self.discovers.len();
+        if clients < STARVATION_THRESHOLD {
+            return;
+        }
+
+        let mut senders: HashMap<&str, usize> = HashMap::new();
+        let mut spoofed = 0;
+        let mut anonymous = 0;
+        for (client, (_, sender)) in &self.discovers {
+            *senders.entry(sender.as_str()).or_default() += 1;
+            if !client.eq_ignore_ascii_case(sender) {
+                spoofed += 1;
+            }
+            if oui::is_randomized(client) || oui::vendor(client).is_none() {
+                anonymous += 1;
+            }
+        }
+        let top_sender = senders
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(sender, _)| sender.to_string())
+            .unwrap_or_default();
+        let spoofed_share = spoofed as f64 / clients as f64;
+        let anonymous_share = anonymous as f64 / clients as f64;
+        // Half the network rebooting after an outage also looks like many
+        // DISCOVERs; made-up and mismatched MACs are what set a tool apart
+        let mut confidence = 0.5;
+        if anonymous_share > 0.5 {
+            confidence += 0.2;
+        }
+        if spoofed_share > 0.5 {
+            confidence += 0.2;
+        }
+
+        let key = format!("DHCP Starvation|{}", top_sender);
+        if self.last_alert.get(&key).is_some_and(|t| now - t < ALERT_COOLDOWN) {
+            return;
+        }
+        self.last_alert.insert(key, now);
+
+        let mut sample: Vec<String> = self.discovers.keys().cloned().collect();
+        sample.sort();
+        sample.truncate(10);
+        let details = format!(
+            "{}s from {} distinct client MACs in {} seconds ({:.0}% randomized or unregistered, {:.0}% not matching the Ethernet sender, mostly sent by {})",
+            dhcp.message_name(), clients, STARVATION_WINDOW, anonymous_share * 100.0, spoofed_share * 100.0, oui::describe(&top_sender)
+        );
+        detections.push(Detection {
+            activity: SuspiciousActivity {
+                activity_type: "DHCP Starvation".to_string(),
+                source: top_sender.clone(),
+                details,
+                indicator: top_sender.clone(),
+                magnitude: clients as f64,
+                severity: Severity::High,
+                score: 0.0,
+                confidence,
+                evidence: Evidence {
+                    count: Some(clients as f64),
+                    peers: sample,
+                    metrics: BTreeMap::from([
+                        ("distinct_clients".to_string(), clients as f64),
+                        ("unregistered_share".to_string(), anonymous_share),
+                        ("spoofed_share".to_string(), spoofed_share),
+                    ]),
+                    ..Evidence::default()
+                },
+                rule_id: "dhcp_monitor".to_string(),
+                timestamp: now,
+            },
+            actions: Vec::new(),
+            vars: HashMap::from([("mac".to_string(), top_sender)]),
+        });
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn alert(
+        &mut self,
+        detections: &mut Vec<Detection>,
+        now: f64,
+        severity: Severity,
+        confidence: f64,
+        event: &NetworkEvent,
+        mac: &str,
+        details: String,
+    ) {
+        let key = format!("Rogue DHCP Server|{}|{}", event.source, mac);
+        if self.last_alert.get(&key).is_some_and(|t| now - t < ALERT_COOLDOWN) {
+            return;
+        }
+        self.last_alert.insert(key, now);
+
+        let mut vars = HashMap::from([("mac".to_string(), mac.to_string())]);
+        if let Some(vendor) = oui::vendor(mac) {
+            vars.insert("vendor".to_string(), vendor.to_string());
+        }
+        detections.push(Detection {
+            activity: SuspiciousActivity {
+                activity_type: "Rogue DHCP Server".to_string(),
+                source: event.source.clone(),
+                details,
+                indicator: mac.to_string(),
+                magnitude: 1.0,
+                severity,
+                score: 0.0,
+                confidence,
+                evidence: Evidence {
+                    count: Some(1.0),
+                    peers: vec![event.source.clone(), mac.to_string()],
+                    ..Evidence::default()
+                },
+                rule_id: "dhcp_monitor".to_string(),
+                timestamp: now,
+            },
+            actions: Vec::new(),
+            vars,
+        });
+    }
+}
+
+impl StreamDetector for DhcpMonitor {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        let Some(dhcp) = &event.dhcp else { return };
+        if dhcp.is_from_server() {
+            self.check_server(event, dhcp, detections);
+        } else if dhcp.is_discover() {
+            self.check_starvation(event, dhcp, detections);
+        }
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        self.discovers.retain(|_, (seen, _)| now - *seen <= STARVATION_WINDOW);
+        self.last_alert.retain(|_, t| now - *t < ALERT_COOLDOWN);
+    }
+}
+
+fn parse_trusted(contents: &str) -> HashMap<String, Vec<String>> {
+    let mut trusted: HashMap<String, Vec<String>> = HashMap::new();
+    for line in contents.lines().map(|line| line.split('#').next().unwrap_or("").trim()) {
+        let mut parts = line.split_whitespace();
+        let Some(server_id) = parts.next() else { continue };
+        let macs = parts.map(|mac| mac.to_uppercase().replace('-', ":"));
+        trusted.entry(normalize_server_id(server_id)).or_default().extend(macs);
+    }
+    trusted
+}
+
+/// Addresses as written; DUIDs as the lowercase hex the decoder produces.
+fn normalize_server_id(server_id: &str) -> String {
+    match server_id.parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => server_id.to_lowercase().replace([':', '-'], ""),
+    }
+}
+
+fn spawn_server_writer(collection: Collection<DhcpServer>) -> mpsc::UnboundedSender<DhcpServer> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<DhcpServer>();
+    tokio::spawn(async move {
+        while let Some(server) = rx.recv().await {
+            let result = collection
+                .update_one(
+                    doc! { "server_id": &server.server_id, "mac": &server.mac },
+                    doc! {
+                        "$min": { "first_seen": server.first_seen },
+                        "$max": { "last_seen": server.last_seen },
+                        "$set": { "address": &server.address },
+                    },
+                )
+                .upsert(true)
+                .await;
+            if let Err(e) = result {
+                eprintln!("Error storing DHCP server: {}", e);
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniff::dhcp::{ACK, DISCOVER, NAK, OFFER};
+
+    const SERVER_MAC: &str = "AA:BB:CC:DD:EE:01";
+
+    fn message(message_type: u8, server_id: Option<&str>, client_mac: &str) -> DhcpMessage {
+        DhcpMessage {
+            version: 4,
+            message_type,
+            transaction_id: 1,
+            client_mac: Some(client_mac.to_string()),
+            client_ip: None,
+            assigned_ip: None,
+            requested_ip: None,
+            hostname: None,
+            vendor_class: None,
+            server_id: server_id.map(str::to_string),
+        }
+    }
+
+    fn event(source: &str, mac: &str, dhcp: DhcpMessage, timestamp: f64) -> NetworkEvent {
+        NetworkEvent {
+            protocol: "UDP".to_string(),
+            source: source.to_string(),
+            destination: "255.255.255.255:68".to_string(),
+            payload_size: 300,
+            timestamp,
+            source_mac: Some(mac.to_string()),
+            arp: None,
+            dns: None,
+            mdns: None,
+            netbios: None,
+            dhcp: Some(dhcp),
+            tcp_syn: None,
+        }
+    }
+
+    fn observe(monitor: &mut DhcpMonitor, event: NetworkEvent) -> Vec<Detection> {
+        let mut detections = Vec::new();
+        monitor.observe(&event, &mut detections);
+        detections
+    }
+
+    fn trusted_monitor() -> DhcpMonitor {
+        DhcpMonitor::new(Vec::new(), parse_trusted("192.168.1.1  aa-bb-cc-dd-ee-01  # router\n"), DEFAULT_LEARNING_SECS)
+    }
+
+    #[test]
+    fn trusted_server_without_identifier_is_recognized() {
+        let mut monitor = trusted_monitor();
+        let nak = event("192.168.1.1:67", SERVER_MAC, message(NAK, None, "02:00:00:00:00:10"), 1.0);
+        assert!(observe(&mut monitor, nak).is_empty());
+
+        let ack = event("192.168.1.1:67", SERVER_MAC, message(ACK, Some("192.168.1.1"), "02:00:00:00:00:10"), 2.0);
+        assert!(observe(&mut monitor, ack).is_empty());
+    }
+
+    #[test]
+    fn flags_untrusted_servers_and_macs() {
+        let mut monitor = trusted_monitor();
+        let rogue = event("192.168.1.66:67", "AA:BB:CC:DD:EE:66", message(OFFER, None, "02:00:00:00:00:10"), 1.0);
+        let detections = observe(&mut monitor, rogue);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].activity.severity, Severity::Critical);
+        assert!(detections[0].activity.details.contains("untrusted server 192.168.1.66 "));
+
+        let spoofed = event("192.168.1.1:67", "AA:BB:CC:DD:EE:66", message(OFFER, Some("192.168.1.1"), "02:00:00:00:00:10"), 2.0);
+        let detections = observe(&mut monitor, spoofed);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].activity.severity, Severity::High);
+    }
+
+    #[test]
+    fn learns_servers_by_bare_address() {
+        let mut monitor = DhcpMonitor::new(Vec::new(), HashMap::new(), 600.0);
+        let offer = event("192.168.1.1:67", SERVER_MAC, message(OFFER, Some("192.168.1.1"), "02:00:00:00:00:10"), 0.0);
+        assert!(observe(&mut monitor, offer).is_empty());
+        let nak = event("192.168.1.1:67", SERVER_MAC, message(NAK, None, "02:00:00:00:00:10"), 10.0);
+        assert!(observe(&mut monitor, nak).is_empty());
+        assert_eq!(monitor.learned.keys().collect::<Vec<_>>(), ["192.168.1.1"]);
+
+        // After learning, the known server is quiet and a newcomer is not
+        let nak = event("192.168.1.1:67", SERVER_MAC, message(NAK, None, "02:00:00:00:00:10"), 1000.0);
+        assert!(observe(&mut monitor, nak).is_empty());
+        let newcomer = event("192.168.1.9:67", "AA:BB:CC:DD:EE:09", message(OFFER, None, "02:00:00:00:00:10"), 1001.0);
+        assert_eq!(observe(&mut monitor, newcomer).len(), 1);
+    }
+
+    #[test]
+    fn flags_starvation() {
+        let mut monitor = DhcpMonitor::new(Vec::new(), HashMap::new(), 600.0);
+        let mut detections = Vec::new();
+        for i in 0..STARVATION_THRESHOLD {
+            let client = format!("06:00:00:00:{:02X}:{:02X}", i / 256, i % 256);
+            let discover = event("0.0.0.0:68", "AA:BB:CC:DD:EE:99", message(DISCOVER, None, &client), i as f64 * 0.1);
+            detections.extend(observe(&mut monitor, discover));
+        }
+        assert_eq!(detections.len(), 1);
+        let activity = &detections[0].activity;
+        assert_eq!(activity.activity_type, "DHCP Starvation");
+        assert_eq!(activity.source, "AA:BB:CC:DD:EE:99");
+        // Randomized clients that all differ from the Ethernet sender
+        assert!((activity.confidence - 0.9).abs() < 1e-9);
+    }
+}
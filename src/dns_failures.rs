@@ -0,0 +1,268 @@
+// NXDOMAIN storm and DNS failure-rate detection.
+//
+// A host running a DGA walks through generated names until one resolves, so
+// it produces bursts of NXDOMAIN answers for many distinct names. Per client
+// we count responses by rcode over a window and alert when NXDOMAIN (or
+// SERVFAIL) answers are both numerous and most of what the host gets back.
+// Alerts carry sample failed names and their mean DGA score.
+//
+// After a storm the client is watched for FOLLOW_UP_SECS: a name that then
+// resolves and itself looks generated is probably the live C2 domain and is
+// reported as "DGA Domain Resolved".
+
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::dga;
+use crate::sniff::dns::{self, DnsMessage};
+use crate::sniff::NetworkEvent;
+use crate::stream::{split_endpoint, StreamDetector};
+use sniff::llm::verdict::Severity;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const WINDOW_SECS: f64 = 300.0;
+const MIN_FAILURES: u64 = 30;
+const MIN_FAILURE_RATIO: f64 = 0.5;
+const MIN_DISTINCT_FAILED: usize = 20;
+const HIGH_DISTINCT_FAILED: usize = 100;
+const MAX_TRACKED_NAMES: usize = 1000;
+const SAMPLE_NAMES: usize = 5;
+const FOLLOW_UP_SECS: f64 = 600.0;
+const ALERT_COOLDOWN: f64 = 900.0;
+const MAX_CLIENTS: usize = 20_000;
+
+#[derive(Default)]
+struct ClientWindow {
+    start: f64,
+    responses: u64,
+    nxdomain: u64,
+    servfail: u64,
+    failed_names: HashSet<String>,
+    samples: Vec<String>,
+}
+
+impl ClientWindow {
+    fn new(start: f64) -> Self {
+        ClientWindow { start, ..Default::default() }
+    }
+}
+
+/// A client that recently had a storm, watched for a generated name resolving.
+struct FollowUp {
+    until: f64,
+    failures: u64,
+    failed_names: HashSet<String>,
+}
+
+#[derive(Default)]
+pub struct DnsFailureDetector {
+    windows: HashMap<String, ClientWindow>,
+    follow_ups: HashMap<String, FollowUp>,
+    last_alert: HashMap<String, f64>,
+}
+
+impl DnsFailureDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn observe_response(&mut self, event: &NetworkEvent, message: &DnsMessage, detections: &mut Vec<Detection>) {
+        let name = match message.query_name() {
+            Some(name) if !name.ends_with(".arpa") => name,
+            _ => return,
+        };
+        // Responses go back to the client that asked
+        let client = match split_endpoint(&event.destination) {
+            Some((ip, _)) => ip.to_string(),
+            None => return,
+        };
+        if !self.windows.contains_key(&client) && self.windows.len() >= MAX_CLIENTS {
+            return;
+        }
+        let now = event.timestamp;
+
+        if message.rcode == dns::RCODE_NOERROR && message.resolved_ip().is_some() {
+            self.check_follow_up(&client, name, message, now, detections);
+        }
+
+        let window = self.windows.entry(client.clone()).or_insert_with(|| ClientWindow::new(now));
+        if now - window.start > WINDOW_SECS {
+            *window = ClientWindow::new(now);
+        }
+        window.responses += 1;
+        match message.rcode {
+            dns::RCODE_NXDOMAIN => window.nxdomain += 1,
+            dns::RCODE_SERVFAIL => window.servfail += 1,
+            _ => return,
+        }
+        if window.failed_names.len() < MAX_TRACKED_NAMES && window.failed_names.insert(name.to_string()) && window.samples.len() < SAMPLE_NAMES {
+            window.samples.push(name.to_string());
+        }
+
+        let alert_key = format!("storm|{}", client);
+        if self.last_alert.get(&alert_key).is_some_and(|t| now - t < ALERT_COOLDOWN) {
+            return;
+        }
+        if let Some(detection) = evaluate(&client, window, now) {
+            self.follow_ups.insert(client.clone(), FollowUp {
+                until: now + FOLLOW_UP_SECS,
+                failures: window.nxdomain + window.servfail,
+                failed_names: window.failed_names.clone(),
+            });
+            self.last_alert.insert(alert_key, now);
+            detections.push(detection);
+        }
+    }
+
+    fn check_follow_up(&mut self, client: &str, name: &str, message: &DnsMessage, now: f64, detections: &mut Vec<Detection>) {
+        let follow_up = match self.follow_ups.get(client) {
+            Some(follow_up) if now <= follow_up.until && !follow_up.failed_names.contains(name) => follow_up,
+            _ => return,
+        };
+        let score = dga::classifier().score(name);
+        if score.level() == "Low" {
+            return;
+        }
+
+        let alert_key = format!("resolved|{}|{}", client, name);
+        if self.last_alert.contains_key(&alert_key) {
+            return;
+        }
+        self.last_alert.insert(alert_key, now);
+
+        let resolved_ip = message.resolved_ip().unwrap_or_default().to_string();
+        detections.push(Detection {
+            activity: SuspiciousActivity {
+                activity_type: "DGA Domain Resolved".to_string(),
+                source: client.to_string(),
+                details: format!(
+                    "{} resolved to {} after {} failed lookups; DGA score {:.2}: {}",
+                    name,
+                    resolved_ip,
+                    follow_up.failures,
+                    score.score,
+                    score.explanation()
+                ),
+                indicator: name.to_string(),
+                magnitude: score.score,
+                severity: Severity::High,
+                score: 0.0,
+                confidence: 0.7,
+                evidence: Evidence {
+                    count: Some(follow_up.failures as f64),
+                    peers: vec![resolved_ip.clone()],
+                    domains: vec![name.to_string()],
+                    metrics: BTreeMap::from([("dga_score".to_string(), score.score)]),
+                    ..Evidence::default()
+                },
+                rule_id: "dns_failures".to_string(),
+                timestamp: now,
+            },
+            actions: Vec::new(),
+            vars: HashMap::from([("resolved_ip".to_string(), resolved_ip)]),
+        });
+    }
+}
+
+impl StreamDetector for DnsFailureDetector {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        match &event.dns {
+            Some(message) if message.is_response => self.observe_response(event, message, detections),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        self.windows.retain(|_, window| now - window.start <= WINDOW_SECS);
+        self.follow_ups.retain(|_, follow_up| now <= follow_up.until);
+        self.last_alert.retain(|_, t| now - *t < ALERT_COOLDOWN.max(FOLLOW_UP_SECS));
+    }
+}
+
+fn evaluate(client: &str, window: &ClientWindow, now: f64) -> Option<Detection> {
+    let (activity_type, rcode, failures) = if window.nxdomain >= window.servfail {
+        ("NXDOMAIN Storm", "NXDOMAIN", window.nxdomain)
+    } else {
+        ("DNS SERVFAIL Storm", "SERVFAIL", window.servfail)
+    };
+    let ratio = failures as f64 / window.responses as f64;
+    let distinct = window.failed_names.len();
+    if failures < MIN_FAILURES || ratio < MIN_FAILURE_RATIO || distinct < MIN_DISTINCT_FAILED {
+        return None;
+    }
+
+    let classifier = dga::classifier();
+    let mean_dga = window.failed_names.iter().map(|n| classifier.score(n).score).sum::<f64>() / distinct as f64;
+    let severity = if distinct >= HIGH_DISTINCT_FAILED || mean_dga >= 0.45 { Severity::High } else { Severity::Medium };
+
+    Some(Detection {
+        activity: SuspiciousActivity {
+            activity_type: activity_type.to_string(),
+            source: client.to_string(),
+            details: format!(
+                "{} of {} responses failed ({:.0}%) in {} seconds for {} distinct names, mean DGA score {:.2}; e.g. {}",
+                failures,
+                window.responses,
+                ratio * 100.0,
+                WINDOW_SECS,
+                distinct,
+                mean_dga,
+                window.samples.join(", ")
+            ),
+            // The failed names change every window; the rcode keeps one
+            // fingerprint per client storm
+            indicator: rcode.to_string(),
+            magnitude: failures as f64,
+            severity,
+            score: 0.0,
+            confidence: 0.6,
+            evidence: Evidence {
+                count: Some(failures as f64),
+                domains: window.samples.clone(),
+                metrics: BTreeMap::from([
+                    ("failure_ratio".to_string(), ratio),
+                    ("distinct_names".to_string(), distinct as f64),
+                    ("mean_dga_score".to_string(), mean_dga),
+                ]),
+                ..Evidence::default()
+            },
+            rule_id: "dns_failures".to_string(),
+            timestamp: now,
+        },
+        actions: Vec::new(),
+        vars: HashMap::from([("sample_names".to_string(), window.samples.join(", "))]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storm(names: &[&str]) -> ClientWindow {
+        let mut window = ClientWindow::new(0.0);
+        for name in names {
+            window.responses += 1;
+            window.nxdomain += 1;
+            window.failed_names.insert(name.to_string());
+            if window.samples.len() < SAMPLE_NAMES {
+                window.samples.push(name.to_string());
+            }
+        }
+        window
+    }
+
+    #[test]
+    fn storm_indicator_does_not_depend_on_names() {
+        let first: Vec<String> = (0..40).map(|i| format!("a{}x.example", i)).collect();
+        let second: Vec<String> = (0..40).map(|i| format!("b{}y.example", i)).collect();
+        let a = evaluate("10.0.0.5", &storm(&first.iter().map(String::as_str).collect::<Vec<_>>()), 10.0).unwrap();
+        let b = evaluate("10.0.0.5", &storm(&second.iter().map(String::as_str).collect::<Vec<_>>()), 20.0).unwrap();
+
+        assert_eq!(a.activity.indicator, "NXDOMAIN");
+        assert_eq!(a.activity.fingerprint(), b.activity.fingerprint());
+        assert_eq!(a.activity.evidence.domains, first[..SAMPLE_NAMES]);
+    }
+
+    #[test]
+    fn few_failures_do_not_alert() {
+        assert!(evaluate("10.0.0.5", &storm(&["a.example", "b.example"]), 10.0).is_none());
+    }
+}
@@ -0,0 +1,304 @@
+// DNS tunneling and exfiltration detection.
+//
+// Tunnels encode data in query names under a domain the attacker controls,
+// so per parent (registrable) domain we watch, over a window of decoded DNS
+// traffic:
+//   - how many distinct subdomains are queried
+//   - the length and entropy of the subdomain labels
+//   - the share of TXT and NULL queries, which carry the return channel
+//   - query name bytes, and the ratio of queries to responses
+// A domain alerts when it has many unique subdomains and enough of the other
+// signals agree. Reverse lookups are ignored; DNS_TUNNEL_ALLOWLIST takes a
+// comma-separated list of parent domains (CDNs, AV reputation lookups) to skip.
+
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::dga::entropy;
+use crate::sniff::dns::{self, DnsMessage};
+use crate::sniff::domain::DomainName;
+use crate::sniff::NetworkEvent;
+use crate::stream::{split_endpoint, HyperLogLog, StreamDetector};
+use sniff::llm::verdict::Severity;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+
+const WINDOW_SECS: f64 = 300.0;
+const MIN_QUERIES: u64 = 20;
+const UNIQUE_SUBDOMAINS_THRESHOLD: f64 = 50.0;
+const LONG_LABEL_THRESHOLD: f64 = 24.0;
+const ENTROPY_THRESHOLD: f64 = 3.5;
+const TXT_NULL_SHARE_THRESHOLD: f64 = 0.25;
+const QUERY_BYTES_THRESHOLD: u64 = 8192;
+const UNANSWERED_RATIO_THRESHOLD: f64 = 2.0;
+// Signals (besides unique subdomains) that must agree before alerting
+const MIN_SIGNALS: usize = 2;
+const HIGH_SIGNALS: usize = 4;
+const ALERT_COOLDOWN: f64 = 900.0;
+// Base32, the usual tunnel encoding, carries 5 bits per character
+const BITS_PER_ENCODED_CHAR: f64 = 5.0;
+const MAX_DOMAINS: usize = 20_000;
+
+#[derive(Default)]
+struct DomainWindow {
+    start: f64,
+    queries: u64,
+    responses: u64,
+    subdomains: HyperLogLog,
+    subdomain_chars: u64,
+    longest_label_sum: u64,
+    entropy_sum: f64,
+    txt_null_queries: u64,
+    query_bytes: u64,
+    clients: HashMap<String, u64>,
+}
+
+impl DomainWindow {
+    fn new(start: f64) -> Self {
+        DomainWindow { start, ..Default::default() }
+    }
+}
+
+pub struct DnsTunnelDetector {
+    windows: HashMap<String, DomainWindow>,
+    last_alert: HashMap<String, f64>,
+    allowlist: HashSet<String>,
+}
+
+impl DnsTunnelDetector {
+    pub fn new(allowlist: HashSet<String>) -> Self {
+        DnsTunnelDetector {
+            windows: HashMap::new(),
+            last_alert: HashMap::new(),
+            allowlist,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let allowlist = env::var("DNS_TUNNEL_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|d| d.trim().trim_end_matches('.').to_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+        Self::new(allowlist)
+    }
+
+    fn observe_message(&mut self, event: &NetworkEvent, message: &DnsMessage, detections: &mut Vec<Detection>) {
+        let name = match message.query_name() {
+            Some(name) if !name.ends_with(".arpa") => name,
+            _ => return,
+        };
+        let domain = DomainName::parse(name);
+        let (parent, subdomain) = match &domain.registrable {
+            Some(parent) => (parent.as_str(), domain.subdomain.as_str()),
+            None => return,
+        };
+        if self.allowlist.contains(parent) {
+            return;
+        }
+        if !self.windows.contains_key(parent) && self.windows.len() >= MAX_DOMAINS {
+            return;
+        }
+
+        let now = event.timestamp;
+        let window = self.windows.entry(parent.to_string()).or_insert_with(|| DomainWindow::new(now));
+        if now - window.start > WINDOW_SECS {
+            *window = DomainWindow::new(now);
+        }
+
+        if message.is_response {
+            window.responses += 1;
+            return;
+        }
+
+        window.queries += 1;
+        window.query_bytes += name.len() as u64;
+        if let Some((client, _)) = split_endpoint(&event.source) {
+            *window.clients.entry(client.to_string()).or_default() += 1;
+        }
+        if matches!(message.query_type(), Some(dns::TYPE_TXT) | Some(dns::TYPE_NULL)) {
+            window.txt_null_queries += 1;
+        }
+        if !subdomain.is_empty() {
+            window.subdomains.insert(subdomain);
+            window.subdomain_chars += subdomain.chars().filter(|c| *c != '.').count() as u64;
+            window.longest_label_sum += subdomain.split('.').map(str::len).max().unwrap_or(0) as u64;
+            window.entropy_sum += entropy(subdomain);
+        }
+
+        if window.queries < MIN_QUERIES || self.last_alert.get(parent).is_some_and(|t| now - t < ALERT_COOLDOWN) {
+            return;
+        }
+        if let Some(detection) = evaluate(parent, window, now) {
+            self.last_alert.insert(parent.to_string(), now);
+            detections.push(detection);
+        }
+    }
+}
+
+impl StreamDetector for DnsTunnelDetector {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        if let Some(message) = &event.dns {
+            self.observe_message(event, message, detections);
+        }
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        self.windows.retain(|_, window| now - window.start <= WINDOW_SECS);
+        self.last_alert.retain(|_, t| now - *t < ALERT_COOLDOWN);
+    }
+}
+
+fn evaluate(parent: &str, window: &DomainWindow, now: f64) -> Option<Detection> {
+    let unique = window.subdomains.estimate();
+    if unique < UNIQUE_SUBDOMAINS_THRESHOLD {
+        return None;
+    }
+
+    let queries = window.queries as f64;
+    let avg_label = window.longest_label_sum as f64 / queries;
+    let avg_entropy = window.entropy_sum / queries;
+    let txt_null_share = window.txt_null_queries as f64 / queries;
+    let unanswered_ratio = queries / (window.responses.max(1) as f64);
+
+    let mut signals = Vec::new();
+    if avg_label >= LONG_LABEL_THRESHOLD {
+        signals.push(format!("average longest label {:.0} chars", avg_label));
+    }
+    if avg_entropy >= ENTROPY_THRESHOLD {
+        signals.push(format!("subdomain entropy {:.2}", avg_entropy));
+    }
+    if txt_null_share >= TXT_NULL_SHARE_THRESHOLD {
+        signals.push(format!("{:.0}% TXT/NULL queries", txt_null_share * 100.0));
+    }
+    if window.query_bytes >= QUERY_BYTES_THRESHOLD {
+        signals.push(format!("{} bytes of query names", window.query_bytes));
+    }
+    if unanswered_ratio >= UNANSWERED_RATIO_THRESHOLD {
+        signals.push(format!("{:.1} queries per response", unanswered_ratio));
+    }
+    if signals.len() < MIN_SIGNALS {
+        return None;
+    }
+
+    // Only distinct names carry new data; repeats are retries
+    let distinct_share = (unique / queries).min(1.0);
+    let exfiltrated = (window.subdomain_chars as f64 * distinct_share * BITS_PER_ENCODED_CHAR / 8.0).round();
+    let client = window
+        .clients
+        .iter()
+        .max_by_key(|(_, count)| **count)
+        .map(|(client, _)| client.clone())
+        .unwrap_or_default();
+    let severity = if signals.len() >= HIGH_SIGNALS { Severity::High } else { Severity::Medium };
+    // Each signal that fires beyond the minimum makes a false positive less likely
+    let confidence = (0.5 + 0.1 * signals.len() as f64).min(0.95);
+
+    Some(Detection {
+        activity: SuspiciousActivity {
+            activity_type: "DNS Tunneling".to_string(),
+            source: client,
+            details: format!(
+                "~{:.0} unique subdomains of {} in {} queries, ~{} bytes exfiltrated: {}",
+                unique,
+                parent,
+                window.queries,
+                exfiltrated,
+                signals.join(", ")
+            ),
+            indicator: parent.to_string(),
+            magnitude: exfiltrated,
+            severity,
+            score: 0.0,
+            confidence,
+            evidence: Evidence {
+                count: Some(window.queries as f64),
+                domains: vec![parent.to_string()],
+                metrics: BTreeMap::from([
+                    ("unique_subdomains".to_string(), unique),
+                    ("exfiltrated_bytes".to_string(), exfiltrated),
+                ]),
+                ..Evidence::default()
+            },
+            rule_id: "dns_tunneling".to_string(),
+            timestamp: now,
+        },
+        actions: Vec::new(),
+        vars: HashMap::from([
+            ("parent_domain".to_string(), parent.to_string()),
+            ("exfiltrated_bytes".to_string(), exfiltrated.to_string()),
+        ]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniff::dns::{DnsQuestion, TYPE_A, TYPE_TXT};
+
+    fn query(name: &str, qtype: u16, timestamp: f64) -> NetworkEvent {
+        NetworkEvent {
+            protocol: "DNS".to_string(),
+            source: "10.0.0.5:50000".to_string(),
+            destination: "10.0.0.53:53".to_string(),
+            payload_size: 0,
+            timestamp,
+            source_mac: None,
+            arp: None,
+            dns: Some(DnsMessage {
+                id: 1,
+                is_response: false,
+                opcode: 0,
+                rcode: 0,
+                questions: vec![DnsQuestion { name: name.to_string(), qtype, registered_domain: None }],
+                answers: Vec::new(),
+                authorities: Vec::new(),
+            }),
+            mdns: None,
+            netbios: None,
+            dhcp: None,
+            tcp_syn: None,
+        }
+    }
+
+    // Hex-encoded chunks of data, as a tunnel would send them
+    fn encoded(i: u64) -> String {
+        format!("{:016x}{:016x}", i.wrapping_mul(0x9E37_79B9_7F4A_7C15), i ^ 0xA5A5_5A5A_DEAD_BEEF)
+    }
+
+    fn run(detector: &mut DnsTunnelDetector, events: impl Iterator<Item = NetworkEvent>) -> Vec<Detection> {
+        let mut detections = Vec::new();
+        for event in events {
+            detector.observe(&event, &mut detections);
+        }
+        detections
+    }
+
+    #[test]
+    fn flags_encoded_txt_queries() {
+        let mut detector = DnsTunnelDetector::new(HashSet::new());
+        let events = (0..200).map(|i| query(&format!("{}.t.tunnel.com", encoded(i)), TYPE_TXT, i as f64));
+        let detections = run(&mut detector, events);
+        assert_eq!(detections.len(), 1, "alerts are rate limited per domain");
+        let activity = &detections[0].activity;
+        assert_eq!(activity.indicator, "tunnel.com");
+        assert_eq!(activity.source, "10.0.0.5");
+        assert!(activity.magnitude > 0.0);
+    }
+
+    #[test]
+    fn ignores_allowlisted_and_ordinary_lookups() {
+        let allowlist = HashSet::from(["tunnel.com".to_string()]);
+        let mut detector = DnsTunnelDetector::new(allowlist);
+        let events = (0..200).map(|i| query(&format!("{}.tunnel.com", encoded(i)), TYPE_TXT, i as f64));
+        assert!(run(&mut detector, events).is_empty());
+
+        // Many hosts under one domain, but short readable names
+        let mut detector = DnsTunnelDetector::new(HashSet::new());
+        let events = (0..200).map(|i| query(&format!("host{}.corp.com", i), TYPE_A, i as f64));
+        assert!(run(&mut detector, events).is_empty());
+
+        let mut detector = DnsTunnelDetector::new(HashSet::new());
+        let events = (0..200).map(|i| query(&format!("{}.4.3.2.1.in-addr.arpa", i), TYPE_A, i as f64));
+        assert!(run(&mut detector, events).is_empty());
+    }
+}
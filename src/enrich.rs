@@ -0,0 +1,191 @@
+// LLM enrichment stage: runs on its own task behind a queue, so alerts are
+// stored and scored without waiting on the model, and sends them for triage,
+// skipping ones whose verdict is still cached. When the primary provider's
+// token budget runs out, enrichment falls back to a local model or is
+// skipped, depending on LLM_BUDGET_FALLBACK. Alerts with a verdict, fresh or
+// cached, are rescored with it.
+
+use crate::detection::{SuspiciousActivity, TrafficAnalyzer};
+use crate::scoring;
+use chrono::{TimeZone, Utc};
+use mongodb::{bson::oid::ObjectId, Database};
+use sniff::llm::budget::TokenBudget;
+use sniff::llm::templates::TemplateLibrary;
+use sniff::llm::verdict::TriageVerdict;
+use sniff::llm::{LlmInference, Provider};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+const DEFAULT_CACHE_TTL_SECS: u64 = 3600;
+// An alert is re-analyzed before its TTL expires if it grew by this factor
+const ESCALATION_FACTOR: f64 = 1.5;
+
+struct CachedVerdict {
+    inference_id: ObjectId,
+    verdict: Option<TriageVerdict>,
+    magnitude: f64,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BudgetFallback {
+    Skip,
+    Local,
+}
+
+pub struct LlmEnricher {
+    primary: Option<Provider>,
+    fallback: Option<Provider>,
+    budget: TokenBudget,
+    ttl: Duration,
+    cache: HashMap<String, CachedVerdict>,
+    templates: TemplateLibrary,
+}
+
+impl LlmEnricher {
+    pub async fn new(db: &Database) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let (primary, fallback) = providers_from_env();
+        match (&primary, &fallback) {
+            (None, Some(_)) => println!("TOGETHER_API_KEY not set, using the local provider only"),
+            (None, None) => println!("TOGETHER_API_KEY not set and LLM_BUDGET_FALLBACK is not local, skipping LLM enrichment"),
+            _ => {}
+        }
+
+        let ttl = env::var("LLM_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CACHE_TTL_SECS);
+
+        let providers: Vec<&str> = primary.iter().chain(fallback.iter()).map(|p| p.name.as_str()).collect();
+        let budget = TokenBudget::load(db.collection("llm_usage"), &providers, get_current_timestamp()).await?;
+
+        Ok(LlmEnricher {
+            primary,
+            fallback,
+            budget,
+            ttl: Duration::from_secs(ttl),
+            cache: HashMap::new(),
+            templates: TemplateLibrary::from_env(),
+        })
+    }
+
+    /// Starts the enrichment task; stored alerts are queued on the returned
+    /// sender and triaged in order.
+    pub fn spawn(mut self, analyzer: TrafficAnalyzer) -> mpsc::UnboundedSender<(SuspiciousActivity, ObjectId)> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<(SuspiciousActivity, ObjectId)>();
+        tokio::spawn(async move {
+            while let Some((activity, alert_id)) = rx.recv().await {
+                if let Err(e) = self.enrich(&analyzer, &activity, alert_id).await {
+                    eprintln!("Error enriching suspicious activity: {}", e);
+                }
+            }
+        });
+        tx
+    }
+
+    /// The first provider with budget left, if any.
+    fn select_provider(&self, now: f64) -> Option<Provider> {
+        self.primary
+            .iter()
+            .chain(self.fallback.iter())
+            .find(|p| self.budget.allows(&p.name, now))
+            .cloned()
+    }
+
+    /// Triages a freshly stored alert. New fingerprints and escalations go to
+    /// the model; everything else is linked to the cached inference.
+    pub async fn enrich(&mut self, analyzer: &TrafficAnalyzer, activity: &SuspiciousActivity, alert_id: ObjectId) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let now = Instant::now();
+        self.cache.retain(|_, cached| cached.expires_at > now);
+
+        let fingerprint = activity.fingerprint();
+        if let Some(cached) = self.cache.get(&fingerprint) {
+            if activity.magnitude < cached.magnitude * ESCALATION_FACTOR {
+                analyzer.link_llm_inference(alert_id, cached.inference_id).await?;
+                return rescore(analyzer, activity, alert_id, cached.verdict.as_ref()).await;
+            }
+        }
+
+        let timestamp = get_current_timestamp();
+        let provider = match self.select_provider(timestamp) {
+            Some(provider) => provider,
+            None => return Ok(()), // Budget exhausted, skip enrichment
+        };
+
+        let prompt = self.templates.render(&activity.activity_type, &template_vars(activity));
+        let triage_provider = provider.clone();
+        let mut inference = tokio::task::spawn_blocking(move || LlmInference::triage(&triage_provider, &prompt)).await??;
+        if let Some(usage) = inference.usage {
+            // The inference is already paid for; keep it even if the counter is not saved
+            if let Err(e) = self.budget.record(&provider.name, usage, timestamp).await {
+                eprintln!("Error recording LLM usage for {}: {}", provider.name, e);
+            }
+        }
+        inference.alert_id = Some(alert_id);
+        inference.fingerprint = Some(fingerprint.clone());
+
+        let verdict = inference.verdict.clone();
+        let inference_id = analyzer.store_llm_inference(inference).await?;
+        analyzer.link_llm_inference(alert_id, inference_id).await?;
+        rescore(analyzer, activity, alert_id, verdict.as_ref()).await?;
+
+        self.cache.insert(fingerprint, CachedVerdict {
+            inference_id,
+            verdict,
+            magnitude: activity.magnitude,
+            expires_at: now + self.ttl,
+        });
+        Ok(())
+    }
+}
+
+/// The primary provider, when TOGETHER_API_KEY is set, and the local model
+/// when LLM_BUDGET_FALLBACK is `local`.
+fn providers_from_env() -> (Option<Provider>, Option<Provider>) {
+    let fallback = match env::var("LLM_BUDGET_FALLBACK").as_deref() {
+        Ok("local") => BudgetFallback::Local,
+        _ => BudgetFallback::Skip,
+    };
+    (Provider::together(), (fallback == BudgetFallback::Local).then(Provider::local))
+}
+
+/// Names of the providers enrichment may use, in order of preference.
+pub fn provider_names() -> Vec<String> {
+    let (primary, fallback) = providers_from_env();
+    primary.into_iter().chain(fallback).map(|p| p.name).collect()
+}
+
+async fn rescore(analyzer: &TrafficAnalyzer, activity: &SuspiciousActivity, alert_id: ObjectId, verdict: Option<&TriageVerdict>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match verdict {
+        Some(verdict) => analyzer.update_alert_score(alert_id, scoring::model().score(activity, Some(verdict))).await,
+        None => Ok(()),
+    }
+}
+
+fn template_vars(activity: &SuspiciousActivity) -> HashMap<&'static str, String> {
+    let time = Utc
+        .timestamp_opt(activity.timestamp as i64, 0)
+        .single()
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_else(|| activity.timestamp.to_string());
+
+    HashMap::from([
+        ("activity_type", activity.activity_type.clone()),
+        ("source", activity.source.clone()),
+        ("details", activity.details.clone()),
+        ("indicator", activity.indicator.clone()),
+        ("magnitude", activity.magnitude.to_string()),
+        ("timestamp", activity.timestamp.to_string()),
+        ("time", time),
+    ])
+}
+
+fn get_current_timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs_f64()
+}
@@ -0,0 +1,407 @@
+// Fast-flux and low-TTL domain detection.
+//
+// Every A/AAAA answer in a decoded DNS response is recorded in dns_mappings
+// with its TTL, and the last WINDOW_SECS of mappings are reloaded at startup.
+// Per queried name we track the distinct addresses, how many unrelated
+// networks they fall in (/16 for IPv4, /32 for IPv6, standing in for ASNs)
+// and the TTLs answered within the window; per registrable domain we track the name servers seen in NS
+// answers and authority records. A name alerts when it resolves to many
+// addresses across many networks with short TTLs; name server churn on top
+// of that ("double flux") raises the severity.
+//
+// FAST_FLUX_ALLOWLIST takes a comma-separated list of registrable domains
+// (CDNs, load balancers) to skip.
+
+use crate::detection::{Detection, DnsMapping, Evidence, SuspiciousActivity};
+use crate::sniff::dns::{self, DnsMessage};
+use crate::sniff::domain::DomainName;
+use crate::sniff::NetworkEvent;
+use crate::stream::{split_endpoint, StreamDetector};
+use futures::StreamExt;
+use mongodb::{bson::doc, Collection, Database};
+use sniff::llm::verdict::Severity;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::env;
+use std::error::Error;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+const WINDOW_SECS: f64 = 3.0 * 3600.0;
+const MIN_DISTINCT_IPS: usize = 10;
+const MIN_NETWORKS: usize = 5;
+// Median TTL at or below this counts as short
+const MAX_MEDIAN_TTL: u32 = 300;
+const NS_CHURN_THRESHOLD: usize = 4;
+const MAX_TTLS: usize = 64;
+const ALERT_COOLDOWN: f64 = 3600.0;
+const MAX_NAMES: usize = 50_000;
+
+#[derive(Default)]
+struct NameAnswers {
+    // address -> last seen
+    ips: HashMap<String, f64>,
+    // (seen, ttl), oldest first
+    ttls: VecDeque<(f64, u32)>,
+    last_seen: f64,
+    last_client: String,
+}
+
+pub struct FastFluxDetector {
+    names: HashMap<String, NameAnswers>,
+    // registrable domain -> name server -> last seen
+    name_servers: HashMap<String, HashMap<String, f64>>,
+    last_alert: HashMap<String, f64>,
+    allowlist: HashSet<String>,
+    writer: Option<mpsc::UnboundedSender<DnsMapping>>,
+}
+
+impl FastFluxDetector {
+    pub fn new(allowlist: HashSet<String>) -> Self {
+        FastFluxDetector {
+            names: HashMap::new(),
+            name_servers: HashMap::new(),
+            last_alert: HashMap::new(),
+            allowlist,
+            writer: None,
+        }
+    }
+
+    /// Replays recent answers from `dns_mappings` and starts a task that
+    /// records new ones there.
+    pub async fn load(db: &Database) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let allowlist = env::var("FAST_FLUX_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|d| d.trim().trim_end_matches('.').to_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+        let mut detector = Self::new(allowlist);
+
+        let collection: Collection<DnsMapping> = db.collection("dns_mappings");
+        let since = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64() - WINDOW_SECS;
+        let mut cursor = collection
+            .find(doc! { "timestamp": { "$gte": since }, "ttl": { "$ne": null } })
+            .await?;
+        let mut loaded = 0;
+        while let Some(mapping) = cursor.next().await {
+            let mapping = mapping?;
+            detector.record(&mapping.query, &mapping.resolved_ip, mapping.ttl.unwrap_or(0), &mapping.source, mapping.timestamp);
+            loaded += 1;
+        }
+        println!("Loaded {} recent DNS answers", loaded);
+
+        detector.writer = Some(spawn_mapping_writer(collection));
+        Ok(detector)
+    }
+
+    fn record(&mut self, name: &str, ip: &str, ttl: u32, client: &str, now: f64) {
+        if !self.names.contains_key(name) && self.names.len() >= MAX_NAMES {
+            return;
+        }
+        let answers = self.names.entry(name.to_string()).or_default();
+        answers.ips.insert(ip.to_string(), now);
+        answers.ttls.push_back((now, ttl));
+        if answers.ttls.len() > MAX_TTLS {
+            answers.ttls.pop_front();
+        }
+        answers.last_seen = now;
+        answers.last_client = client.to_string();
+    }
+
+    fn observe_response(&mut self, event: &NetworkEvent, message: &DnsMessage, detections: &mut Vec<Detection>) {
+        let name = match message.query_name() {
+            Some(name) => name.to_string(),
+            None => return,
+        };
+        let domain = DomainName::parse(&name);
+        let registrable = match &domain.registrable {
+            Some(registrable) if !self.allowlist.contains(registrable) => registrable.clone(),
+            _ => return,
+        };
+        let now = event.timestamp;
+        let client = split_endpoint(&event.destination).map(|(ip, _)| ip).unwrap_or(&event.destination).to_string();
+
+        // Addresses at the end of a CNAME chain still answer the query
+        let mut answered = false;
+        for record in &message.answers {
+            if record.rtype != dns::TYPE_A && record.rtype != dns::TYPE_AAAA {
+                continue;
+            }
+            answered = true;
+            self.record(&name, &record.data, record.ttl, &client, now);
+            if let Some(writer) = &self.writer {
+                let _ = writer.send(DnsMapping {
+                    query: name.clone(),
+                    registered_domain: Some(registrable.clone()),
+                    resolved_ip: record.data.clone(),
+                    ttl: Some(record.ttl),
+                    timestamp: now,
+                    is_http: false,
+                    source: client.clone(),
+                });
+            }
+        }
+        for record in message.answers.iter().chain(&message.authorities) {
+            if record.rtype == dns::TYPE_NS && !record.data.is_empty() {
+                self.name_servers.entry(registrable.clone()).or_default().insert(record.data.clone(), now);
+            }
+        }
+
+        if answered && !self.last_alert.get(&name).is_some_and(|t| now - t < ALERT_COOLDOWN) {
+            if let Some(detection) = self.evaluate(&name, &registrable, now) {
+                self.last_alert.insert(name, now);
+                detections.push(detection);
+            }
+        }
+    }
+
+    fn evaluate(&self, name: &str, registrable: &str, now: f64) -> Option<Detection> {
+        let answers = self.names.get(name)?;
+        let ips: Vec<&String> = answers.ips.iter().filter(|(_, seen)| now - **seen <= WINDOW_SECS).map(|(ip, _)| ip).collect();
+        if ips.len() < MIN_DISTINCT_IPS {
+            return None;
+        }
+        let networks: HashSet<String> = ips.iter().filter_map(|ip| network_of(ip)).collect();
+        if networks.len() < MIN_NETWORKS {
+            return None;
+        }
+        let mut ttls: Vec<u32> = answers.ttls.iter().filter(|(seen, _)| now - *seen <= WINDOW_SECS).map(|(_, ttl)| *ttl).collect();
+        ttls.sort_unstable();
+        let median_ttl = ttls.get(ttls.len() / 2).copied().unwrap_or(0);
+        if median_ttl > MAX_MEDIAN_TTL {
+            return None;
+        }
+
+        let name_servers = self
+            .name_servers
+            .get(registrable)
+            .map(|servers| servers.values().filter(|seen| now - **seen <= WINDOW_SECS).count())
+            .unwrap_or(0);
+        let double_flux = name_servers >= NS_CHURN_THRESHOLD;
+
+        let mut details = format!(
+            "{} resolved to {} addresses in {} networks within {} hours, median TTL {}s",
+            name,
+            ips.len(),
+            networks.len(),
+            WINDOW_SECS / 3600.0,
+            median_ttl
+        );
+        if double_flux {
+            details.push_str(&format!(", {} name servers for {}", name_servers, registrable));
+        }
+
+        Some(Detection {
+            activity: SuspiciousActivity {
+                activity_type: "Fast Flux".to_string(),
+                source: answers.last_client.clone(),
+                details,
+                indicator: name.to_string(),
+                magnitude: ips.len() as f64,
+                severity: if double_flux { Severity::High } else { Severity::Medium },
+                score: 0.0,
+                confidence: if double_flux { 0.85 } else { 0.7 },
+                evidence: Evidence {
+                    count: Some(ips.len() as f64),
+                    peers: ips.iter().map(|ip| ip.to_string()).collect(),
+                    domains: vec![name.to_string()],
+                    metrics: BTreeMap::from([
+                        ("median_ttl".to_string(), median_ttl as f64),
+                        ("networks".to_string(), networks.len() as f64),
+                        ("name_servers".to_string(), name_servers as f64),
+                    ]),
+                    ..Evidence::default()
+                },
+                rule_id: "fast_flux".to_string(),
+                timestamp: now,
+            },
+            actions: Vec::new(),
+            vars: HashMap::from([
+                ("registered_domain".to_string(), registrable.to_string()),
+                ("median_ttl".to_string(), median_ttl.to_string()),
+                ("networks".to_string(), networks.len().to_string()),
+            ]),
+        })
+    }
+}
+
+impl StreamDetector for FastFluxDetector {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        match &event.dns {
+            Some(message) if message.is_response => self.observe_response(event, message, detections),
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        self.names.retain(|_, answers| now - answers.last_seen <= WINDOW_SECS);
+        for answers in self.names.values_mut() {
+            answers.ips.retain(|_, seen| now - *seen <= WINDOW_SECS);
+            answers.ttls.retain(|(seen, _)| now - *seen <= WINDOW_SECS);
+        }
+        self.name_servers.retain(|_, servers| {
+            servers.retain(|_, seen| now - *seen <= WINDOW_SECS);
+            !servers.is_empty()
+        });
+        self.last_alert.retain(|_, t| now - *t < ALERT_COOLDOWN);
+    }
+}
+
+/// The /16 of an IPv4 address or /32 of an IPv6 address.
+fn network_of(ip: &str) -> Option<String> {
+    match ip.parse::<IpAddr>().ok()? {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            Some(format!("{}.{}.0.0/16", octets[0], octets[1]))
+        }
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            Some(format!("{:x}:{:x}::/32", segments[0], segments[1]))
+        }
+    }
+}
+
+fn spawn_mapping_writer(collection: Collection<DnsMapping>) -> mpsc::UnboundedSender<DnsMapping> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<DnsMapping>();
+    tokio::spawn(async move {
+        while let Some(mapping) = rx.recv().await {
+            if let Err(e) = collection.insert_one(mapping).await {
+                eprintln!("Error storing DNS mapping: {}", e);
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniff::dns::{DnsQuestion, DnsRecord, TYPE_A, TYPE_NS};
+
+    const NAME: &str = "www.flux-example.com";
+    const DOMAIN: &str = "flux-example.com";
+
+    fn record(rtype: u16, ttl: u32, data: &str) -> DnsRecord {
+        DnsRecord { name: NAME.to_string(), rtype, ttl, data: data.to_string(), data_len: 4 }
+    }
+
+    fn response(answers: Vec<DnsRecord>, authorities: Vec<DnsRecord>, timestamp: f64) -> NetworkEvent {
+        NetworkEvent {
+            protocol: "DNS".to_string(),
+            source: "10.0.0.53:53".to_string(),
+            destination: "10.0.0.5:50000".to_string(),
+            payload_size: 0,
+            timestamp,
+            source_mac: None,
+            arp: None,
+            dns: Some(DnsMessage {
+                id: 1,
+                is_response: true,
+                opcode: 0,
+                rcode: 0,
+                questions: vec![DnsQuestion { name: NAME.to_string(), qtype: TYPE_A, registered_domain: None }],
+                answers,
+                authorities,
+            }),
+            mdns: None,
+            netbios: None,
+            dhcp: None,
+            tcp_syn: None,
+        }
+    }
+
+    /// `count` addresses, each in its own /16 starting at `first`.
+    fn spread(first: u8, count: u8) -> Vec<String> {
+        (first..first + count).map(|i| format!("{}.{}.7.9", 60 + i / 100, i)).collect()
+    }
+
+    fn record_all(detector: &mut FastFluxDetector, ips: &[String], ttl: u32, now: f64) {
+        for ip in ips {
+            detector.record(NAME, ip, ttl, "10.0.0.5", now);
+        }
+    }
+
+    #[test]
+    fn networks() {
+        assert_eq!(network_of("203.0.113.9").as_deref(), Some("203.0.0.0/16"));
+        assert_eq!(network_of("2001:db8:1:2::1").as_deref(), Some("2001:db8::/32"));
+        assert_eq!(network_of("not an address"), None);
+    }
+
+    #[test]
+    fn flags_many_networks_with_short_ttls() {
+        let mut detector = FastFluxDetector::new(HashSet::new());
+        record_all(&mut detector, &spread(0, 10), 60, 1000.0);
+        let detection = detector.evaluate(NAME, DOMAIN, 1000.0).unwrap();
+        let activity = &detection.activity;
+        assert_eq!(activity.activity_type, "Fast Flux");
+        assert_eq!(activity.source, "10.0.0.5");
+        assert_eq!(activity.indicator, NAME);
+        assert_eq!(activity.severity, Severity::Medium);
+        assert_eq!(activity.evidence.count, Some(10.0));
+        assert_eq!(detection.vars["median_ttl"], "60");
+        assert_eq!(detection.vars["networks"], "10");
+    }
+
+    #[test]
+    fn ignores_few_networks_or_long_ttls() {
+        // Ten addresses in two /16s look like one provider's pool
+        let mut detector = FastFluxDetector::new(HashSet::new());
+        let pooled: Vec<String> = (0..10).map(|i| format!("198.{}.0.{}", 51 + i % 2, i)).collect();
+        record_all(&mut detector, &pooled, 60, 1000.0);
+        assert!(detector.evaluate(NAME, DOMAIN, 1000.0).is_none());
+
+        let mut detector = FastFluxDetector::new(HashSet::new());
+        record_all(&mut detector, &spread(0, 10), 3600, 1000.0);
+        assert!(detector.evaluate(NAME, DOMAIN, 1000.0).is_none());
+
+        let mut detector = FastFluxDetector::new(HashSet::new());
+        record_all(&mut detector, &spread(0, 9), 60, 1000.0);
+        assert!(detector.evaluate(NAME, DOMAIN, 1000.0).is_none());
+    }
+
+    #[test]
+    fn ttls_age_out_with_the_window() {
+        let mut detector = FastFluxDetector::new(HashSet::new());
+        record_all(&mut detector, &spread(0, 10), 3600, 1000.0);
+        let later = 1000.0 + WINDOW_SECS + 60.0;
+        record_all(&mut detector, &spread(10, 10), 60, later);
+        // Only the recent short TTLs count towards the median
+        let detection = detector.evaluate(NAME, DOMAIN, later).unwrap();
+        assert_eq!(detection.vars["median_ttl"], "60");
+        assert_eq!(detection.activity.evidence.count, Some(10.0));
+
+        detector.tick(later, &mut Vec::new());
+        assert_eq!(detector.names[NAME].ttls.len(), 10);
+    }
+
+    #[test]
+    fn name_server_churn_is_double_flux() {
+        let mut detector = FastFluxDetector::new(HashSet::new());
+        let answers = spread(0, 10).iter().map(|ip| record(TYPE_A, 60, ip)).collect();
+        let authorities = (0..4).map(|i| record(TYPE_NS, 60, &format!("ns{}.flux-dns.net", i))).collect();
+        let mut detections = Vec::new();
+        detector.observe(&response(answers, authorities, 1000.0), &mut detections);
+        assert_eq!(detections.len(), 1);
+        let activity = &detections[0].activity;
+        assert_eq!(activity.severity, Severity::High);
+        assert_eq!(activity.confidence, 0.85);
+        assert_eq!(activity.evidence.metrics["name_servers"], 4.0);
+
+        // Within the cooldown the same name does not alert again
+        let answers = spread(10, 3).iter().map(|ip| record(TYPE_A, 60, ip)).collect();
+        detector.observe(&response(answers, Vec::new(), 1100.0), &mut detections);
+        assert_eq!(detections.len(), 1);
+    }
+
+    #[test]
+    fn skips_allowlisted_domains() {
+        let mut detector = FastFluxDetector::new(HashSet::from([DOMAIN.to_string()]));
+        let answers = spread(0, 10).iter().map(|ip| record(TYPE_A, 60, ip)).collect();
+        let mut detections = Vec::new();
+        detector.observe(&response(answers, Vec::new(), 1000.0), &mut detections);
+        assert!(detections.is_empty());
+        assert!(detector.names.is_empty());
+    }
+}
@@ -0,0 +1,499 @@
+// Incident correlation.
+//
+// Alerts that share an entity (host address, MAC, domain or destination)
+// within INCIDENT_WINDOW_SECS (default one hour) of the incident's last alert
+// join the same incident; an alert linking two incidents merges them. Each
+// incident is checked against kill-chain sequence rules (rules/sequences.yaml,
+// or INCIDENT_SEQUENCES_FILE to add or replace by id).
+//
+// Score: per distinct alert its severity weight (low 1, medium 3, high 6,
+// critical 10), plus 5 for every activity type beyond the first, plus each
+// matched sequence's score. Severity is the highest of the alerts' and the
+// matched sequences'. Incidents with at least MIN_ALERTS alerts or a matched
+// sequence are stored in `incidents`, and their alerts get an incident_id.
+
+use crate::detection::Detection;
+use crate::stream::{split_arp_endpoint, split_endpoint};
+use futures::StreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+const BUILTIN_SEQUENCES: &str = include_str!("../rules/sequences.yaml");
+const DEFAULT_WINDOW_SECS: f64 = 3600.0;
+const MIN_ALERTS: usize = 2;
+const TYPE_BONUS: f64 = 5.0;
+// Entity fields a detection's variables may carry
+const ENTITY_VARS: [&str; 7] = ["src_ip", "dest_ip", "resolved_ip", "query", "registered_domain", "src_mac", "dest_mac"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SequenceRule {
+    pub id: String,
+    pub name: String,
+    /// Each stage lists alternative activity types
+    pub stages: Vec<Vec<String>>,
+    pub within: f64,
+    pub severity: String,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncidentAlert {
+    pub alert_id: ObjectId,
+    pub activity_type: String,
+    pub severity: String,
+    pub source: String,
+    pub indicator: String,
+    /// When the alert first joined the incident
+    pub timestamp: f64,
+    /// When the alert was last detected again
+    #[serde(default)]
+    pub last_seen: f64,
+}
+
+impl IncidentAlert {
+    /// The times the alert is known to have fired at.
+    fn times(&self) -> [f64; 2] {
+        [self.timestamp, self.last_seen.max(self.timestamp)]
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Incident {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub title: String,
+    /// "host:10.0.0.5", "mac:aa:bb:..", "domain:example.com"
+    pub entities: BTreeSet<String>,
+    pub alerts: Vec<IncidentAlert>,
+    /// Names of matched sequence rules
+    pub sequences: Vec<String>,
+    pub score: f64,
+    pub severity: String,
+    pub first_seen: f64,
+    pub last_seen: f64,
+}
+
+impl Incident {
+    fn new(now: f64) -> Self {
+        Incident {
+            id: ObjectId::new(),
+            title: String::new(),
+            entities: BTreeSet::new(),
+            alerts: Vec::new(),
+            sequences: Vec::new(),
+            score: 0.0,
+            severity: "low".to_string(),
+            first_seen: now,
+            last_seen: now,
+        }
+    }
+
+    fn absorb(&mut self, other: Incident) {
+        self.entities.extend(other.entities);
+        for alert in other.alerts {
+            match self.alerts.iter_mut().find(|a| a.alert_id == alert.alert_id) {
+                Some(existing) => existing.last_seen = existing.last_seen.max(alert.last_seen),
+                None => self.alerts.push(alert),
+            }
+        }
+        self.alerts.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+
+    fn should_store(&self) -> bool {
+        self.alerts.len() >= MIN_ALERTS || !self.sequences.is_empty()
+    }
+}
+
+enum IncidentWrite {
+    Upsert(Incident),
+    Delete(ObjectId),
+}
+
+pub struct Correlator {
+    incidents: Vec<Incident>,
+    sequences: Vec<SequenceRule>,
+    window: f64,
+    writer: Option<mpsc::UnboundedSender<IncidentWrite>>,
+}
+
+impl Correlator {
+    pub fn new(sequences: Vec<SequenceRule>, window: f64) -> Self {
+        Correlator {
+            incidents: Vec::new(),
+            sequences,
+            window,
+            writer: None,
+        }
+    }
+
+    /// Loads the sequence rules and the incidents still inside the window, and
+    /// starts a task that stores incident updates.
+    pub async fn load(db: &Database) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let window = env::var("INCIDENT_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_WINDOW_SECS);
+        let mut correlator = Self::new(load_sequences()?, window);
+
+        let collection: Collection<Incident> = db.collection("incidents");
+        let since = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64() - window;
+        let mut cursor = collection.find(doc! { "last_seen": { "$gte": since } }).await?;
+        while let Some(incident) = cursor.next().await {
+            correlator.incidents.push(incident?);
+        }
+        println!("Loaded {} sequence rules and {} open incidents", correlator.sequences.len(), correlator.incidents.len());
+
+        correlator.writer = Some(spawn_incident_writer(collection, db.collection("sus_events")));
+        Ok(correlator)
+    }
+
+    /// Adds a stored alert to its incident, returning the incident when a
+    /// sequence newly matched.
+    pub fn correlate(&mut self, detection: &Detection, alert_id: ObjectId) -> Option<&Incident> {
+        let activity = &detection.activity;
+        let now = activity.timestamp;
+        self.incidents.retain(|incident| now - incident.last_seen <= self.window);
+
+        let entities = entities(detection);
+        if entities.is_empty() {
+            return None;
+        }
+
+        // Every incident this alert touches is merged into the oldest one
+        let mut related: Vec<Incident> = Vec::new();
+        let mut i = 0;
+        while i < self.incidents.len() {
+            if self.incidents[i].entities.iter().any(|e| entities.contains(e)) {
+                related.push(self.incidents.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        related.sort_by(|a, b| a.first_seen.total_cmp(&b.first_seen));
+        let mut related = related.into_iter();
+        let mut incident = related.next().unwrap_or_else(|| Incident::new(now));
+        for merged in related {
+            self.write(IncidentWrite::Delete(merged.id));
+            incident.absorb(merged);
+        }
+
+        incident.entities.extend(entities);
+        incident.last_seen = incident.last_seen.max(now);
+        match incident.alerts.iter_mut().find(|a| a.alert_id == alert_id) {
+            // A re-detection may complete a later stage of a sequence
+            Some(existing) => existing.last_seen = existing.last_seen.max(now),
+            None => incident.alerts.push(IncidentAlert {
+                alert_id,
+                activity_type: activity.activity_type.clone(),
+                severity: activity.severity.to_string(),
+                source: activity.source.clone(),
+                indicator: activity.indicator.clone(),
+                timestamp: now,
+                last_seen: now,
+            }),
+        }
+
+        let matched_before = incident.sequences.len();
+        self.score(&mut incident);
+        let escalated = incident.sequences.len() > matched_before;
+        if incident.should_store() {
+            self.write(IncidentWrite::Upsert(incident.clone()));
+        }
+        self.incidents.push(incident);
+        escalated.then(|| self.incidents.last()).flatten()
+    }
+
+    fn score(&self, incident: &mut Incident) {
+        incident.sequences = self
+            .sequences
+            .iter()
+            .filter(|sequence| sequence_matches(sequence, &incident.alerts))
+            .map(|sequence| sequence.name.clone())
+            .collect();
+
+        let types: BTreeSet<&str> = incident.alerts.iter().map(|a| a.activity_type.as_str()).collect();
+        let matched: Vec<&SequenceRule> = self.sequences.iter().filter(|s| incident.sequences.contains(&s.name)).collect();
+        incident.score = incident.alerts.iter().map(|a| severity_weight(&a.severity)).sum::<f64>()
+            + TYPE_BONUS * types.len().saturating_sub(1) as f64
+            + matched.iter().map(|s| s.score).sum::<f64>();
+        incident.severity = incident
+            .alerts
+            .iter()
+            .map(|a| a.severity.as_str())
+            .chain(matched.iter().map(|s| s.severity.as_str()))
+            .max_by(|a, b| severity_weight(a).total_cmp(&severity_weight(b)))
+            .unwrap_or("low")
+            .to_string();
+
+        let host = incident.entities.iter().find(|e| e.starts_with("host:")).or(incident.entities.first());
+        incident.title = match (matched.first(), host) {
+            (Some(sequence), Some(host)) => format!("{} involving {}", sequence.name, host),
+            (None, Some(host)) => format!("{} related alerts involving {}", incident.alerts.len(), host),
+            _ => format!("{} related alerts", incident.alerts.len()),
+        };
+    }
+
+    fn write(&self, write: IncidentWrite) {
+        if let Some(writer) = &self.writer {
+            let _ = writer.send(write);
+        }
+    }
+}
+
+/// Whether the alerts contain the sequence's stages in order within its span.
+/// Every firing of a first-stage alert is tried as the start; from there each
+/// stage takes its earliest firing at or after the previous stage's, which
+/// gives the shortest run from that start.
+fn sequence_matches(sequence: &SequenceRule, alerts: &[IncidentAlert]) -> bool {
+    let in_stage = |stage: &[String], alert: &IncidentAlert| stage.iter().any(|t| t.eq_ignore_ascii_case(&alert.activity_type));
+    let Some((first_stage, later_stages)) = sequence.stages.split_first() else { return false };
+
+    let mut starts = alerts.iter().filter(|a| in_stage(first_stage, a)).flat_map(IncidentAlert::times);
+    starts.any(|start| {
+        let mut previous = start;
+        for stage in later_stages {
+            let next = alerts
+                .iter()
+                .filter(|a| in_stage(stage, a))
+                .flat_map(IncidentAlert::times)
+                .filter(|t| *t >= previous)
+                .min_by(f64::total_cmp);
+            match next {
+                Some(timestamp) if timestamp - start <= sequence.within => previous = timestamp,
+                _ => return false,
+            }
+        }
+        true
+    })
+}
+
+fn severity_weight(severity: &str) -> f64 {
+    match severity.to_lowercase().as_str() {
+        "critical" => 10.0,
+        "high" => 6.0,
+        "medium" => 3.0,
+        _ => 1.0,
+    }
+}
+
+/// The hosts, MACs and domains an alert is about.
+fn entities(detection: &Detection) -> BTreeSet<String> {
+    let activity = &detection.activity;
+    // Split on commas only, so ARP's "MAC (IP)" stays whole
+    let values = activity
+        .source
+        .split(',')
+        .chain(std::iter::once(activity.indicator.as_str()))
+        .chain(ENTITY_VARS.iter().filter_map(|var| detection.vars.get(*var)).map(String::as_str))
+        // A rule's collected values, such as the sources of a rare port
+        .chain(detection.vars.get("values").into_iter().flat_map(|v| v.split(", ")));
+
+    let mut entities = BTreeSet::new();
+    for value in values {
+        let value = value.trim();
+        if let Some((mac, ip)) = split_arp_endpoint(value) {
+            entities.insert(format!("mac:{}", mac.to_lowercase()));
+            entities.insert(format!("host:{}", ip));
+        } else if let Some(entity) = entity(value) {
+            entities.insert(entity);
+        }
+    }
+    entities
+}
+
+fn entity(value: &str) -> Option<String> {
+    let address = split_endpoint(value).map(|(ip, _)| ip).unwrap_or(value);
+    if let Ok(ip) = address.trim_matches(['[', ']']).parse::<IpAddr>() {
+        return (!ip.is_unspecified() && !ip.is_loopback()).then(|| format!("host:{}", ip));
+    }
+    if value.len() == 17 && value.split(':').count() == 6 && value.split(':').all(|b| u8::from_str_radix(b, 16).is_ok()) {
+        return Some(format!("mac:{}", value.to_lowercase()));
+    }
+    let name = value.trim_end_matches('.').to_lowercase();
+    let is_domain = name.contains('.')
+        && name.chars().any(|c| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_');
+    is_domain.then(|| format!("domain:{}", name))
+}
+
+/// Built-in sequences plus INCIDENT_SEQUENCES_FILE, which replaces by id.
+pub fn load_sequences() -> Result<Vec<SequenceRule>, Box<dyn Error + Send + Sync>> {
+    let mut sequences: Vec<SequenceRule> = serde_yaml::from_str(BUILTIN_SEQUENCES)?;
+    if let Ok(path) = env::var("INCIDENT_SEQUENCES_FILE") {
+        let contents = fs::read_to_string(&path)?;
+        let extra: Vec<SequenceRule> = serde_yaml::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?;
+        for sequence in extra {
+            sequences.retain(|s| s.id != sequence.id);
+            sequences.push(sequence);
+        }
+    }
+    Ok(sequences)
+}
+
+fn spawn_incident_writer(collection: Collection<Incident>, alerts: Collection<mongodb::bson::Document>) -> mpsc::UnboundedSender<IncidentWrite> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<IncidentWrite>();
+    tokio::spawn(async move {
+        while let Some(write) = rx.recv().await {
+            let result = match write {
+                IncidentWrite::Upsert(incident) => {
+                    let alert_ids: Vec<ObjectId> = incident.alerts.iter().map(|a| a.alert_id).collect();
+                    let id = incident.id;
+                    match collection.replace_one(doc! { "_id": id }, incident).upsert(true).await {
+                        Ok(_) => alerts
+                            .update_many(doc! { "_id": { "$in": alert_ids } }, doc! { "$set": { "incident_id": id } })
+                            .await
+                            .map(|_| ()),
+                        Err(e) => Err(e),
+                    }
+                }
+                IncidentWrite::Delete(id) => collection.delete_one(doc! { "_id": id }).await.map(|_| ()),
+            };
+            if let Err(e) = result {
+                eprintln!("Error storing incident: {}", e);
+            }
+        }
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::{Evidence, SuspiciousActivity};
+    use sniff::llm::verdict::Severity;
+    use std::collections::HashMap;
+
+    fn detection(activity_type: &str, source: &str, indicator: &str, timestamp: f64) -> Detection {
+        Detection {
+            activity: SuspiciousActivity {
+                activity_type: activity_type.to_string(),
+                source: source.to_string(),
+                details: String::new(),
+                indicator: indicator.to_string(),
+                magnitude: 1.0,
+                severity: Severity::Medium,
+                score: 0.0,
+                confidence: 0.5,
+                evidence: Evidence::default(),
+                rule_id: "test".to_string(),
+                timestamp,
+            },
+            actions: Vec::new(),
+            vars: HashMap::new(),
+        }
+    }
+
+    fn alert(activity_type: &str, timestamp: f64) -> IncidentAlert {
+        IncidentAlert {
+            alert_id: ObjectId::new(),
+            activity_type: activity_type.to_string(),
+            severity: "medium".to_string(),
+            source: String::new(),
+            indicator: String::new(),
+            timestamp,
+            last_seen: timestamp,
+        }
+    }
+
+    fn recon_to_exfiltration() -> SequenceRule {
+        let sequences: Vec<SequenceRule> = serde_yaml::from_str(BUILTIN_SEQUENCES).unwrap();
+        sequences.into_iter().find(|s| s.id == "recon_to_exfiltration").unwrap()
+    }
+
+    #[test]
+    fn sequence_needs_stages_in_order() {
+        let sequence = recon_to_exfiltration();
+        let in_order = [alert("Port Scanning", 0.0), alert("Beaconing", 100.0), alert("Large Data Transfer", 200.0)];
+        assert!(sequence_matches(&sequence, &in_order));
+
+        let reversed = [alert("Large Data Transfer", 0.0), alert("Beaconing", 100.0), alert("Port Scanning", 200.0)];
+        assert!(!sequence_matches(&sequence, &reversed));
+        assert!(!sequence_matches(&sequence, &in_order[..2]));
+    }
+
+    #[test]
+    fn sequence_tries_every_start() {
+        let sequence = recon_to_exfiltration();
+        let alerts = [
+            alert("Port Scanning", 0.0),
+            alert("Port Scanning", 9000.0),
+            alert("Rare Port Activity", 9500.0),
+            alert("Large Data Transfer", 9600.0),
+        ];
+        assert!(sequence_matches(&sequence, &alerts));
+        assert!(!sequence_matches(&sequence, &[alerts[0].clone(), alerts[2].clone(), alerts[3].clone()]));
+    }
+
+    #[test]
+    fn redetection_counts_as_a_later_stage() {
+        let sequence = recon_to_exfiltration();
+        let mut transfer = alert("Large Data Transfer", 0.0);
+        let alerts = [transfer.clone(), alert("Port Scanning", 10.0), alert("Beaconing", 20.0)];
+        assert!(!sequence_matches(&sequence, &alerts));
+
+        transfer.last_seen = 30.0;
+        assert!(sequence_matches(&sequence, &[transfer, alerts[1].clone(), alerts[2].clone()]));
+    }
+
+    #[test]
+    fn extracts_entities() {
+        let mut scan = detection("Port Scanning", "10.0.0.5:51234, 0.0.0.0:1", "Evil.Example.", 0.0);
+        scan.vars.insert("values".to_string(), "10.0.0.7, 10.0.0.8".to_string());
+        scan.vars.insert("src_mac".to_string(), "AA:BB:CC:DD:EE:FF".to_string());
+        let entities: Vec<String> = entities(&scan).into_iter().collect();
+        assert_eq!(
+            entities,
+            ["domain:evil.example", "host:10.0.0.5", "host:10.0.0.7", "host:10.0.0.8", "mac:aa:bb:cc:dd:ee:ff"]
+        );
+
+        let arp = detection("ARP Spoofing", "AA:BB:CC:DD:EE:01 (192.168.1.1)", "", 0.0);
+        let entities: Vec<String> = super::entities(&arp).into_iter().collect();
+        assert_eq!(entities, ["host:192.168.1.1", "mac:aa:bb:cc:dd:ee:01"]);
+        assert!(super::entities(&detection("Port Scanning", "127.0.0.1", "count", 0.0)).is_empty());
+    }
+
+    #[test]
+    fn correlates_alerts_sharing_an_entity() {
+        let mut correlator = Correlator::new(vec![recon_to_exfiltration()], 3600.0);
+        assert!(correlator.correlate(&detection("Port Scanning", "10.0.0.5", "", 0.0), ObjectId::new()).is_none());
+        assert!(correlator.correlate(&detection("Beaconing", "10.0.0.9", "", 10.0), ObjectId::new()).is_none());
+        assert_eq!(correlator.incidents.len(), 2);
+
+        // Linking both hosts merges their incidents and completes the sequence
+        let incident = correlator
+            .correlate(&detection("Large Data Transfer", "10.0.0.5", "10.0.0.9", 20.0), ObjectId::new())
+            .unwrap();
+        assert_eq!(incident.alerts.len(), 3);
+        assert_eq!(incident.sequences, ["Reconnaissance followed by exfiltration"]);
+        assert_eq!(incident.severity, "critical");
+        // Three medium alerts, two extra types and the sequence
+        assert_eq!(incident.score, 3.0 * 3.0 + 2.0 * TYPE_BONUS + 30.0);
+        assert_eq!(correlator.incidents.len(), 1);
+
+        // Past the window a new incident starts
+        correlator.correlate(&detection("Port Scanning", "10.0.0.5", "", 5000.0), ObjectId::new());
+        assert_eq!(correlator.incidents.len(), 1);
+        assert_eq!(correlator.incidents[0].alerts.len(), 1);
+    }
+
+    #[test]
+    fn redetected_alerts_update_last_seen() {
+        let mut correlator = Correlator::new(Vec::new(), 3600.0);
+        let id = ObjectId::new();
+        correlator.correlate(&detection("Beaconing", "10.0.0.5", "", 0.0), id);
+        correlator.correlate(&detection("Beaconing", "10.0.0.5", "", 60.0), id);
+        let alerts = &correlator.incidents[0].alerts;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!((alerts[0].timestamp, alerts[0].last_seen), (0.0, 60.0));
+    }
+}
@@ -0,0 +1,683 @@
+// Local threat-intelligence indicator store.
+//
+// Feeds are files in INTEL_FEEDS_DIR (default intel.d), each named after its
+// file stem:
+//   - .txt/.list: one IP, CIDR or domain per line, `#` comments, anything
+//     after the first whitespace is the description
+//   - .csv: a header row with an `indicator` (or `value`/`ioc`) column and
+//     optional `type`, `description` and `severity` columns
+//   - .json: a STIX 2.1 bundle, whose indicator objects' ipv4-addr,
+//     ipv6-addr, domain-name and url comparisons are loaded, or an array of
+//     objects with the CSV columns as keys
+// Addresses go into a binary prefix trie per address family and domains into
+// a label trie keyed from the TLD down, so a listed domain also matches its
+// subdomains and lookups cost one walk per name.
+//
+// IntelMatcher checks every event's endpoints, DNS questions and answers as
+// they are captured and raises "Threat Intel Match" alerts. A feed file that
+// fails to parse is skipped whole; the other feeds still load.
+
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::sniff::dns;
+use crate::sniff::NetworkEvent;
+use crate::stream::{split_endpoint, StreamDetector};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use sniff::llm::verdict::Severity;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const DEFAULT_FEEDS_DIR: &str = "intel.d";
+const DEFAULT_SEVERITY: &str = "high";
+const ALERT_COOLDOWN: f64 = 3600.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndicatorKind {
+    Network,
+    Domain,
+}
+
+impl IndicatorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IndicatorKind::Network => "network",
+            IndicatorKind::Domain => "domain",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Indicator {
+    /// As listed, e.g. "203.0.113.0/24" or "evil.example"
+    pub value: String,
+    pub kind: IndicatorKind,
+    pub feed: String,
+    pub description: String,
+    pub severity: String,
+    /// The STIX indicator id, for bundle feeds
+    pub stix_id: Option<String>,
+}
+
+/// Binary trie over address bits; each node may end a listed prefix.
+#[derive(Default)]
+struct PrefixTrie {
+    // [child for 0, child for 1, indicator]
+    nodes: Vec<[Option<usize>; 3]>,
+}
+
+impl PrefixTrie {
+    fn insert(&mut self, bits: u128, width: u32, prefix_len: u32, indicator: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push([None; 3]);
+        }
+        let mut node = 0;
+        for i in 0..prefix_len {
+            let bit = ((bits >> (width - 1 - i)) & 1) as usize;
+            node = match self.nodes[node][bit] {
+                Some(next) => next,
+                None => {
+                    self.nodes.push([None; 3]);
+                    let next = self.nodes.len() - 1;
+                    self.nodes[node][bit] = Some(next);
+                    next
+                }
+            };
+        }
+        self.nodes[node][2].get_or_insert(indicator);
+    }
+
+    /// The most specific prefix containing the address.
+    fn longest_match(&self, bits: u128, width: u32) -> Option<usize> {
+        let mut node = 0;
+        let mut found = self.nodes.first()?[2];
+        for i in 0..width {
+            let bit = ((bits >> (width - 1 - i)) & 1) as usize;
+            match self.nodes[node][bit] {
+                Some(next) => node = next,
+                None => break,
+            }
+            found = self.nodes[node][2].or(found);
+        }
+        found
+    }
+}
+
+#[derive(Default)]
+struct DomainNode {
+    children: HashMap<String, usize>,
+    indicator: Option<usize>,
+}
+
+/// Trie over domain labels from the TLD down.
+#[derive(Default)]
+struct SuffixTrie {
+    nodes: Vec<DomainNode>,
+}
+
+impl SuffixTrie {
+    fn insert(&mut self, domain: &str, indicator: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push(DomainNode::default());
+        }
+        let mut node = 0;
+        for label in domain.rsplit('.') {
+            node = match self.nodes[node].children.get(label) {
+                Some(&next) => next,
+                None => {
+                    self.nodes.push(DomainNode::default());
+                    let next = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(label.to_string(), next);
+                    next
+                }
+            };
+        }
+        self.nodes[node].indicator.get_or_insert(indicator);
+    }
+
+    /// The most specific listed domain that is `name` or one of its parents.
+    fn longest_match(&self, name: &str) -> Option<usize> {
+        let mut node = 0;
+        let mut found = None;
+        self.nodes.first()?;
+        for label in name.rsplit('.') {
+            match self.nodes[node].children.get(label) {
+                Some(&next) => node = next,
+                None => break,
+            }
+            found = self.nodes[node].indicator.or(found);
+        }
+        found
+    }
+}
+
+#[derive(Default)]
+pub struct IntelStore {
+    indicators: Vec<Indicator>,
+    v4: PrefixTrie,
+    v6: PrefixTrie,
+    domains: SuffixTrie,
+}
+
+/// An indicator as read from a feed, before it is added to the store.
+struct Listing {
+    value: String,
+    description: String,
+    severity: String,
+    stix_id: Option<String>,
+}
+
+/// A CSV row or plain JSON feed entry.
+#[derive(Debug, Deserialize)]
+struct FeedEntry {
+    #[serde(alias = "value", alias = "ioc")]
+    indicator: String,
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    severity: Option<String>,
+}
+
+impl IntelStore {
+    /// Loads every feed file in `dir`; a missing directory is an empty store.
+    /// A file that fails to parse is skipped whole and the rest still load.
+    pub fn load(dir: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut store = Self::default();
+        for path in feed_files(dir)? {
+            let feed = path.file_stem().and_then(|s| s.to_str()).unwrap_or("feed").to_string();
+            let listings = match read_feed(&path) {
+                Ok(listings) => listings,
+                Err(e) => {
+                    eprintln!("Skipping threat feed {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let before = store.len();
+            for listing in listings {
+                store.add(&listing.value, &listing.description, &listing.severity, &feed, listing.stix_id);
+            }
+            println!("Loaded {} indicators from threat feed {}", store.len() - before, feed);
+        }
+        Ok(store)
+    }
+
+    pub fn from_env() -> Self {
+        let dir = env::var("INTEL_FEEDS_DIR").unwrap_or_else(|_| DEFAULT_FEEDS_DIR.to_string());
+        Self::load(Path::new(&dir)).unwrap_or_else(|e| {
+            eprintln!("Error loading threat intel feeds: {}", e);
+            Self::default()
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.indicators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indicators.is_empty()
+    }
+
+    fn add(&mut self, value: &str, description: &str, severity: &str, feed: &str, stix_id: Option<String>) {
+        let value = value.trim().trim_end_matches('.').to_lowercase();
+        let kind = if let Some((bits, width, prefix_len)) = parse_network(&value) {
+            let index = self.indicators.len();
+            match width {
+                32 => self.v4.insert(bits, width, prefix_len, index),
+                _ => self.v6.insert(bits, width, prefix_len, index),
+            }
+            IndicatorKind::Network
+        } else if value.contains('.') && !value.contains(['/', ':', ' ']) {
+            self.domains.insert(value.trim_start_matches("*."), self.indicators.len());
+            IndicatorKind::Domain
+        } else {
+            return;
+        };
+        self.indicators.push(Indicator {
+            value,
+            kind,
+            feed: feed.to_string(),
+            description: description.to_string(),
+            severity: severity.to_string(),
+            stix_id,
+        });
+    }
+
+    pub fn match_ip(&self, ip: &str) -> Option<&Indicator> {
+        let index = match ip.parse::<IpAddr>().ok()? {
+            IpAddr::V4(v4) => self.v4.longest_match(u32::from(v4) as u128, 32),
+            IpAddr::V6(v6) => self.v6.longest_match(u128::from(v6), 128),
+        }?;
+        self.indicators.get(index)
+    }
+
+    pub fn match_domain(&self, name: &str) -> Option<&Indicator> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        self.indicators.get(self.domains.longest_match(&name)?)
+    }
+}
+
+fn feed_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if matches!(path.extension().and_then(|e| e.to_str()), Some("txt" | "list" | "csv" | "json")) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The feeds loaded at first use, shared by the matcher and alert scoring.
+pub fn store() -> &'static IntelStore {
+    static STORE: OnceLock<IntelStore> = OnceLock::new();
+    STORE.get_or_init(IntelStore::from_env)
+}
+
+/// An address or CIDR as (bits, address width, prefix length).
+pub fn parse_network(value: &str) -> Option<(u128, u32, u32)> {
+    let (address, prefix_len) = match value.split_once('/') {
+        Some((address, len)) => (address, Some(len.parse::<u32>().ok()?)),
+        None => (value, None),
+    };
+    let (bits, width) = match address.parse::<IpAddr>().ok()? {
+        IpAddr::V4(v4) => (u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    };
+    let prefix_len = prefix_len.unwrap_or(width);
+    (prefix_len <= width).then_some((bits, width, prefix_len))
+}
+
+/// Whether `ip` falls in the address or CIDR `cidr`.
+pub fn cidr_contains(cidr: &str, ip: IpAddr) -> bool {
+    let (network, width, prefix_len) = match parse_network(cidr) {
+        Some(network) => network,
+        None => return false,
+    };
+    let (bits, ip_width) = match ip {
+        IpAddr::V4(v4) => (u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    };
+    if width != ip_width {
+        return false;
+    }
+    let shift = width - prefix_len;
+    prefix_len == 0 || network >> shift == bits >> shift
+}
+
+/// Reads the indicators in a feed file, failing on the first malformed entry.
+fn read_feed(path: &Path) -> Result<Vec<Listing>, Box<dyn Error + Send + Sync>> {
+    let contents = fs::read_to_string(path)?;
+    parse_feed(&contents, path.extension().and_then(|e| e.to_str()))
+}
+
+fn parse_feed(contents: &str, extension: Option<&str>) -> Result<Vec<Listing>, Box<dyn Error + Send + Sync>> {
+    let mut listings = Vec::new();
+    match extension {
+        Some("csv") => {
+            let mut reader = csv::ReaderBuilder::new().flexible(true).trim(csv::Trim::All).from_reader(contents.as_bytes());
+            for entry in reader.deserialize::<FeedEntry>() {
+                listings.push(entry_listing(entry?));
+            }
+        }
+        Some("json") => match serde_json::from_str::<Value>(contents)? {
+            Value::Object(bundle) if bundle.get("type").and_then(Value::as_str) == Some("bundle") => {
+                let objects = bundle.get("objects").and_then(Value::as_array).cloned().unwrap_or_default();
+                for object in &objects {
+                    stix_listings(object, &mut listings);
+                }
+            }
+            Value::Array(entries) => {
+                for entry in entries {
+                    listings.push(entry_listing(serde_json::from_value(entry)?));
+                }
+            }
+            _ => return Err("expected a STIX bundle or an array of indicators".into()),
+        },
+        _ => {
+            for line in contents.lines() {
+                let line = line.split('#').next().unwrap_or("").trim();
+                let (value, description) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                if !value.is_empty() {
+                    listings.push(Listing {
+                        value: value.to_string(),
+                        description: description.trim().to_string(),
+                        severity: DEFAULT_SEVERITY.to_string(),
+                        stix_id: None,
+                    });
+                }
+            }
+        }
+    }
+    Ok(listings)
+}
+
+fn entry_listing(entry: FeedEntry) -> Listing {
+    let value = match entry.kind.as_deref().map(str::to_lowercase).as_deref() {
+        Some("url") => url_host(&entry.indicator).unwrap_or_default(),
+        _ => entry.indicator,
+    };
+    Listing {
+        value,
+        description: entry.description.unwrap_or_default(),
+        severity: entry.severity.map(|s| s.to_lowercase()).unwrap_or_else(|| DEFAULT_SEVERITY.to_string()),
+        stix_id: None,
+    }
+}
+
+/// The value comparisons in a STIX 2.1 indicator's pattern.
+fn stix_listings(object: &Value, listings: &mut Vec<Listing>) {
+    let field = |name: &str| object.get(name).and_then(Value::as_str);
+    if field("type") != Some("indicator")
+        || field("pattern_type").is_some_and(|t| t != "stix")
+        || object.get("revoked").and_then(Value::as_bool) == Some(true)
+    {
+        return;
+    }
+    let pattern = match field("pattern") {
+        Some(pattern) => pattern,
+        None => return,
+    };
+    let description = field("name").or(field("description")).unwrap_or("");
+    let stix_id = field("id").map(str::to_string);
+
+    for captures in stix_comparison().captures_iter(pattern) {
+        let value = captures[2].replace("\\'", "'");
+        let value = match &captures[1] {
+            "url" => match url_host(&value) {
+                Some(host) => host,
+                None => continue,
+            },
+            _ => value,
+        };
+        listings.push(Listing {
+            value,
+            description: description.to_string(),
+            severity: DEFAULT_SEVERITY.to_string(),
+            stix_id: stix_id.clone(),
+        });
+    }
+}
+
+/// The host part of a URL indicator.
+fn url_host(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+fn stix_comparison() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(ipv4-addr|ipv6-addr|domain-name|url):value\s*=\s*'((?:[^'\\]|\\.)*)'").expect("valid STIX pattern regex")
+    })
+}
+
+/// Matches captured events against an IntelStore.
+pub struct IntelMatcher {
+    store: &'static IntelStore,
+    last_alert: HashMap<String, f64>,
+}
+
+impl IntelMatcher {
+    pub fn new(store: &'static IntelStore) -> Self {
+        IntelMatcher {
+            store,
+            last_alert: HashMap::new(),
+        }
+    }
+
+    fn alert(&mut self, indicator: &Indicator, observed: &str, host: &str, context: &str, now: f64, detections: &mut Vec<Detection>) {
+        let key = format!("{}|{}", indicator.value, host);
+        if self.last_alert.get(&key).is_some_and(|t| now - t < ALERT_COOLDOWN) {
+            return;
+        }
+        self.last_alert.insert(key, now);
+
+        let mut details = format!("{} {} matched {} indicator {}", context, observed, indicator.feed, indicator.value);
+        if !indicator.description.is_empty() {
+            details.push_str(&format!(" ({})", indicator.description));
+        }
+        let mut vars = HashMap::from([
+            ("feed".to_string(), indicator.feed.clone()),
+            ("intel_indicator".to_string(), indicator.value.clone()),
+            ("intel_description".to_string(), indicator.description.clone()),
+            ("intel_type".to_string(), indicator.kind.as_str().to_string()),
+        ]);
+        if let Some(stix_id) = &indicator.stix_id {
+            vars.insert("stix_id".to_string(), stix_id.clone());
+        }
+        detections.push(Detection {
+            activity: SuspiciousActivity {
+                activity_type: "Threat Intel Match".to_string(),
+                source: host.to_string(),
+                details,
+                indicator: observed.to_string(),
+                magnitude: 1.0,
+                severity: Severity::parse(&indicator.severity).unwrap_or(Severity::Medium),
+                score: 0.0,
+                // Feeds go stale and get shared infrastructure wrong
+                confidence: 0.8,
+                evidence: Evidence {
+                    count: Some(1.0),
+                    peers: if indicator.kind == IndicatorKind::Network { vec![observed.to_string()] } else { Vec::new() },
+                    domains: if indicator.kind == IndicatorKind::Domain { vec![observed.to_string()] } else { Vec::new() },
+                    ..Evidence::default()
+                },
+                rule_id: "threat_intel".to_string(),
+                timestamp: now,
+            },
+            actions: Vec::new(),
+            vars,
+        });
+    }
+}
+
+impl StreamDetector for IntelMatcher {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        if self.store.is_empty() || event.arp.is_some() {
+            return;
+        }
+        let now = event.timestamp;
+        let source = split_endpoint(&event.source).map(|(ip, _)| ip).unwrap_or(&event.source);
+        let destination = split_endpoint(&event.destination).map(|(ip, _)| ip).unwrap_or(&event.destination);
+
+        // The host reported is the other end of the conversation
+        if let Some(indicator) = self.store.match_ip(destination).cloned() {
+            self.alert(&indicator, destination, source, &format!("{} traffic to", event.protocol), now, detections);
+        }
+        if let Some(indicator) = self.store.match_ip(source).cloned() {
+            self.alert(&indicator, source, destination, &format!("{} traffic from", event.protocol), now, detections);
+        }
+
+        if let Some(message) = &event.dns {
+            let client = if message.is_response { destination } else { source };
+            for question in &message.questions {
+                if let Some(indicator) = self.store.match_domain(&question.name).cloned() {
+                    self.alert(&indicator, &question.name, client, "DNS lookup of", now, detections);
+                }
+            }
+            for record in &message.answers {
+                let indicator = match record.rtype {
+                    dns::TYPE_A | dns::TYPE_AAAA => self.store.match_ip(&record.data),
+                    dns::TYPE_CNAME => self.store.match_domain(&record.data),
+                    _ => None,
+                };
+                if let Some(indicator) = indicator.cloned() {
+                    let context = format!("{} answer", record.name);
+                    self.alert(&indicator, &record.data, client, &context, now, detections);
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        self.last_alert.retain(|_, t| now - *t < ALERT_COOLDOWN);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(values: &[&str]) -> IntelStore {
+        let mut store = IntelStore::default();
+        for value in values {
+            store.add(value, "", DEFAULT_SEVERITY, "test", None);
+        }
+        store
+    }
+
+    fn matched_ip<'a>(store: &'a IntelStore, ip: &str) -> Option<&'a str> {
+        store.match_ip(ip).map(|i| i.value.as_str())
+    }
+
+    fn matched_domain<'a>(store: &'a IntelStore, name: &str) -> Option<&'a str> {
+        store.match_domain(name).map(|i| i.value.as_str())
+    }
+
+    #[test]
+    fn prefix_trie_prefers_the_longest_prefix() {
+        let store = store(&["10.0.0.0/8", "10.1.0.0/16", "10.1.2.3", "2001:db8::/32", "2001:db8:1::/48"]);
+        assert_eq!(matched_ip(&store, "10.9.9.9"), Some("10.0.0.0/8"));
+        assert_eq!(matched_ip(&store, "10.1.9.9"), Some("10.1.0.0/16"));
+        assert_eq!(matched_ip(&store, "10.1.2.3"), Some("10.1.2.3"));
+        assert_eq!(matched_ip(&store, "11.0.0.1"), None);
+        assert_eq!(matched_ip(&store, "2001:db8:1::5"), Some("2001:db8:1::/48"));
+        assert_eq!(matched_ip(&store, "2001:db8:2::5"), Some("2001:db8::/32"));
+        // v4 prefixes never match v6 addresses with the same leading bits
+        assert_eq!(matched_ip(&store, "a00::1"), None);
+    }
+
+    #[test]
+    fn prefix_trie_handles_zero_length_and_empty() {
+        let mut trie = PrefixTrie::default();
+        assert_eq!(trie.longest_match(1, 32), None);
+        trie.insert(0, 32, 0, 7);
+        assert_eq!(trie.longest_match(u32::MAX as u128, 32), Some(7));
+
+        let store = store(&["0.0.0.0/0"]);
+        assert_eq!(matched_ip(&store, "198.51.100.1"), Some("0.0.0.0/0"));
+        assert_eq!(matched_ip(&store, "not an ip"), None);
+    }
+
+    #[test]
+    fn suffix_trie_matches_listed_domains_and_subdomains() {
+        let store = store(&["evil.example", "*.cdn.evil.example", "Bad.Test."]);
+        assert_eq!(matched_domain(&store, "evil.example"), Some("evil.example"));
+        assert_eq!(matched_domain(&store, "a.b.evil.example"), Some("evil.example"));
+        assert_eq!(matched_domain(&store, "x.cdn.evil.example"), Some("*.cdn.evil.example"));
+        assert_eq!(matched_domain(&store, "WWW.BAD.TEST."), Some("bad.test"));
+        // A parent of a listed domain or a lookalike label does not match
+        assert_eq!(matched_domain(&store, "example"), None);
+        assert_eq!(matched_domain(&store, "notevil.example"), None);
+        assert_eq!(SuffixTrie::default().longest_match("evil.example"), None);
+    }
+
+    #[test]
+    fn first_listing_wins() {
+        let mut store = IntelStore::default();
+        store.add("evil.example", "first", DEFAULT_SEVERITY, "a", None);
+        store.add("evil.example", "second", DEFAULT_SEVERITY, "b", None);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.match_domain("evil.example").map(|i| i.feed.as_str()), Some("a"));
+    }
+
+    fn values(listings: &[Listing]) -> Vec<&str> {
+        listings.iter().map(|l| l.value.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_plain_lists() {
+        let listings = parse_feed("# bad hosts\n203.0.113.7  scanner seen in logs\n\nevil.example # c2\n", Some("txt")).unwrap();
+        assert_eq!(values(&listings), ["203.0.113.7", "evil.example"]);
+        assert_eq!(listings[0].description, "scanner seen in logs");
+        assert_eq!(listings[1].severity, DEFAULT_SEVERITY);
+    }
+
+    #[test]
+    fn reads_csv_feeds() {
+        let csv = "ioc, type, description, severity\n198.51.100.0/24, ip, botnet, Medium\nhttps://phish.example/login, url, phishing,\nevil.example\n";
+        let listings = parse_feed(csv, Some("csv")).unwrap();
+        assert_eq!(values(&listings), ["198.51.100.0/24", "phish.example", "evil.example"]);
+        assert_eq!(listings[0].severity, "medium");
+        assert_eq!(listings[1].description, "phishing");
+        assert_eq!(listings[2].severity, DEFAULT_SEVERITY);
+
+        assert!(parse_feed("description\nno indicator column\n", Some("csv")).is_err());
+    }
+
+    #[test]
+    fn reads_json_arrays() {
+        let json = r#"[{"indicator": "evil.example", "severity": "LOW"}, {"value": "http://[2001:db8::1]/x", "type": "URL"}]"#;
+        let listings = parse_feed(json, Some("json")).unwrap();
+        assert_eq!(values(&listings), ["evil.example", "2001:db8::1"]);
+        assert_eq!(listings[0].severity, "low");
+
+        assert!(parse_feed(r#"[{"description": "no indicator"}]"#, Some("json")).is_err());
+        assert!(parse_feed(r#"{"type": "not-a-bundle"}"#, Some("json")).is_err());
+        assert!(parse_feed("not json", Some("json")).is_err());
+    }
+
+    #[test]
+    fn reads_stix_bundles() {
+        let bundle = r#"{
+            "type": "bundle",
+            "objects": [
+                {"type": "indicator", "id": "indicator--1", "name": "C2 servers", "pattern_type": "stix",
+                 "pattern": "[ipv4-addr:value = '203.0.113.9'] OR [domain-name:value = 'o\\'brien.example'] OR [url:value = 'https://c2.example:8443/beacon']"},
+                {"type": "indicator", "id": "indicator--2", "revoked": true, "pattern": "[domain-name:value = 'revoked.example']"},
+                {"type": "indicator", "id": "indicator--3", "pattern_type": "sigma", "pattern": "[domain-name:value = 'sigma.example']"},
+                {"type": "malware", "id": "malware--1", "pattern": "[domain-name:value = 'malware.example']"},
+                {"type": "indicator", "id": "indicator--4", "pattern": "[file:hashes.MD5 = 'abc'] AND [ipv6-addr:value = '2001:db8::/32']"}
+            ]
+        }"#;
+        let listings = parse_feed(bundle, Some("json")).unwrap();
+        assert_eq!(values(&listings), ["203.0.113.9", "o'brien.example", "c2.example", "2001:db8::/32"]);
+        assert_eq!(listings[0].description, "C2 servers");
+        assert_eq!(listings[0].stix_id.as_deref(), Some("indicator--1"));
+        assert_eq!(listings[3].stix_id.as_deref(), Some("indicator--4"));
+    }
+
+    #[test]
+    fn a_bad_feed_does_not_disable_the_others() {
+        let dir = env::temp_dir().join(format!("sniff-intel-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("broken.json"), "{ not json").unwrap();
+        fs::write(dir.join("good.txt"), "evil.example\n").unwrap();
+        // The first entry is fine but the file is not loaded in part
+        fs::write(dir.join("partial.json"), r#"[{"indicator": "ok.example"}, {"severity": "high"}]"#).unwrap();
+
+        let store = IntelStore::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.match_domain("www.evil.example").map(|i| i.feed.as_str()), Some("good"));
+        assert!(IntelStore::load(&dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert_eq!(parse_network("10.0.0.0/8"), Some((0x0a00_0000, 32, 8)));
+        assert_eq!(parse_network("::1"), Some((1, 128, 128)));
+        assert_eq!(parse_network("10.0.0.0/33"), None);
+        assert!(cidr_contains("10.0.0.0/8", "10.2.3.4".parse().unwrap()));
+        assert!(!cidr_contains("10.0.0.0/8", "::1".parse().unwrap()));
+        assert_eq!(url_host("https://user@[2001:db8::1]:8443/x").as_deref(), Some("2001:db8::1"));
+        assert_eq!(url_host("http://evil.example:8080/path?q").as_deref(), Some("evil.example"));
+    }
+}
@@ -1,50 +1,236 @@
 use reqwest::blocking::Client;
-use serde::Serialize;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::env;
+use std::error::Error;
 use dotenv::dotenv;
+use mongodb::bson::oid::ObjectId;
 
+pub mod budget;
+pub mod redact;
+pub mod templates;
+pub mod verdict;
 
-#[derive(Debug, Clone, Serialize)]
+use budget::TokenUsage;
+use redact::Pseudonymizer;
+use templates::RenderedPrompt;
+use verdict::{TriageVerdict, VERDICT_SCHEMA};
+
+const MODEL: &str = "meta-llama/Llama-3-8b-chat-hf";
+const TRIAGE_RETRIES: usize = 2;
+const DEFAULT_LOCAL_URL: &str = "http://localhost:11434/v1/chat/completions";
+const DEFAULT_LOCAL_MODEL: &str = "llama3";
+
+/// An OpenAI-compatible chat completions endpoint.
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub name: String,
+    pub url: String,
+    pub model: String,
+    pub api_key: Option<String>,
+}
+
+impl Provider {
+    /// The hosted Together endpoint, if `TOGETHER_API_KEY` is set.
+    pub fn together() -> Option<Self> {
+        dotenv().ok();
+        let api_key = env::var("TOGETHER_API_KEY").ok()?;
+        Some(Provider {
+            name: "together".to_string(),
+            url: "https://api.together.xyz/v1/chat/completions".to_string(),
+            model: MODEL.to_string(),
+            api_key: Some(api_key),
+        })
+    }
+
+    /// A model served on this host (Ollama by default), configured with
+    /// `LLM_LOCAL_URL` and `LLM_LOCAL_MODEL`. Prompts still go through the
+    /// pseudonymizer so switching providers never changes what is sent.
+    pub fn local() -> Self {
+        dotenv().ok();
+        Provider {
+            name: "local".to_string(),
+            url: env::var("LLM_LOCAL_URL").unwrap_or_else(|_| DEFAULT_LOCAL_URL.to_string()),
+            model: env::var("LLM_LOCAL_MODEL").unwrap_or_else(|_| DEFAULT_LOCAL_MODEL.to_string()),
+            api_key: env::var("LLM_LOCAL_API_KEY").ok(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LlmInference {
     pub payload: String,
     pub response: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verdict: Option<TriageVerdict>,
+    /// The `sus_events` document this inference was produced for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_id: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// Which prompt template (and revision) produced this inference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Tokens used across all attempts for this inference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
 }
 
 impl LlmInference {
-    pub fn new() {
+    pub fn new(payload: String, response: String) -> Self {
+        LlmInference {
+            payload,
+            response,
+            verdict: None,
+            alert_id: None,
+            fingerprint: None,
+            template_id: None,
+            template_version: None,
+            provider: None,
+            usage: None,
+        }
     }
-    pub fn get_inference(input: &String) -> Result<String, reqwest::Error> {
-        dotenv().ok();
-        let api_key = env::var("TOGETHER_API_KEY").expect("TOGETHER_API_KEY must be set");
 
-        let response = Client::new()
-            .post("https://api.together.xyz/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", api_key))
-            .json(&json!({
-                "model": "meta-llama/Llama-3-8b-chat-hf",
-                "messages": [{"role": "user", "content": input}, {"role": "system", "content": "You are a threat AI detection model. You are being given potentially suspicious packet data. Analyze the incoming address with external internet sources"},],
-                "temperature": 0.7,
-                "max_tokens": 128
-            }))
-            .send()?
-            .json::<serde_json::Value>()?;
+    /// Sends `input` to the model with internal identifiers pseudonymized.
+    /// The returned text has the real identifiers restored, so it is only
+    /// suitable for local storage and display.
+    pub fn get_inference(input: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let mut pseudonymizer = Pseudonymizer::from_env()?;
+        let messages = vec![
+            json!({"role": "system", "content": "You are a threat AI detection model. You are being given potentially suspicious packet data. You have no internet access; analyze it from the data provided."}),
+            json!({"role": "user", "content": pseudonymizer.redact(input)}),
+        ];
+
+        let provider = Provider::together().expect("TOGETHER_API_KEY must be set");
+        let (content, _) = chat(&provider, &messages, 0.7, 128)?;
+        Ok(pseudonymizer.restore(&content))
+    }
+
+    /// Asks the model for a JSON triage verdict on a rendered alert prompt.
+    /// Responses that do not match the schema are sent back with the
+    /// validation errors, up to `TRIAGE_RETRIES` times; if none validate the
+    /// raw text is kept and `verdict` is left empty.
+    pub fn triage(provider: &Provider, prompt: &RenderedPrompt) -> Result<LlmInference, Box<dyn Error + Send + Sync>> {
+        let mut pseudonymizer = Pseudonymizer::from_env()?;
+        let mut messages = vec![
+            json!({"role": "system", "content": format!(
+                "{}\n\nRespond with a single JSON object and nothing else, matching this schema:\n{}",
+                pseudonymizer.redact(&prompt.system), VERDICT_SCHEMA)}),
+            json!({"role": "user", "content": pseudonymizer.redact(&prompt.user)}),
+        ];
+
+        let mut inference = LlmInference::new(prompt.user.clone(), String::new());
+        inference.template_id = Some(prompt.template_id.clone());
+        inference.template_version = Some(prompt.template_version);
+        inference.provider = Some(provider.name.clone());
+        let mut usage = TokenUsage::default();
+
+        for attempt in 0..=TRIAGE_RETRIES {
+            let (content, attempt_usage) = chat(provider, &messages, 0.2, 512)?;
+            usage.add(attempt_usage);
+            inference.usage = Some(usage);
+            inference.response = pseudonymizer.restore(&content);
+            match validate_response(&pseudonymizer, &content) {
+                Ok(verdict) => {
+                    inference.verdict = Some(verdict);
+                    break;
+                }
+                Err(e) if attempt < TRIAGE_RETRIES => {
+                    messages.push(json!({"role": "assistant", "content": content}));
+                    messages.push(json!({"role": "user", "content": retry_message(&e)}));
+                }
+                Err(e) => eprintln!("LLM verdict failed validation after {} attempts: {}", attempt + 1, e),
+            }
+        }
 
-        // Print raw response before parsing
-        let text = response.to_string();
-        println!("Raw response: {}", text);
+        Ok(inference)
+    }
+}
 
-        Ok(response["choices"][0]["message"]["content"]
-            .as_str()
-            .unwrap_or("No response")
-            .to_string())
+/// Validates the response as the model sent it, so validation errors only
+/// quote pseudonyms, then restores the identifiers in the verdict's text.
+fn validate_response(pseudonymizer: &Pseudonymizer, content: &str) -> Result<TriageVerdict, String> {
+    let mut verdict = TriageVerdict::parse(content)?;
+    verdict.category = pseudonymizer.restore(&verdict.category);
+    verdict.rationale = pseudonymizer.restore(&verdict.rationale);
+    for action in &mut verdict.suggested_actions {
+        *action = pseudonymizer.restore(action);
     }
+    Ok(verdict)
 }
 
+fn retry_message(error: &str) -> String {
+    format!("That response was invalid: {}. Reply again with only the corrected JSON object.", error)
+}
+
+fn chat(provider: &Provider, messages: &[Value], temperature: f64, max_tokens: u32) -> Result<(String, TokenUsage), Box<dyn Error + Send + Sync>> {
+    let mut request = Client::new().post(&provider.url);
+    if let Some(api_key) = &provider.api_key {
+        request = request.header("Authorization", format!("Bearer {}", api_key));
+    }
+
+    let response = request
+        .json(&json!({
+            "model": provider.model,
+            "messages": messages,
+            "temperature": temperature,
+            "max_tokens": max_tokens
+        }))
+        .send()?
+        .json::<Value>()?;
+
+    tracing::debug!(response = %response, "LLM provider response");
+
+    let content = response["choices"][0]["message"]["content"]
+        .as_str()
+        .unwrap_or("No response")
+        .to_string();
+
+    let prompt: String = messages.iter().filter_map(|m| m["content"].as_str()).collect();
+    let usage = TokenUsage::from_response(&response, &prompt, &content);
+    Ok((content, usage))
+}
 
 // pub fn run(input: String) {
 //     match get_inference(&input) {
 //         Ok(response) => println!("{}", response.to_string()),
 //         Err(err) => eprintln!("Error: {}", err),
 //     }
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_message_only_quotes_pseudonyms() {
+        let mut pseudonymizer = Pseudonymizer::new(b"test key".to_vec(), false);
+        let prompt = pseudonymizer.redact("10.0.0.5 scanned 10.0.0.9");
+        let token = prompt.split_whitespace().next().unwrap().to_string();
+
+        // The model put a host where the severity belongs
+        let content = format!(r#"{{"severity": "{}", "confidence": 0.8, "category": "recon", "rationale": "x", "suggested_actions": []}}"#, token);
+        let error = validate_response(&pseudonymizer, &content).unwrap_err();
+        let message = retry_message(&error);
+        assert!(message.contains(&token));
+        assert!(!message.contains("10.0.0.5"));
+    }
+
+    #[test]
+    fn valid_verdicts_are_restored() {
+        let mut pseudonymizer = Pseudonymizer::new(b"test key".to_vec(), false);
+        let token = pseudonymizer.redact("10.0.0.5");
+        let content = format!(
+            r#"{{"severity": "high", "confidence": 0.8, "category": "Recon", "rationale": "{0} scanned the LAN", "suggested_actions": ["isolate {0}"]}}"#,
+            token
+        );
+        let verdict = validate_response(&pseudonymizer, &content).unwrap();
+        assert_eq!(verdict.category, "recon");
+        assert_eq!(verdict.rationale, "10.0.0.5 scanned the LAN");
+        assert_eq!(verdict.suggested_actions, ["isolate 10.0.0.5"]);
+    }
+}
@@ -0,0 +1,211 @@
+//! Token accounting and per-provider spend limits.
+//!
+//! Usage is bucketed by provider and UTC hour in the `llm_usage` collection so
+//! the counters survive restarts. Limits come from the environment, e.g.
+//! `LLM_BUDGET_TOGETHER_DAILY_TOKENS`, `LLM_BUDGET_TOGETHER_HOURLY_TOKENS` and
+//! `LLM_COST_TOGETHER_PER_1K_TOKENS`; a provider without limits is unbounded.
+
+use futures::StreamExt;
+use mongodb::{bson::doc, Collection};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+
+const SECS_PER_HOUR: i64 = 3600;
+const HOURS_PER_DAY: i64 = 24;
+// Rough English/JSON average, used when the provider omits `usage`
+const CHARS_PER_TOKEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// True when the counts were estimated locally rather than reported
+    pub estimated: bool,
+}
+
+impl TokenUsage {
+    /// Reads the provider's `usage` block, estimating from text length when
+    /// it is missing.
+    pub fn from_response(response: &Value, prompt: &str, completion: &str) -> Self {
+        let usage = &response["usage"];
+        match (usage["prompt_tokens"].as_u64(), usage["completion_tokens"].as_u64()) {
+            (Some(prompt_tokens), Some(completion_tokens)) => TokenUsage {
+                prompt_tokens,
+                completion_tokens,
+                estimated: false,
+            },
+            _ => TokenUsage {
+                prompt_tokens: estimate_tokens(prompt),
+                completion_tokens: estimate_tokens(completion),
+                estimated: true,
+            },
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn add(&mut self, other: TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.estimated |= other.estimated;
+    }
+}
+
+pub fn estimate_tokens(text: &str) -> u64 {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN) as u64
+}
+
+/// One provider's usage during one UTC hour, as persisted in `llm_usage`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub provider: String,
+    /// Unix timestamp of the start of the hour
+    pub hour: i64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub requests: u64,
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BudgetLimits {
+    pub daily_tokens: Option<u64>,
+    pub hourly_tokens: Option<u64>,
+    pub cost_per_1k_tokens: f64,
+}
+
+impl BudgetLimits {
+    pub fn from_env(provider: &str) -> Self {
+        let key = provider.to_uppercase();
+        let daily = env::var(format!("LLM_BUDGET_{}_DAILY_TOKENS", key)).ok();
+        let hourly = env::var(format!("LLM_BUDGET_{}_HOURLY_TOKENS", key)).ok();
+        let cost = env::var(format!("LLM_COST_{}_PER_1K_TOKENS", key)).ok();
+
+        BudgetLimits {
+            daily_tokens: daily.and_then(|v| v.parse().ok()),
+            hourly_tokens: hourly.and_then(|v| v.parse().ok()),
+            cost_per_1k_tokens: cost.and_then(|v| v.parse().ok()).unwrap_or(0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetStatus {
+    pub provider: String,
+    pub hourly_tokens: u64,
+    pub hourly_limit: Option<u64>,
+    pub daily_tokens: u64,
+    pub daily_limit: Option<u64>,
+    pub daily_requests: u64,
+    pub daily_cost_usd: f64,
+    pub exhausted: bool,
+}
+
+pub struct TokenBudget {
+    limits: HashMap<String, BudgetLimits>,
+    usage: HashMap<(String, i64), UsageRecord>,
+    collection: Collection<UsageRecord>,
+}
+
+impl TokenBudget {
+    /// Loads today's counters for `providers` and their limits from the environment.
+    pub async fn load(collection: Collection<UsageRecord>, providers: &[&str], now: f64) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let limits = providers
+            .iter()
+            .map(|p| (p.to_string(), BudgetLimits::from_env(p)))
+            .collect();
+
+        let day_start = day_of(hour_of(now)) * HOURS_PER_DAY * SECS_PER_HOUR;
+        let mut cursor = collection.find(doc! { "hour": { "$gte": day_start } }).await?;
+        let mut usage = HashMap::new();
+        while let Some(record) = cursor.next().await {
+            let record = record?;
+            usage.insert((record.provider.clone(), record.hour), record);
+        }
+
+        Ok(TokenBudget { limits, usage, collection })
+    }
+
+    pub fn allows(&self, provider: &str, now: f64) -> bool {
+        !self.status(provider, now).exhausted
+    }
+
+    /// Adds a request's usage to the current hour and persists the bucket.
+    pub async fn record(&mut self, provider: &str, usage: TokenUsage, now: f64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let hour = hour_of(now);
+        let cost_per_1k = self.limits.get(provider).map(|l| l.cost_per_1k_tokens).unwrap_or(0.0);
+        let cost = usage.total() as f64 / 1000.0 * cost_per_1k;
+        self.usage.retain(|(_, bucket), _| day_of(*bucket) == day_of(hour));
+
+        let record = self.usage.entry((provider.to_string(), hour)).or_insert_with(|| UsageRecord {
+            provider: provider.to_string(),
+            hour,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            requests: 0,
+            cost_usd: 0.0,
+        });
+        record.prompt_tokens += usage.prompt_tokens;
+        record.completion_tokens += usage.completion_tokens;
+        record.requests += 1;
+        record.cost_usd += cost;
+
+        self.collection
+            .update_one(
+                doc! { "provider": provider, "hour": hour },
+                doc! { "$inc": {
+                    "prompt_tokens": usage.prompt_tokens as i64,
+                    "completion_tokens": usage.completion_tokens as i64,
+                    "requests": 1_i64,
+                    "cost_usd": cost,
+                }},
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    pub fn status(&self, provider: &str, now: f64) -> BudgetStatus {
+        let hour = hour_of(now);
+        let limits = self.limits.get(provider).cloned().unwrap_or_default();
+
+        let mut status = BudgetStatus {
+            provider: provider.to_string(),
+            hourly_tokens: 0,
+            hourly_limit: limits.hourly_tokens,
+            daily_tokens: 0,
+            daily_limit: limits.daily_tokens,
+            daily_requests: 0,
+            daily_cost_usd: 0.0,
+            exhausted: false,
+        };
+        for ((name, bucket), record) in &self.usage {
+            if name != provider || day_of(*bucket) != day_of(hour) {
+                continue;
+            }
+            let tokens = record.prompt_tokens + record.completion_tokens;
+            status.daily_tokens += tokens;
+            status.daily_requests += record.requests;
+            status.daily_cost_usd += record.cost_usd;
+            if *bucket == hour {
+                status.hourly_tokens += tokens;
+            }
+        }
+        status.exhausted = limits.hourly_tokens.is_some_and(|l| status.hourly_tokens >= l)
+            || limits.daily_tokens.is_some_and(|l| status.daily_tokens >= l);
+        status
+    }
+}
+
+fn hour_of(timestamp: f64) -> i64 {
+    (timestamp as i64).div_euclid(SECS_PER_HOUR) * SECS_PER_HOUR
+}
+
+fn day_of(hour: i64) -> i64 {
+    hour.div_euclid(SECS_PER_HOUR * HOURS_PER_DAY)
+}
@@ -0,0 +1,257 @@
+//! Keyed pseudonymization for anything that leaves the host in an LLM prompt.
+//!
+//! Internal IPs, MACs and hostnames are swapped for stable tokens such as
+//! `ip-3f9a12bc` (HMAC-SHA256 over the identifier with a local secret), so the
+//! model can still tell hosts apart without learning who they are. The same
+//! `Pseudonymizer` restores the real identifiers in the model's answer.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use regex::{Captures, Regex};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+const KEY_ENV: &str = "LLM_PSEUDONYM_KEY";
+const KEY_FILE_ENV: &str = "LLM_PSEUDONYM_KEY_FILE";
+const KEEP_PUBLIC_ENV: &str = "LLM_KEEP_PUBLIC";
+const DEFAULT_KEY_FILE: &str = ".sniff_pseudonym.key";
+
+const MAC_PATTERN: &str = r"\b(?:[0-9A-Fa-f]{2}[:-]){5}[0-9A-Fa-f]{2}\b";
+// Whole runs of word, dot and colon characters that contain a colon; only a
+// run that parses as an address in full is replaced, so `std::fs` or
+// `12:30:45` are left alone
+const IPV6_PATTERN: &str = r"[0-9A-Za-z_.:]*:[0-9A-Za-z_.:]*";
+const IPV4_PATTERN: &str = r"\b(?:\d{1,3}\.){3}\d{1,3}\b";
+const HOST_PATTERN: &str = r"\b(?:[A-Za-z0-9](?:[A-Za-z0-9-]{0,61}[A-Za-z0-9])?\.)+[A-Za-z][A-Za-z0-9-]{1,62}\b";
+const TOKEN_PATTERN: &str = r"\b(?:ip|ip6|mac|host)-[0-9a-f]{8}\b";
+
+// Suffixes that only make sense on a private network
+const INTERNAL_SUFFIXES: [&str; 8] = [
+    ".local", ".lan", ".home", ".internal", ".corp", ".localdomain", ".home.arpa", ".in-addr.arpa",
+];
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+    keep_public: bool,
+    pseudonyms: HashMap<String, String>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: Vec<u8>, keep_public: bool) -> Self {
+        Pseudonymizer {
+            key,
+            keep_public,
+            pseudonyms: HashMap::new(),
+        }
+    }
+
+    /// Builds a pseudonymizer from `LLM_PSEUDONYM_KEY`, falling back to a key
+    /// file that is generated on first use so pseudonyms stay stable across runs.
+    pub fn from_env() -> io::Result<Self> {
+        let key = match env::var(KEY_ENV) {
+            Ok(key) if !key.is_empty() => key.into_bytes(),
+            _ => {
+                let path = env::var(KEY_FILE_ENV).unwrap_or_else(|_| DEFAULT_KEY_FILE.to_string());
+                load_or_create_key(Path::new(&path))?
+            }
+        };
+        let keep_public = env::var(KEEP_PUBLIC_ENV)
+            .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+
+        Ok(Self::new(key, keep_public))
+    }
+
+    /// Replaces identifying values in `text` with keyed pseudonyms.
+    pub fn redact(&mut self, text: &str) -> String {
+        let text = self.replace(text, MAC_PATTERN, |this, value| Some(this.pseudonym("mac", &value.to_uppercase().replace('-', ":"))));
+        let text = self.replace(&text, IPV6_PATTERN, |this, value| {
+            // A sentence may end right after the address
+            let address = value.trim_end_matches('.');
+            let ip = address.parse::<Ipv6Addr>().ok()?;
+            this.redact_ip(IpAddr::V6(ip)).map(|token| token + &value[address.len()..])
+        });
+        let text = self.replace(&text, IPV4_PATTERN, |this, value| {
+            let ip = value.parse::<Ipv4Addr>().ok()?;
+            this.redact_ip(IpAddr::V4(ip))
+        });
+        self.replace(&text, HOST_PATTERN, |this, value| {
+            let host = value.to_lowercase();
+            if this.keep_public && !is_internal_hostname(&host) {
+                return None;
+            }
+            Some(this.pseudonym("host", &host))
+        })
+    }
+
+    /// Maps pseudonyms in a model response back to the identifiers they replaced.
+    pub fn restore(&self, text: &str) -> String {
+        let re = Regex::new(TOKEN_PATTERN).expect("valid token pattern");
+        re.replace_all(text, |caps: &Captures| {
+            let token = &caps[0];
+            self.pseudonyms.get(token).cloned().unwrap_or_else(|| token.to_string())
+        })
+        .into_owned()
+    }
+
+    fn redact_ip(&mut self, ip: IpAddr) -> Option<String> {
+        if self.keep_public && !is_internal_ip(&ip) {
+            return None;
+        }
+        let kind = if ip.is_ipv4() { "ip" } else { "ip6" };
+        Some(self.pseudonym(kind, &ip.to_string()))
+    }
+
+    fn pseudonym(&mut self, kind: &str, value: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(kind.as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let token = format!("{}-{}", kind, hex::encode(&digest[..4]));
+        self.pseudonyms.insert(token.clone(), value.to_string());
+        token
+    }
+
+    fn replace<F>(&mut self, text: &str, pattern: &str, mut f: F) -> String
+    where
+        F: FnMut(&mut Self, &str) -> Option<String>,
+    {
+        let re = Regex::new(pattern).expect("valid redaction pattern");
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+        for m in re.find_iter(text) {
+            out.push_str(&text[last..m.start()]);
+            match f(self, m.as_str()) {
+                Some(replacement) => out.push_str(&replacement),
+                None => out.push_str(m.as_str()),
+            }
+            last = m.end();
+        }
+        out.push_str(&text[last..]);
+        out
+    }
+}
+
+/// Reads the hex key at `path`, generating one only if the file does not
+/// exist; an unreadable or malformed file is an error rather than replaced,
+/// since a new key would change every pseudonym.
+fn load_or_create_key(path: &Path) -> io::Result<Vec<u8>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => match hex::decode(contents.trim()) {
+            Ok(key) if !key.is_empty() => Ok(key),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not hold a hex pseudonym key", path.display()),
+            )),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut key = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(path)?.write_all(hex::encode(&key).as_bytes())?;
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub fn is_internal_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_unspecified()
+                || (octets[0] == 100 && (64..128).contains(&octets[1])) // CGNAT
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || (first & 0xfe00) == 0xfc00 // unique local
+                || (first & 0xffc0) == 0xfe80 // link local
+        }
+    }
+}
+
+pub fn is_internal_hostname(host: &str) -> bool {
+    let host = host.trim_end_matches('.');
+    !host.contains('.') || INTERNAL_SUFFIXES.iter().any(|suffix| host.ends_with(suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudonymizer() -> Pseudonymizer {
+        Pseudonymizer::new(b"test key".to_vec(), false)
+    }
+
+    #[test]
+    fn redacts_and_restores_addresses() {
+        let mut p = pseudonymizer();
+        let text = "192.168.1.10 talked to fe80::1 and aa:bb:cc:dd:ee:ff.";
+        let redacted = p.redact(text);
+        assert!(!redacted.contains("192.168.1.10"));
+        assert!(!redacted.contains("fe80::1"));
+        assert!(!redacted.contains("aa:bb:cc:dd:ee:ff"));
+        assert!(redacted.ends_with('.'));
+        assert_eq!(p.restore(&redacted), "192.168.1.10 talked to fe80::1 and AA:BB:CC:DD:EE:FF.");
+    }
+
+    #[test]
+    fn ipv6_needs_a_whole_address() {
+        let mut p = pseudonymizer();
+        for text in ["use std::fs;", "at 12:30:45 today", "a:b:c", "http://example"] {
+            let redacted = p.redact(text);
+            assert!(!redacted.contains("ip6-"), "{} became {}", text, redacted);
+        }
+        assert!(p.redact("from ::1").starts_with("from ip6-"));
+        assert!(p.redact("2001:db8::5 sent").starts_with("ip6-"));
+        assert!(p.redact("[::1]:8080").starts_with("[ip6-"));
+    }
+
+    #[test]
+    fn pseudonyms_are_stable_per_key() {
+        let a = pseudonymizer().redact("10.0.0.1");
+        let b = pseudonymizer().redact("10.0.0.1");
+        let c = Pseudonymizer::new(b"other key".to_vec(), false).redact("10.0.0.1");
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn key_file_is_created_once_and_never_replaced() {
+        let dir = env::temp_dir().join(format!("sniff-key-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("key");
+        let _ = fs::remove_file(&path);
+
+        let key = load_or_create_key(&path).unwrap();
+        assert_eq!(load_or_create_key(&path).unwrap(), key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::write(&path, "not hex").unwrap();
+        assert!(load_or_create_key(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not hex");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
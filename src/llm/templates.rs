@@ -0,0 +1,146 @@
+//! Versioned prompt templates, one per alert `activity_type`.
+//!
+//! The built-in templates under `templates/` are compiled in; YAML files in
+//! `LLM_TEMPLATE_DIR` (default `templates`) override them at runtime, so a
+//! prompt can be tuned without rebuilding. Bump `version` when changing one so
+//! stored inferences can be compared across prompt revisions.
+
+use serde::Deserialize;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::hash::Hash;
+use std::path::Path;
+
+const DEFAULT_TEMPLATE_DIR: &str = "templates";
+
+const BUILTIN_TEMPLATES: [&str; 16] = [
+    include_str!("../../templates/default.yaml"),
+    include_str!("../../templates/port_scanning.yaml"),
+    include_str!("../../templates/arp_spoofing.yaml"),
+    include_str!("../../templates/suspicious_dns.yaml"),
+    include_str!("../../templates/dns_flood.yaml"),
+    include_str!("../../templates/udp_flood.yaml"),
+    include_str!("../../templates/large_data_transfer.yaml"),
+    include_str!("../../templates/rare_port_activity.yaml"),
+    include_str!("../../templates/beaconing.yaml"),
+    include_str!("../../templates/dns_tunneling.yaml"),
+    include_str!("../../templates/fast_flux.yaml"),
+    include_str!("../../templates/nxdomain_storm.yaml"),
+    include_str!("../../templates/threat_intel_match.yaml"),
+    include_str!("../../templates/new_device.yaml"),
+    include_str!("../../templates/rogue_dhcp_server.yaml"),
+    include_str!("../../templates/os_fingerprint_changed.yaml"),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptTemplate {
+    pub id: String,
+    pub version: u32,
+    /// The `SuspiciousActivity::activity_type` this template handles, or `*`
+    pub activity_type: String,
+    pub system: String,
+    pub user: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderedPrompt {
+    pub system: String,
+    pub user: String,
+    pub template_id: String,
+    pub template_version: u32,
+}
+
+pub struct TemplateLibrary {
+    templates: HashMap<String, PromptTemplate>,
+    fallback: PromptTemplate,
+}
+
+impl TemplateLibrary {
+    pub fn builtin() -> Self {
+        let mut templates = HashMap::new();
+        for source in BUILTIN_TEMPLATES {
+            let template: PromptTemplate = serde_yaml::from_str(source).expect("built-in prompt template is valid YAML");
+            templates.insert(template.activity_type.clone(), template);
+        }
+        let fallback = templates.remove("*").expect("built-in default template");
+
+        TemplateLibrary { templates, fallback }
+    }
+
+    /// Built-in templates overridden by any `*.yaml` files in `dir`.
+    pub fn load(dir: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut library = Self::builtin();
+        if !dir.is_dir() {
+            return Ok(library);
+        }
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("yaml") {
+                continue;
+            }
+            let template: PromptTemplate = serde_yaml::from_str(&fs::read_to_string(&path)?)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if template.activity_type == "*" {
+                library.fallback = template;
+            } else {
+                library.templates.insert(template.activity_type.clone(), template);
+            }
+        }
+        Ok(library)
+    }
+
+    pub fn from_env() -> Self {
+        let dir = env::var("LLM_TEMPLATE_DIR").unwrap_or_else(|_| DEFAULT_TEMPLATE_DIR.to_string());
+        Self::load(Path::new(&dir)).unwrap_or_else(|e| {
+            eprintln!("Error loading prompt templates, using built-ins: {}", e);
+            Self::builtin()
+        })
+    }
+
+    pub fn get(&self, activity_type: &str) -> &PromptTemplate {
+        self.templates.get(activity_type).unwrap_or(&self.fallback)
+    }
+
+    /// Fills `{{name}}` placeholders from `vars`. Unknown placeholders are
+    /// left in place so a missing variable is visible in the stored payload.
+    pub fn render(&self, activity_type: &str, vars: &HashMap<&str, String>) -> RenderedPrompt {
+        let template = self.get(activity_type);
+        RenderedPrompt {
+            system: fill(&template.system, vars).trim_end().to_string(),
+            user: fill(&template.user, vars).trim_end().to_string(),
+            template_id: template.id.clone(),
+            template_version: template.version,
+        }
+    }
+}
+
+/// Replaces each `{{name}}` in `text` with its value in `vars`, leaving
+/// unknown placeholders as they are. Shared with detection rule messages.
+pub fn fill<K: Borrow<str> + Eq + Hash>(text: &str, vars: &HashMap<K, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                let name = after[..end].trim();
+                match vars.get(name) {
+                    Some(value) => out.push_str(value),
+                    None => out.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
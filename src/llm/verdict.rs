@@ -0,0 +1,275 @@
+//! Typed triage verdicts and the schema the model's JSON must satisfy.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+pub const VERDICT_SCHEMA: &str = r#"{
+  "severity": "low" | "medium" | "high" | "critical",
+  "confidence": number between 0.0 and 1.0,
+  "category": short string, e.g. "reconnaissance", "spoofing", "exfiltration", "benign",
+  "rationale": one or two sentences explaining the verdict,
+  "suggested_actions": array of short strings
+}"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    // Capitalized forms are what older alerts were stored with
+    #[serde(alias = "Low")]
+    Low,
+    #[serde(alias = "Medium")]
+    Medium,
+    #[serde(alias = "High")]
+    High,
+    #[serde(alias = "Critical")]
+    Critical,
+}
+
+impl Severity {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "low" | "info" | "informational" => Some(Severity::Low),
+            "medium" | "moderate" => Some(Severity::Medium),
+            "high" => Some(Severity::High),
+            "critical" => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriageVerdict {
+    pub severity: Severity,
+    pub confidence: f64,
+    pub category: String,
+    pub rationale: String,
+    pub suggested_actions: Vec<String>,
+}
+
+impl TriageVerdict {
+    /// Parses and validates a model response. On failure the error lists every
+    /// schema violation so it can be fed back to the model for a retry.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let json = extract_json(text).ok_or_else(|| "response does not contain a JSON object".to_string())?;
+        let value: Value = serde_json::from_str(&json)
+            .or_else(|_| serde_json::from_str(&repair_json(&json)))
+            .map_err(|e| format!("invalid JSON: {}", e))?;
+
+        Self::validate(&value)
+    }
+
+    fn validate(value: &Value) -> Result<Self, String> {
+        let mut errors = Vec::new();
+
+        let severity = match value["severity"].as_str() {
+            Some(s) => Severity::parse(s).or_else(|| {
+                errors.push(format!("severity \"{}\" is not one of low, medium, high, critical", s));
+                None
+            }),
+            None => {
+                errors.push("severity is missing or not a string".to_string());
+                None
+            }
+        };
+
+        let confidence = match value["confidence"].as_f64() {
+            Some(c) if (0.0..=1.0).contains(&c) => Some(c),
+            // Models sometimes answer in whole percent; 1.5 is just wrong
+            Some(c) if (1.0..=100.0).contains(&c) && c.fract() == 0.0 => Some(c / 100.0),
+            Some(c) => {
+                errors.push(format!("confidence {} is outside 0.0-1.0", c));
+                None
+            }
+            None => {
+                errors.push("confidence is missing or not a number".to_string());
+                None
+            }
+        };
+
+        let category = non_empty_str(value, "category", &mut errors);
+        let rationale = non_empty_str(value, "rationale", &mut errors);
+
+        let suggested_actions = match &value["suggested_actions"] {
+            Value::Array(items) => {
+                let actions: Vec<String> = items.iter().filter_map(|v| v.as_str()).map(|s| s.trim().to_string()).collect();
+                if actions.len() != items.len() {
+                    errors.push("suggested_actions must only contain strings".to_string());
+                }
+                Some(actions)
+            }
+            Value::String(s) => Some(vec![s.trim().to_string()]),
+            Value::Null => Some(Vec::new()),
+            _ => {
+                errors.push("suggested_actions must be an array of strings".to_string());
+                None
+            }
+        };
+
+        match (severity, confidence, category, rationale, suggested_actions) {
+            (Some(severity), Some(confidence), Some(category), Some(rationale), Some(suggested_actions)) if errors.is_empty() => {
+                Ok(TriageVerdict {
+                    severity,
+                    confidence,
+                    category: category.to_lowercase(),
+                    rationale,
+                    suggested_actions,
+                })
+            }
+            _ => Err(errors.join("; ")),
+        }
+    }
+}
+
+fn non_empty_str(value: &Value, field: &str, errors: &mut Vec<String>) -> Option<String> {
+    match value[field].as_str().map(str::trim) {
+        Some(s) if !s.is_empty() => Some(s.to_string()),
+        _ => {
+            errors.push(format!("{} is missing or empty", field));
+            None
+        }
+    }
+}
+
+/// Pulls the outermost `{...}` out of a response that may be wrapped in
+/// prose or a markdown code fence.
+fn extract_json(text: &str) -> Option<String> {
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    (end > start).then(|| text[start..=end].to_string())
+}
+
+/// Fixes the mistakes small models commonly make: trailing commas and
+/// single-quoted strings.
+fn repair_json(json: &str) -> String {
+    let mut out = String::with_capacity(json.len());
+    let mut in_string = false;
+    let mut quote = '"';
+    let mut escaped = false;
+    let chars: Vec<char> = json.chars().collect();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                in_string = false;
+                out.push('"');
+                continue;
+            } else if c == '"' && quote == '\'' {
+                out.push_str("\\\"");
+                continue;
+            }
+            out.push(c);
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => {
+                in_string = true;
+                quote = c;
+                out.push('"');
+            }
+            ',' => {
+                let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+                if !matches!(next, Some('}') | Some(']')) {
+                    out.push(c);
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"{"severity": "High", "confidence": 0.8, "category": "Reconnaissance", "rationale": "Many ports probed.", "suggested_actions": ["block the source"]}"#;
+
+    fn with(field: &str, value: &str) -> String {
+        let mut json: Value = serde_json::from_str(VALID).unwrap();
+        json[field] = serde_json::from_str(value).unwrap();
+        json.to_string()
+    }
+
+    #[test]
+    fn parses_valid_verdicts() {
+        let verdict = TriageVerdict::parse(VALID).unwrap();
+        assert_eq!(verdict.severity, Severity::High);
+        assert_eq!(verdict.confidence, 0.8);
+        assert_eq!(verdict.category, "reconnaissance");
+        assert_eq!(verdict.suggested_actions, ["block the source"]);
+    }
+
+    #[test]
+    fn extracts_and_repairs_json() {
+        let wrapped = format!("Here is my verdict:\n```json\n{}\n```", VALID);
+        assert!(TriageVerdict::parse(&wrapped).is_ok());
+
+        let sloppy = r#"{'severity': 'low', 'confidence': 0.3, 'category': 'benign', 'rationale': 'A "printer".', 'suggested_actions': ['none',],}"#;
+        let verdict = TriageVerdict::parse(sloppy).unwrap();
+        assert_eq!(verdict.rationale, "A \"printer\".");
+        assert_eq!(verdict.suggested_actions, ["none"]);
+
+        assert_eq!(TriageVerdict::parse("no json here").unwrap_err(), "response does not contain a JSON object");
+        assert!(TriageVerdict::parse("{not: json}").unwrap_err().starts_with("invalid JSON"));
+    }
+
+    #[test]
+    fn confidence_in_percent_must_be_whole() {
+        assert_eq!(TriageVerdict::parse(&with("confidence", "85")).unwrap().confidence, 0.85);
+        assert_eq!(TriageVerdict::parse(&with("confidence", "1")).unwrap().confidence, 1.0);
+        assert_eq!(TriageVerdict::parse(&with("confidence", "1.5")).unwrap_err(), "confidence 1.5 is outside 0.0-1.0");
+        assert_eq!(TriageVerdict::parse(&with("confidence", "250")).unwrap_err(), "confidence 250 is outside 0.0-1.0");
+        assert!(TriageVerdict::parse(&with("confidence", "-0.1")).is_err());
+        assert_eq!(TriageVerdict::parse(&with("confidence", "\"high\"")).unwrap_err(), "confidence is missing or not a number");
+    }
+
+    #[test]
+    fn lists_every_violation() {
+        let error = TriageVerdict::parse(r#"{"severity": "urgent", "category": " ", "suggested_actions": [1, "x"]}"#).unwrap_err();
+        assert_eq!(
+            error,
+            "severity \"urgent\" is not one of low, medium, high, critical; \
+             confidence is missing or not a number; \
+             category is missing or empty; \
+             rationale is missing or empty; \
+             suggested_actions must only contain strings"
+        );
+    }
+
+    #[test]
+    fn suggested_actions_are_lenient() {
+        assert_eq!(TriageVerdict::parse(&with("suggested_actions", "\" isolate \"")).unwrap().suggested_actions, ["isolate"]);
+        assert!(TriageVerdict::parse(&with("suggested_actions", "null")).unwrap().suggested_actions.is_empty());
+        assert_eq!(
+            TriageVerdict::parse(&with("suggested_actions", "{}")).unwrap_err(),
+            "suggested_actions must be an array of strings"
+        );
+    }
+
+    #[test]
+    fn parses_severity_names() {
+        assert_eq!(Severity::parse(" Moderate "), Some(Severity::Medium));
+        assert_eq!(Severity::parse("info"), Some(Severity::Low));
+        assert_eq!(Severity::parse("urgent"), None);
+        assert!(Severity::Critical > Severity::High);
+        assert_eq!(Severity::Critical.to_string(), "critical");
+    }
+}
@@ -1,19 +1,55 @@
 mod sniff;
+mod alerts;
+mod assets;
 mod dashboard;
-mod llm;
 mod db;
-mod parser;
 mod detection;
+mod dhcp;
+mod dga;
+mod arp;
+mod attack;
+mod baseline;
+mod beacon;
+mod dns_failures;
+mod dns_tunnel;
+mod fast_flux;
+mod incidents;
+mod enrich;
+mod intel;
+mod rules;
+mod scoring;
+mod stream;
+mod suppress;
 
+use ::sniff::llm;
+use ::sniff::llm::verdict::Severity;
+use clap::{Parser, Subcommand};
 use crossbeam_channel::{unbounded, Receiver};
 use std::thread;
 use sniff::NetworkEvent;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use crate::db::NetworkDB;
+use crate::arp::ArpMonitor;
+use crate::dhcp::DhcpMonitor;
+use crate::baseline::BaselineDetector;
+use crate::beacon::BeaconDetector;
+use crate::dns_failures::DnsFailureDetector;
+use crate::dns_tunnel::DnsTunnelDetector;
+use crate::fast_flux::FastFluxDetector;
+use crate::incidents::{Correlator, Incident};
+use crate::intel::IntelMatcher;
 use crate::detection::TrafficAnalyzer;
+use crate::detection::Detection;
+use crate::enrich::LlmEnricher;
+use crate::stream::StreamEngine;
+use crate::suppress::{SuppressionRule, Suppressions};
+use crate::alerts::{Alert, AlertState};
+use crate::assets::{Asset, AssetInventory, AssetTags};
+use tokio::sync::mpsc;
 use tokio::time;
 
 struct NetworkStats {
@@ -38,12 +74,184 @@ impl NetworkStats {
     }
 }
 
+#[derive(Parser)]
+#[command(about = "DNS & ARP monitoring with anomaly detection")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check detection rule files for errors (built-ins and DETECTION_RULES_DIR by default)
+    LintRules {
+        paths: Vec<PathBuf>,
+    },
+    /// Evaluate the detection rules once against the events stored in MongoDB
+    Scan,
+    /// Download the current Public Suffix List to PUBLIC_SUFFIX_LIST
+    UpdateSuffixList,
+    /// Download the IEEE MAC address registry to OUI_DATABASE
+    UpdateOui,
+    /// Manage alert suppression rules
+    Suppress {
+        #[command(subcommand)]
+        action: SuppressAction,
+    },
+    /// Report ATT&CK technique coverage and alert counts by tactic
+    AttackCoverage,
+    /// List alerts and move them through their lifecycle
+    Alerts {
+        #[command(subcommand)]
+        action: AlertAction,
+    },
+    /// List correlated incidents or show one with its alerts
+    Incidents {
+        #[command(subcommand)]
+        action: IncidentAction,
+    },
+    /// List the devices seen on the network and tag them
+    Assets {
+        #[command(subcommand)]
+        action: AssetAction,
+    },
+    /// List the most queried registrable domains in the stored DNS events
+    DnsDomains {
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Show today's LLM token usage and budget for each configured provider
+    LlmUsage,
+    /// List LLM triage verdicts at or above a severity
+    Inferences {
+        /// low, medium, high or critical
+        #[arg(long, default_value = "high")]
+        min_severity: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum IncidentAction {
+    /// List incidents, highest score first
+    List {
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Show an incident and its alerts
+    Show {
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AssetAction {
+    /// List assets, most recently seen first
+    List,
+    /// Set an asset's owner, criticality or tags
+    Tag {
+        mac: String,
+        /// Owner; an empty string clears it
+        #[arg(long)]
+        owner: Option<String>,
+        /// 0-1, how much alerts involving the asset matter
+        #[arg(long)]
+        criticality: Option<f64>,
+        /// Replaces the asset's tags; repeat for several
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AlertAction {
+    /// List the most recently seen alerts
+    List {
+        /// open, acknowledged, resolved or reopened
+        #[arg(long)]
+        state: Option<String>,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Acknowledge an alert
+    Ack {
+        id: String,
+    },
+    /// Resolve an alert
+    Resolve {
+        id: String,
+    },
+    /// Reopen a resolved alert
+    Reopen {
+        id: String,
+    },
+    /// Assign an alert to an analyst; omit the name to unassign
+    Assign {
+        id: String,
+        analyst: Option<String>,
+    },
+    /// Add a note to an alert
+    Note {
+        id: String,
+        text: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SuppressAction {
+    /// Add a rule; give at least one of --type, --source, --dest or --port
+    Add {
+        /// Activity type, e.g. "Port Scanning"
+        #[arg(long = "type")]
+        activity_type: Option<String>,
+        /// Source CIDR or address
+        #[arg(long)]
+        source: Option<String>,
+        /// Destination CIDR or address
+        #[arg(long)]
+        dest: Option<String>,
+        #[arg(long)]
+        port: Option<u16>,
+        /// UTC time of day range, e.g. 22:00-06:00
+        #[arg(long)]
+        time: Option<String>,
+        /// Duration (12h, 7d) or date (2026-12-31)
+        #[arg(long)]
+        expires: String,
+        /// Why the alerts are expected
+        #[arg(long)]
+        comment: String,
+    },
+    /// List rules with their hit counts
+    List,
+    /// Delete a rule by id
+    Remove {
+        id: String,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Cli::parse().command {
+        Some(Command::LintRules { paths }) => return lint_rules(&paths),
+        Some(Command::Scan) => return scan().await,
+        Some(Command::UpdateSuffixList) => return update_suffix_list().await,
+        Some(Command::UpdateOui) => return update_oui().await,
+        Some(Command::Suppress { action }) => return suppress(action).await,
+        Some(Command::Alerts { action }) => return manage_alerts(action).await,
+        Some(Command::AttackCoverage) => return attack_coverage().await,
+        Some(Command::Incidents { action }) => return manage_incidents(action).await,
+        Some(Command::Assets { action }) => return manage_assets(action).await,
+        Some(Command::DnsDomains { limit }) => return dns_domains(limit).await,
+        Some(Command::LlmUsage) => return llm_usage().await,
+        Some(Command::Inferences { min_severity }) => return list_inferences(&min_severity).await,
+        None => {}
+    }
+
     let (tx, rx) = unbounded();
     let running = Arc::new(AtomicBool::new(true));
     let db = NetworkDB::new().await?;
     let analyzer = TrafficAnalyzer::new(db.get_database_instance()).await;
+    analyzer.create_indexes().await.map_err(|e| e as Box<dyn std::error::Error>)?;
     println!("Connected to MongoDB successfully");
 
     let capture_thread = thread::spawn(move || {
@@ -64,42 +272,388 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
+    let enricher = LlmEnricher::new(&db.get_database_instance())
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?
+        .spawn(analyzer.clone());
+    let (alert_tx, mut alert_rx) = mpsc::unbounded_channel::<Detection>();
     let analyzer_clone = analyzer.clone();
-    let running_clone = running.clone();
+    let mut suppressions = Suppressions::new(&db.get_database_instance());
+    let mut correlator = Correlator::load(&db.get_database_instance())
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
     tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_secs(30));
-        while running_clone.load(Ordering::SeqCst) {
-            interval.tick().await;
-            match analyzer_clone.detect_suspicious_traffic().await {
-                Ok(suspicious) => {
-                    for activity in suspicious {
-                        println!("Suspicious Activity Detected: {} from {} - {}", activity.activity_type, activity.source, activity.details);
-                        if let Err(e) = analyzer_clone.store_suspicious_event(activity).await {
-                            eprintln!("Error inserting suspicious activity: {}", e);
-                        }
+        while let Some(mut detection) = alert_rx.recv().await {
+            detection.activity.score = scoring::model().score(&detection.activity, None);
+            let activity = &detection.activity;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or_default();
+            if let Some(rule) = suppressions.check(&detection, now).await {
+                println!("Suppressed {} from {} ({})", activity.activity_type, activity.source, rule.comment);
+                continue;
+            }
+            println!("Suspicious Activity Detected: {} from {} (score {:.0}) - {}", activity.activity_type, activity.source, activity.score, activity.details);
+            if let Err(e) = analyzer_clone.apply_actions(&detection).await {
+                eprintln!("Error applying rule actions: {}", e);
+            }
+            match analyzer_clone.store_suspicious_event(activity).await {
+                Ok(alert_id) => {
+                    if let Some(incident) = correlator.correlate(&detection, alert_id) {
+                        println!("Incident: {} (score {:.0}, {})", incident.title, incident.score, incident.severity);
+                    }
+                    if enricher.send((detection.activity, alert_id)).is_err() {
+                        eprintln!("Enrichment task stopped, skipping alert {}", alert_id);
                     }
                 },
-                Err(e) => eprintln!("Error detecting suspicious traffic: {}", e),
+                Err(e) => eprintln!("Error inserting suspicious activity: {}", e),
             }
         }
     });
 
-    process_events(rx, running.clone(), db).await;
+    let mut engine = StreamEngine::with_rules(&analyzer.rules());
+    let arp_monitor = ArpMonitor::load(&db.get_database_instance())
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    engine.add(Box::new(arp_monitor));
+    let dhcp_monitor = DhcpMonitor::load(&db.get_database_instance())
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    engine.add(Box::new(dhcp_monitor));
+    let baselines = BaselineDetector::load(&db.get_database_instance())
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    engine.add(Box::new(baselines));
+    engine.add(Box::new(BeaconDetector::new()));
+    engine.add(Box::new(DnsTunnelDetector::from_env()));
+    engine.add(Box::new(DnsFailureDetector::new()));
+    let fast_flux = FastFluxDetector::load(&db.get_database_instance())
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    engine.add(Box::new(fast_flux));
+    engine.add(Box::new(IntelMatcher::new(intel::store())));
+    let inventory = AssetInventory::load(&db.get_database_instance())
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    engine.add(Box::new(inventory));
+    process_events(rx, running.clone(), db, engine, alert_tx).await;
     capture_thread.join().unwrap();
     Ok(())
 }
 
-async fn process_events(rx: Receiver<NetworkEvent>, running: Arc<AtomicBool>, db: NetworkDB) {
+fn lint_rules(paths: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    let issues = rules::lint(paths);
+    for issue in &issues {
+        println!("{}", issue);
+    }
+
+    let errors = issues.iter().filter(|i| i.level == rules::LintLevel::Error).count();
+    println!("{} error(s), {} warning(s)", errors, issues.len() - errors);
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn scan() -> Result<(), Box<dyn std::error::Error>> {
+    let db = NetworkDB::new().await?;
+    let analyzer = TrafficAnalyzer::new(db.get_database_instance()).await;
+    let detections = analyzer
+        .detect_suspicious_traffic()
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+
+    for detection in &detections {
+        let activity = &detection.activity;
+        println!("[{}] {} from {} - {}", activity.rule_id, activity.activity_type, activity.source, activity.details);
+    }
+    println!("{} detection(s)", detections.len());
+    Ok(())
+}
+
+async fn update_suffix_list() -> Result<(), Box<dyn std::error::Error>> {
+    let path = sniff::domain::list_path();
+    let rules = sniff::domain::download(Path::new(&path))
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    println!("Saved {} public suffix rules to {}", rules, path);
+    Ok(())
+}
+
+async fn update_oui() -> Result<(), Box<dyn std::error::Error>> {
+    let path = sniff::oui::database_path();
+    let assignments = sniff::oui::download(Path::new(&path))
+        .await
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    println!("Saved {} MAC address assignments to {}", assignments, path);
+    Ok(())
+}
+
+async fn suppress(action: SuppressAction) -> Result<(), Box<dyn std::error::Error>> {
+    let db = NetworkDB::new().await?;
+    match action {
+        SuppressAction::Add { activity_type, source, dest, port, time, expires, comment } => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+            let rule = SuppressionRule {
+                id: None,
+                activity_type,
+                source_cidr: source,
+                dest_cidr: dest,
+                port,
+                time_of_day: time,
+                expires_at: suppress::parse_expiry(&expires, now)?,
+                comment,
+                created_at: now,
+                hits: 0,
+                last_hit: None,
+            };
+            let id = db.add_suppression(rule).await.map_err(|e| e as Box<dyn std::error::Error>)?;
+            println!("Added suppression rule {}", id);
+        }
+        SuppressAction::List => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64();
+            for rule in db.list_suppressions().await.map_err(|e| e as Box<dyn std::error::Error>)? {
+                let expires = chrono::DateTime::from_timestamp(rule.expires_at as i64, 0).map(|t| t.to_rfc3339()).unwrap_or_default();
+                println!(
+                    "{} {} | {} hits | expires {}{} | {}",
+                    rule.id.map(|id| id.to_hex()).unwrap_or_default(),
+                    rule.describe(),
+                    rule.hits,
+                    expires,
+                    if rule.expires_at <= now { " (expired)" } else { "" },
+                    rule.comment
+                );
+            }
+        }
+        SuppressAction::Remove { id } => {
+            if db.delete_suppression(&id).await.map_err(|e| e as Box<dyn std::error::Error>)? {
+                println!("Removed suppression rule {}", id);
+            } else {
+                println!("No suppression rule {}", id);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn attack_coverage() -> Result<(), Box<dyn std::error::Error>> {
+    let db = NetworkDB::new().await?;
+    for tactic in db.get_attack_coverage().await.map_err(|e| e as Box<dyn std::error::Error>)? {
+        let mapped = tactic.techniques.iter().filter(|t| t.mapped).count();
+        println!("{}: {}/{} techniques covered, {} alerts", tactic.tactic, mapped, tactic.techniques.len(), tactic.alerts);
+        for technique in &tactic.techniques {
+            println!("  {} {:<10} {} ({} alerts)", if technique.mapped { "+" } else { "-" }, technique.id, technique.name, technique.alerts);
+        }
+    }
+    Ok(())
+}
+
+async fn manage_alerts(action: AlertAction) -> Result<(), Box<dyn std::error::Error>> {
+    let db = NetworkDB::new().await?;
+    // Who made a change, for the alert's history
+    let analyst = std::env::var("USER").unwrap_or_else(|_| "analyst".to_string());
+    let alert = match action {
+        AlertAction::List { state, limit } => {
+            let state = match state {
+                Some(state) => Some(serde_json::from_value::<AlertState>(serde_json::Value::String(state))?),
+                None => None,
+            };
+            for alert in db.list_alerts(state, limit).await.map_err(|e| e as Box<dyn std::error::Error>)? {
+                print_alert(&alert);
+            }
+            return Ok(());
+        }
+        AlertAction::Ack { id } => db.set_alert_state(&id, AlertState::Acknowledged, &analyst).await,
+        AlertAction::Resolve { id } => db.set_alert_state(&id, AlertState::Resolved, &analyst).await,
+        AlertAction::Reopen { id } => db.set_alert_state(&id, AlertState::Reopened, &analyst).await,
+        AlertAction::Assign { id, analyst } => db.assign_alert(&id, analyst.as_deref()).await,
+        AlertAction::Note { id, text } => db.add_alert_note(&id, &analyst, &text).await,
+    };
+    print_alert(&alert.map_err(|e| e as Box<dyn std::error::Error>)?);
+    Ok(())
+}
+
+async fn manage_incidents(action: IncidentAction) -> Result<(), Box<dyn std::error::Error>> {
+    let db = NetworkDB::new().await?;
+    match action {
+        IncidentAction::List { limit } => {
+            for incident in db.list_incidents(limit).await.map_err(|e| e as Box<dyn std::error::Error>)? {
+                print_incident(&incident);
+            }
+        }
+        IncidentAction::Show { id } => match db.get_incident(&id).await.map_err(|e| e as Box<dyn std::error::Error>)? {
+            Some((incident, alerts)) => {
+                print_incident(&incident);
+                println!("    entities: {}", incident.entities.iter().cloned().collect::<Vec<_>>().join(", "));
+                for alert in &alerts {
+                    print!("    ");
+                    print_alert(alert);
+                }
+            }
+            None => println!("No incident {}", id),
+        },
+    }
+    Ok(())
+}
+
+async fn manage_assets(action: AssetAction) -> Result<(), Box<dyn std::error::Error>> {
+    let db = NetworkDB::new().await?;
+    match action {
+        AssetAction::List => {
+            for asset in db.list_assets().await.map_err(|e| e as Box<dyn std::error::Error>)? {
+                print_asset(&asset);
+            }
+        }
+        AssetAction::Tag { mac, owner, criticality, tags } => {
+            let tags = AssetTags {
+                owner,
+                criticality,
+                tags: (!tags.is_empty()).then_some(tags),
+            };
+            print_asset(&db.tag_asset(&mac, tags).await.map_err(|e| e as Box<dyn std::error::Error>)?);
+        }
+    }
+    Ok(())
+}
+
+async fn dns_domains(limit: i64) -> Result<(), Box<dyn std::error::Error>> {
+    let db = NetworkDB::new().await?;
+    for domain in db.get_dns_domain_summary(limit).await.map_err(|e| e as Box<dyn std::error::Error>)? {
+        println!(
+            "{:<40} {:>8} queries {:>6} names {:>4} clients",
+            domain.registered_domain, domain.queries, domain.distinct_names, domain.clients
+        );
+    }
+    Ok(())
+}
+
+async fn llm_usage() -> Result<(), Box<dyn std::error::Error>> {
+    let providers = enrich::provider_names();
+    if providers.is_empty() {
+        println!("No LLM provider configured (set TOGETHER_API_KEY or LLM_BUDGET_FALLBACK=local)");
+        return Ok(());
+    }
+    let db = NetworkDB::new().await?;
+    for status in db.get_llm_budget_status(&providers).await.map_err(|e| e as Box<dyn std::error::Error>)? {
+        let limit = |limit: Option<u64>| limit.map(|l| l.to_string()).unwrap_or_else(|| "unlimited".to_string());
+        println!(
+            "{}: {}/{} tokens this hour, {}/{} today, {} requests, ${:.2}{}",
+            status.provider,
+            status.hourly_tokens,
+            limit(status.hourly_limit),
+            status.daily_tokens,
+            limit(status.daily_limit),
+            status.daily_requests,
+            status.daily_cost_usd,
+            if status.exhausted { " (exhausted)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+async fn list_inferences(min_severity: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let min_severity = Severity::parse(min_severity).ok_or_else(|| format!("unknown severity {}", min_severity))?;
+    let db = NetworkDB::new().await?;
+    for inference in db.get_inferences_by_severity(min_severity).await? {
+        let Some(verdict) = &inference.verdict else { continue };
+        println!(
+            "{} [{}] {} ({:.0}% confident{}) | {}",
+            inference.alert_id.map(|id| id.to_hex()).unwrap_or_else(|| "-".to_string()),
+            verdict.severity,
+            verdict.category,
+            verdict.confidence * 100.0,
+            inference.provider.as_ref().map(|p| format!(", {}", p)).unwrap_or_default(),
+            verdict.rationale
+        );
+        for action in &verdict.suggested_actions {
+            println!("    - {}", action);
+        }
+    }
+    Ok(())
+}
+
+fn print_asset(asset: &Asset) {
+    let addresses: Vec<&str> = asset.addresses.iter().map(|a| a.ip.as_str()).collect();
+    let vendor = match (&asset.vendor, asset.randomized) {
+        (Some(vendor), _) => vendor.as_str(),
+        (None, true) => "randomized",
+        (None, false) => "unknown vendor",
+    };
+    println!(
+        "{} ({}) {} [{}] {}{}{}{}",
+        asset.mac,
+        vendor,
+        asset.hostname.as_deref().unwrap_or("-"),
+        addresses.join(", "),
+        asset.services.join(" "),
+        asset.os.as_ref().map(|os| format!(" | {}", os)).unwrap_or_default(),
+        asset.owner.as_ref().map(|o| format!(" | owner {}", o)).unwrap_or_default(),
+        asset.criticality.map(|c| format!(" | criticality {:.1}", c)).unwrap_or_default()
+    );
+    if !asset.tags.is_empty() {
+        println!("    tags: {}", asset.tags.join(", "));
+    }
+}
+
+fn print_incident(incident: &Incident) {
+    println!(
+        "{} {} score {:.0} | {} | {} alert(s){}",
+        incident.id.to_hex(),
+        incident.severity,
+        incident.score,
+        incident.title,
+        incident.alerts.len(),
+        if incident.sequences.is_empty() { String::new() } else { format!(" | {}", incident.sequences.join(", ")) }
+    );
+}
+
+fn print_alert(alert: &Alert) {
+    let activity = &alert.activity;
+    println!(
+        "{} [{}] {} {} from {} x{} | {}{}",
+        alert.id.to_hex(),
+        alert.state,
+        activity.severity,
+        activity.activity_type,
+        activity.source,
+        alert.occurrences,
+        activity.details,
+        alert.assignee.as_ref().map(|a| format!(" | assigned to {}", a)).unwrap_or_default()
+    );
+    for note in &alert.notes {
+        println!("    {}: {}", note.author, note.text);
+    }
+}
+
+async fn process_events(
+    rx: Receiver<NetworkEvent>,
+    running: Arc<AtomicBool>,
+    db: NetworkDB,
+    mut engine: StreamEngine,
+    alerts: mpsc::UnboundedSender<Detection>,
+) {
     let mut stats = NetworkStats::new();
+    let mut last_tick = Instant::now();
     while running.load(Ordering::SeqCst) {
+        let mut detections = Vec::new();
         match rx.try_recv() {
             Ok(event) => {
                 stats.update(&event);
+                detections.extend(engine.observe(&event));
                 if let Err(e) = db.store_event(event.clone()).await {
                     eprintln!("Error storing event in MongoDB: {}", e);
                 }
             },
             Err(_) => thread::sleep(Duration::from_millis(100)),
         }
+
+        if last_tick.elapsed() >= Duration::from_secs(1) {
+            last_tick = Instant::now();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or_default();
+            detections.extend(engine.tick(now));
+        }
+        for detection in detections {
+            if alerts.send(detection).is_err() {
+                eprintln!("Alert handler stopped, dropping detection");
+            }
+        }
     }
 }
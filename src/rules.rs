@@ -0,0 +1,778 @@
+// Declarative detection rules.
+//
+// A rule filters one protocol's events within a time window, groups them by
+// one or more fields, aggregates each group (count, distinct count or sum) and
+// raises a SuspiciousActivity when the aggregate is above the threshold. The
+// built-in rules live in rules/builtin.yaml; YAML files in DETECTION_RULES_DIR
+// add rules or replace built-ins with the same id.
+
+use crate::attack;
+use crate::detection::{Detection, Evidence, SuspiciousActivity};
+use crate::dga;
+use crate::sniff::dns;
+use mongodb::bson::{doc, Bson, Document};
+use regex::Regex;
+use serde::Deserialize;
+use sniff::llm::templates::fill;
+use sniff::llm::verdict::Severity;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BUILTIN_RULES: &str = include_str!("../rules/builtin.yaml");
+const DEFAULT_RULES_DIR: &str = "rules.d";
+
+const BASE_FIELDS: [&str; 11] = [
+    "protocol", "source", "destination", "payload_size", "timestamp",
+    "src_ip", "src_port", "dest_ip", "dest_port", "src_mac", "dest_mac",
+];
+const DNS_FIELDS: [&str; 4] = ["query", "query_type", "resolved_ip", "registered_domain"];
+const PROTOCOLS: [&str; 4] = ["TCP", "UDP", "DNS", "ARP"];
+const SEVERITIES: [&str; 4] = ["low", "medium", "high", "critical"];
+const FUNCTIONS: [&str; 1] = ["dns_risk"];
+const ACTIONS: [&str; 1] = ["store_dns_mapping"];
+const RISK_LEVELS: [&str; 3] = ["Low", "Medium", "High"];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub id: String,
+    /// Becomes the alert's `activity_type`
+    pub name: String,
+    #[serde(default)]
+    #[allow(dead_code)] // documentation for rule authors
+    pub description: Option<String>,
+    pub protocol: String,
+    /// Look-back window in seconds
+    pub window: f64,
+    #[serde(default)]
+    pub filter: Vec<FieldFilter>,
+    pub group_by: Vec<String>,
+    pub aggregate: Aggregate,
+    /// Alert when the aggregate is strictly greater than this
+    pub threshold: f64,
+    /// Field whose distinct values are collected into `{{values}}`
+    #[serde(default)]
+    pub collect: Option<String>,
+    #[serde(default)]
+    pub requires: Option<Requirement>,
+    #[serde(default)]
+    pub classify: Option<Classifier>,
+    #[serde(default)]
+    pub actions: Vec<String>,
+    pub severity: String,
+    /// How sure a match makes us, 0-1
+    #[serde(default = "default_confidence")]
+    pub confidence: f64,
+    pub message: String,
+    /// Alert source template, defaults to the first group_by field
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Alert indicator template, defaults to empty
+    #[serde(default)]
+    pub indicator: Option<String>,
+    /// ATT&CK technique IDs, replacing the catalog mapping for this rule
+    #[serde(default)]
+    pub techniques: Vec<String>,
+}
+
+fn default_confidence() -> f64 {
+    0.6
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldFilter {
+    pub field: String,
+    pub op: FilterOp,
+    pub value: serde_yaml::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    In,
+    NotIn,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Regex,
+    InRanges,
+    NotInRanges,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Aggregate {
+    #[serde(rename = "type")]
+    pub kind: AggregateKind,
+    #[serde(default)]
+    pub field: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateKind {
+    Count,
+    DistinctCount,
+    Sum,
+}
+
+/// Only alert on groups that have at least one matching event in another
+/// protocol, e.g. a DNS answer followed by HTTP traffic to the same address.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Requirement {
+    pub protocol: String,
+    /// Field in the other protocol's events
+    pub field: String,
+    /// group_by field it must equal
+    pub equals: String,
+    #[serde(default)]
+    pub filter: Vec<FieldFilter>,
+}
+
+/// A Rust-side check applied to each group after aggregation.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Classifier {
+    pub function: String,
+    pub field: String,
+    pub min_level: String,
+}
+
+pub fn collection_for(protocol: &str) -> Option<&'static str> {
+    match protocol {
+        "TCP" => Some("tcp_events"),
+        "UDP" => Some("udp_events"),
+        "DNS" => Some("dns_events"),
+        "ARP" => Some("arp_events"),
+        _ => None,
+    }
+}
+
+impl Rule {
+    pub fn collection(&self) -> &'static str {
+        collection_for(&self.protocol).unwrap_or("tcp_events")
+    }
+
+    /// Compiles the rule into an aggregation pipeline over `collection()`.
+    pub fn pipeline(&self, now: f64) -> Vec<Document> {
+        let mut pipeline = vec![doc! { "$match": {
+            "timestamp": { "$gte": now - self.window },
+            "protocol": &self.protocol,
+        }}];
+        pipeline.extend(derived_fields());
+        if !self.filter.is_empty() {
+            pipeline.push(doc! { "$match": filter_document(&self.filter) });
+        }
+
+        let mut id = Document::new();
+        for field in &self.group_by {
+            id.insert(field.clone(), format!("${}", field));
+        }
+        let mut group = doc! { "_id": id };
+        match self.aggregate.kind {
+            AggregateKind::Count => group.insert("value", doc! { "$sum": 1 }),
+            AggregateKind::Sum => group.insert("value", doc! { "$sum": self.aggregate_field() }),
+            AggregateKind::DistinctCount => group.insert("distinct", doc! { "$addToSet": self.aggregate_field() }),
+        };
+        if let Some(collect) = &self.collect {
+            group.insert("values", doc! { "$addToSet": format!("${}", collect) });
+        }
+        pipeline.push(doc! { "$group": group });
+
+        if self.aggregate.kind == AggregateKind::DistinctCount {
+            pipeline.push(doc! { "$addFields": { "value": { "$size": "$distinct" } } });
+        }
+        pipeline.push(doc! { "$match": { "value": { "$gt": self.threshold } } });
+
+        if let Some(requires) = &self.requires {
+            let mut lookup = vec![doc! { "$match": { "protocol": &requires.protocol } }];
+            lookup.extend(derived_fields());
+            if !requires.filter.is_empty() {
+                lookup.push(doc! { "$match": filter_document(&requires.filter) });
+            }
+            lookup.push(doc! { "$match": { "$expr": { "$eq": [format!("${}", requires.field), "$$required"] } } });
+            lookup.push(doc! { "$limit": 1 });
+
+            pipeline.push(doc! { "$lookup": {
+                "from": collection_for(&requires.protocol).unwrap_or("tcp_events"),
+                "let": { "required": format!("$_id.{}", requires.equals) },
+                "pipeline": lookup,
+                "as": "required_matches",
+            }});
+            pipeline.push(doc! { "$match": { "required_matches": { "$ne": [] } } });
+        }
+        pipeline
+    }
+
+    /// Turns one aggregation result into a detection, or `None` if the
+    /// rule's classifier rejects it.
+    pub fn to_detection(&self, result: &Document, now: f64) -> Option<Detection> {
+        let id = result.get_document("_id").ok()?;
+        let keys = self.group_by.iter().map(|f| id.get(f).map(bson_to_string).unwrap_or_default()).collect();
+        let values = result.get_array("values").ok().map(|v| v.iter().map(bson_to_string).collect());
+        self.detection(keys, bson_to_f64(result.get("value")).unwrap_or(0.0), values, now)
+    }
+
+    /// Builds the alert for a group whose aggregate crossed the threshold.
+    /// `keys` are the group's values in `group_by` order.
+    pub fn detection(&self, keys: Vec<String>, value: f64, values: Option<Vec<String>>, now: f64) -> Option<Detection> {
+        let mut vars: HashMap<String, String> = self.group_by.iter().cloned().zip(keys).collect();
+        vars.insert("value".to_string(), format_number(value));
+        vars.insert("window".to_string(), format_number(self.window));
+        if let Some(values) = values {
+            vars.insert("values".to_string(), values.join(", "));
+        }
+
+        if let Some(classify) = &self.classify {
+            let input = vars.get(&classify.field).cloned().unwrap_or_default();
+            let (level, extra) = apply_function(&classify.function, &input)?;
+            if risk_rank(&level) < risk_rank(&classify.min_level) {
+                return None;
+            }
+            vars.insert(classify.function.clone(), level);
+            vars.extend(extra);
+        }
+
+        let source = match &self.source {
+            Some(template) => fill(template, &vars),
+            None => self.group_by.first().and_then(|f| vars.get(f)).cloned().unwrap_or_default(),
+        };
+        let activity = SuspiciousActivity {
+            activity_type: self.name.clone(),
+            source,
+            details: fill(&self.message, &vars),
+            indicator: self.indicator.as_deref().map(|t| fill(t, &vars)).unwrap_or_default(),
+            magnitude: value,
+            severity: Severity::parse(&self.severity).unwrap_or(Severity::Medium),
+            score: 0.0,
+            confidence: self.confidence,
+            evidence: self.evidence(value, &vars),
+            rule_id: self.id.clone(),
+            timestamp: now,
+        };
+
+        Some(Detection {
+            activity,
+            actions: self.actions.clone(),
+            vars,
+        })
+    }
+
+    /// The group's count and the addresses, ports and names among its fields.
+    fn evidence(&self, value: f64, vars: &HashMap<String, String>) -> Evidence {
+        let mut evidence = Evidence {
+            count: Some(value),
+            ..Evidence::default()
+        };
+        let source = self.group_by.first().and_then(|f| vars.get(f));
+        let fields = self.group_by.iter().filter_map(|f| Some((f, vars.get(f)?)));
+        let collected = self.collect.as_ref().and_then(|f| Some((f, vars.get("values")?)));
+        for (field, values) in fields.chain(collected) {
+            for value in values.split(", ").filter(|v| !v.is_empty()) {
+                match field.as_str() {
+                    "src_port" | "dest_port" => evidence.ports.extend(value.parse::<u16>().ok()),
+                    "query" | "registered_domain" => evidence.domains.push(value.to_string()),
+                    _ if source.map(String::as_str) != Some(value) => evidence.peers.push(value.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        if let Some(score) = vars.get("dga_score").and_then(|s| s.parse().ok()) {
+            evidence.metrics.insert("dga_score".to_string(), score);
+        }
+        evidence
+    }
+
+    fn aggregate_field(&self) -> String {
+        format!("${}", self.aggregate.field.as_deref().unwrap_or("payload_size"))
+    }
+}
+
+/// Splits "ip:port" and ARP's "MAC (IP)" into the derived address fields.
+fn derived_fields() -> Vec<Document> {
+    vec![
+        doc! { "$addFields": {
+            "_src": { "$regexFind": { "input": "$source", "regex": r"^(.*):(\d+)$" } },
+            "_dst": { "$regexFind": { "input": "$destination", "regex": r"^(.*):(\d+)$" } },
+            "_arp_src": { "$regexFind": { "input": "$source", "regex": r"^(\S+) \((.*)\)$" } },
+            "_arp_dst": { "$regexFind": { "input": "$destination", "regex": r"^(\S+) \((.*)\)$" } },
+        }},
+        doc! { "$addFields": {
+            "src_ip": { "$ifNull": [
+                { "$arrayElemAt": ["$_src.captures", 0] },
+                { "$ifNull": [{ "$arrayElemAt": ["$_arp_src.captures", 1] }, "$source"] },
+            ]},
+            "dest_ip": { "$ifNull": [
+                { "$arrayElemAt": ["$_dst.captures", 0] },
+                { "$ifNull": [{ "$arrayElemAt": ["$_arp_dst.captures", 1] }, "$destination"] },
+            ]},
+            "src_port": { "$toInt": { "$arrayElemAt": ["$_src.captures", 1] } },
+            "dest_port": { "$toInt": { "$arrayElemAt": ["$_dst.captures", 1] } },
+            "src_mac": { "$arrayElemAt": ["$_arp_src.captures", 0] },
+            "dest_mac": { "$arrayElemAt": ["$_arp_dst.captures", 0] },
+            "query": { "$arrayElemAt": ["$dns.questions.name", 0] },
+            "query_type": query_type_name(),
+            "registered_domain": { "$arrayElemAt": ["$dns.questions.registered_domain", 0] },
+            "resolved_ip": { "$arrayElemAt": [
+                { "$map": {
+                    "input": { "$filter": {
+                        "input": { "$ifNull": ["$dns.answers", []] },
+                        "cond": { "$in": ["$$this.rtype", [dns::TYPE_A as i32, dns::TYPE_AAAA as i32]] },
+                    }},
+                    "in": "$$this.data",
+                }},
+                0,
+            ]},
+        }},
+    ]
+}
+
+/// The first question's type as a name ("A", "TXT"), matching `dns::type_name`.
+fn query_type_name() -> Document {
+    let qtype = doc! { "$arrayElemAt": ["$dns.questions.qtype", 0] };
+    let branches: Vec<Document> = dns::type_names()
+        .iter()
+        .map(|(number, name)| doc! { "case": { "$eq": [qtype.clone(), *number as i32] }, "then": *name })
+        .collect();
+    doc! { "$switch": {
+        "branches": branches,
+        "default": { "$concat": ["TYPE", { "$toString": qtype }] },
+    }}
+}
+
+fn filter_document(filters: &[FieldFilter]) -> Document {
+    let mut clauses = Vec::new();
+    for filter in filters {
+        let value = yaml_to_bson(&filter.value);
+        let clause = match filter.op {
+            FilterOp::Eq => doc! { &filter.field: value },
+            FilterOp::Ne => doc! { &filter.field: { "$ne": value } },
+            FilterOp::In => doc! { &filter.field: { "$in": value } },
+            FilterOp::NotIn => doc! { &filter.field: { "$nin": value } },
+            FilterOp::Gt => doc! { &filter.field: { "$gt": value } },
+            FilterOp::Gte => doc! { &filter.field: { "$gte": value } },
+            FilterOp::Lt => doc! { &filter.field: { "$lt": value } },
+            FilterOp::Lte => doc! { &filter.field: { "$lte": value } },
+            FilterOp::Regex => doc! { &filter.field: { "$regex": value } },
+            FilterOp::InRanges | FilterOp::NotInRanges => {
+                let ranges: Vec<Document> = ranges(&filter.value)
+                    .into_iter()
+                    .map(|(lo, hi)| doc! { &filter.field: { "$gte": lo, "$lte": hi } })
+                    .collect();
+                if filter.op == FilterOp::InRanges {
+                    doc! { "$or": ranges }
+                } else {
+                    doc! { "$nor": ranges }
+                }
+            }
+        };
+        clauses.push(clause);
+    }
+    doc! { "$and": clauses }
+}
+
+pub fn ranges(value: &serde_yaml::Value) -> Vec<(f64, f64)> {
+    value
+        .as_sequence()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|pair| {
+                    let pair = pair.as_sequence()?;
+                    Some((pair.first()?.as_f64()?, pair.get(1)?.as_f64()?))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn yaml_to_bson(value: &serde_yaml::Value) -> Bson {
+    match value {
+        serde_yaml::Value::Null => Bson::Null,
+        serde_yaml::Value::Bool(b) => Bson::Boolean(*b),
+        serde_yaml::Value::Number(n) => match n.as_i64() {
+            Some(i) => Bson::Int64(i),
+            None => Bson::Double(n.as_f64().unwrap_or(0.0)),
+        },
+        serde_yaml::Value::String(s) => Bson::String(s.clone()),
+        serde_yaml::Value::Sequence(items) => Bson::Array(items.iter().map(yaml_to_bson).collect()),
+        serde_yaml::Value::Mapping(_) | serde_yaml::Value::Tagged(_) => Bson::Null,
+    }
+}
+
+/// Runs a classify function, returning its Low/Medium/High level and any
+/// extra placeholders it sets (see `function_vars`).
+fn apply_function(name: &str, input: &str) -> Option<(String, Vec<(String, String)>)> {
+    match name {
+        "dns_risk" => {
+            let score = dga::classifier().score(input);
+            let extra = vec![
+                ("dga_score".to_string(), format!("{:.2}", score.score)),
+                ("dga_explanation".to_string(), score.explanation()),
+            ];
+            Some((score.level().to_string(), extra))
+        }
+        _ => None,
+    }
+}
+
+fn function_vars(name: &str) -> &'static [&'static str] {
+    match name {
+        "dns_risk" => &["dga_score", "dga_explanation"],
+        _ => &[],
+    }
+}
+
+fn risk_rank(level: &str) -> usize {
+    RISK_LEVELS
+        .iter()
+        .position(|l| l.eq_ignore_ascii_case(level))
+        .unwrap_or(0)
+}
+
+pub fn bson_to_string(value: &Bson) -> String {
+    match value {
+        Bson::String(s) => s.clone(),
+        Bson::Int32(i) => i.to_string(),
+        Bson::Int64(i) => i.to_string(),
+        Bson::Double(d) => format_number(*d),
+        Bson::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn bson_to_f64(value: Option<&Bson>) -> Option<f64> {
+    match value? {
+        Bson::Int32(i) => Some(*i as f64),
+        Bson::Int64(i) => Some(*i as f64),
+        Bson::Double(d) => Some(*d),
+        _ => None,
+    }
+}
+
+pub fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+fn placeholders(template: &str) -> Vec<String> {
+    let re = Regex::new(r"\{\{\s*([A-Za-z0-9_]+)\s*\}\}").expect("valid placeholder pattern");
+    re.captures_iter(template).map(|c| c[1].to_string()).collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn builtin() -> Self {
+        let rules = serde_yaml::from_str(BUILTIN_RULES).expect("built-in rules are valid YAML");
+        RuleSet { rules }
+    }
+
+    /// Built-in rules plus every `*.yaml`/`*.yml` file in `dir`. Rules that
+    /// fail `lint` with an error are skipped, so a typo cannot silently change
+    /// what a rule matches.
+    pub fn load(dir: &Path) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut set = Self::builtin();
+        for path in rule_files(dir)? {
+            for rule in parse_file(&path)? {
+                let mut issues = Vec::new();
+                lint_rule(&rule, &format!("{}: {}", path.display(), rule.id), &mut issues);
+                let errors: Vec<&LintIssue> = issues.iter().filter(|i| i.level == LintLevel::Error).collect();
+                if !errors.is_empty() {
+                    for issue in errors {
+                        eprintln!("Skipping detection rule: {}", issue);
+                    }
+                    continue;
+                }
+                set.rules.retain(|r| r.id != rule.id);
+                set.rules.push(rule);
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn from_env() -> Self {
+        let dir = env::var("DETECTION_RULES_DIR").unwrap_or_else(|_| DEFAULT_RULES_DIR.to_string());
+        Self::load(Path::new(&dir)).unwrap_or_else(|e| {
+            eprintln!("Error loading detection rules, using built-ins: {}", e);
+            Self::builtin()
+        })
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn rule(&self, id: &str) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.id == id)
+    }
+}
+
+fn rule_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if matches!(path.extension().and_then(|e| e.to_str()), Some("yaml") | Some("yml")) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn parse_file(path: &Path) -> Result<Vec<Rule>, Box<dyn Error + Send + Sync>> {
+    let contents = fs::read_to_string(path)?;
+    serde_yaml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LintLevel {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct LintIssue {
+    pub level: LintLevel,
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            LintLevel::Error => "error",
+            LintLevel::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", level, self.location, self.message)
+    }
+}
+
+/// Checks rule files (or directories of them) and returns every problem
+/// found. With no paths, the built-ins and DETECTION_RULES_DIR are checked.
+pub fn lint(paths: &[PathBuf]) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+    let mut sources: Vec<(String, Result<Vec<Rule>, String>)> = Vec::new();
+
+    if paths.is_empty() {
+        sources.push(("rules/builtin.yaml".to_string(), serde_yaml::from_str(BUILTIN_RULES).map_err(|e| e.to_string())));
+        let dir = env::var("DETECTION_RULES_DIR").unwrap_or_else(|_| DEFAULT_RULES_DIR.to_string());
+        if Path::new(&dir).is_dir() {
+            collect_sources(Path::new(&dir), &mut sources, &mut issues);
+        }
+    } else {
+        for path in paths {
+            collect_sources(path, &mut sources, &mut issues);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for (location, parsed) in sources {
+        match parsed {
+            Ok(rules) => {
+                for rule in &rules {
+                    let location = format!("{}: {}", location, rule.id);
+                    if !seen.insert(rule.id.clone()) {
+                        issues.push(LintIssue {
+                            level: LintLevel::Warning,
+                            location: location.clone(),
+                            message: "duplicate id, later definition wins".to_string(),
+                        });
+                    }
+                    lint_rule(rule, &location, &mut issues);
+                }
+            }
+            Err(e) => issues.push(LintIssue { level: LintLevel::Error, location, message: e }),
+        }
+    }
+    issues
+}
+
+fn collect_sources(path: &Path, sources: &mut Vec<(String, Result<Vec<Rule>, String>)>, issues: &mut Vec<LintIssue>) {
+    let files = if path.is_dir() {
+        match rule_files(path) {
+            Ok(files) => files,
+            Err(e) => {
+                issues.push(LintIssue { level: LintLevel::Error, location: path.display().to_string(), message: e.to_string() });
+                return;
+            }
+        }
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    for file in files {
+        let parsed = fs::read_to_string(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_yaml::from_str(&contents).map_err(|e| e.to_string()));
+        sources.push((file.display().to_string(), parsed));
+    }
+}
+
+fn lint_rule(rule: &Rule, location: &str, issues: &mut Vec<LintIssue>) {
+    let mut error = |message: String| issues.push(LintIssue { level: LintLevel::Error, location: location.to_string(), message });
+
+    if rule.id.trim().is_empty() {
+        error("id is empty".to_string());
+    }
+    if !PROTOCOLS.contains(&rule.protocol.as_str()) {
+        error(format!("unknown protocol \"{}\", expected one of {}", rule.protocol, PROTOCOLS.join(", ")));
+    }
+    if rule.window <= 0.0 {
+        error("window must be a positive number of seconds".to_string());
+    }
+    if rule.threshold < 0.0 {
+        error("threshold must not be negative".to_string());
+    }
+    if rule.group_by.is_empty() {
+        error("group_by needs at least one field".to_string());
+    }
+    if !SEVERITIES.contains(&rule.severity.to_lowercase().as_str()) {
+        error(format!("unknown severity \"{}\", expected one of {}", rule.severity, SEVERITIES.join(", ")));
+    }
+    if !(0.0..=1.0).contains(&rule.confidence) {
+        error("confidence must be between 0 and 1".to_string());
+    }
+    match (rule.aggregate.kind, &rule.aggregate.field) {
+        (AggregateKind::Count, Some(_)) => error("count aggregates take no field".to_string()),
+        (AggregateKind::DistinctCount | AggregateKind::Sum, None) => error("distinct_count and sum aggregates need a field".to_string()),
+        _ => {}
+    }
+    for filter in rule.filter.iter().chain(rule.requires.iter().flat_map(|r| r.filter.iter())) {
+        if let Some(message) = lint_filter(filter) {
+            error(message);
+        }
+    }
+    if let Some(requires) = &rule.requires {
+        if collection_for(&requires.protocol).is_none() {
+            error(format!("requires: unknown protocol \"{}\"", requires.protocol));
+        }
+        if !rule.group_by.contains(&requires.equals) {
+            error(format!("requires.equals \"{}\" is not a group_by field", requires.equals));
+        }
+    }
+    if let Some(classify) = &rule.classify {
+        if !FUNCTIONS.contains(&classify.function.as_str()) {
+            error(format!("unknown classify function \"{}\"", classify.function));
+        }
+        if !rule.group_by.contains(&classify.field) {
+            error(format!("classify.field \"{}\" is not a group_by field", classify.field));
+        }
+        if !RISK_LEVELS.iter().any(|l| l.eq_ignore_ascii_case(&classify.min_level)) {
+            error(format!("classify.min_level \"{}\" is not one of low, medium, high", classify.min_level));
+        }
+    }
+    for action in &rule.actions {
+        if !ACTIONS.contains(&action.as_str()) {
+            error(format!("unknown action \"{}\"", action));
+        }
+    }
+    for technique in &rule.techniques {
+        if attack::catalog().technique(technique).is_none() {
+            error(format!("unknown ATT&CK technique \"{}\"", technique));
+        }
+    }
+
+    let mut available: HashSet<String> = rule.group_by.iter().cloned().collect();
+    available.extend(["value".to_string(), "window".to_string()]);
+    if rule.collect.is_some() {
+        available.insert("values".to_string());
+    }
+    if let Some(classify) = &rule.classify {
+        available.insert(classify.function.clone());
+        available.extend(function_vars(&classify.function).iter().map(|v| v.to_string()));
+    }
+    let templates = [Some(&rule.message), rule.source.as_ref(), rule.indicator.as_ref()];
+    for template in templates.into_iter().flatten() {
+        for name in placeholders(template) {
+            if !available.contains(&name) {
+                error(format!("placeholder {{{{{}}}}} is never set", name));
+            }
+        }
+    }
+
+    let mut fields: Vec<&String> = rule.group_by.iter().chain(rule.filter.iter().map(|f| &f.field)).collect();
+    fields.extend(rule.aggregate.field.iter());
+    fields.extend(rule.collect.iter());
+    for field in fields {
+        let known = BASE_FIELDS.contains(&field.as_str()) || (rule.protocol == "DNS" && DNS_FIELDS.contains(&field.as_str()));
+        if !known {
+            issues.push(LintIssue {
+                level: LintLevel::Warning,
+                location: location.to_string(),
+                message: format!("field \"{}\" is not recorded for {} events", field, rule.protocol),
+            });
+        }
+    }
+}
+
+fn lint_filter(filter: &FieldFilter) -> Option<String> {
+    let value = &filter.value;
+    match filter.op {
+        FilterOp::In | FilterOp::NotIn if !value.is_sequence() => {
+            Some(format!("filter on \"{}\": in/not_in need a list", filter.field))
+        }
+        FilterOp::InRanges | FilterOp::NotInRanges => {
+            let count = value.as_sequence().map(|s| s.len()).unwrap_or(0);
+            (count == 0 || ranges(value).len() != count)
+                .then(|| format!("filter on \"{}\": ranges must be a list of [low, high] pairs", filter.field))
+        }
+        FilterOp::Gt | FilterOp::Gte | FilterOp::Lt | FilterOp::Lte if !value.is_number() => {
+            Some(format!("filter on \"{}\": comparisons need a number", filter.field))
+        }
+        FilterOp::Regex => match value.as_str().map(Regex::new) {
+            Some(Ok(_)) => None,
+            Some(Err(e)) => Some(format!("filter on \"{}\": invalid regex: {}", filter.field, e)),
+            None => Some(format!("filter on \"{}\": regex must be a string", filter.field)),
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rare_port_alerts_are_keyed_on_the_destination() {
+        let rules = RuleSet::builtin();
+        let rule = rules.rule("rare_port_activity").unwrap();
+        let keys = || vec!["10.0.0.9".to_string(), "55555".to_string()];
+        let first = rule.detection(keys(), 10.0, Some(vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()]), 1.0).unwrap();
+        let second = rule.detection(keys(), 12.0, Some(vec!["10.0.0.3".to_string()]), 2.0).unwrap();
+
+        assert_eq!(first.activity.fingerprint(), second.activity.fingerprint());
+        assert_eq!(first.activity.indicator, "10.0.0.9:55555");
+        assert_eq!(first.activity.evidence.peers, ["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(first.activity.evidence.ports, [55555]);
+    }
+
+    #[test]
+    fn builtin_rules_lint_cleanly() {
+        let rules = RuleSet::builtin();
+        let mut issues = Vec::new();
+        for rule in rules.rules() {
+            lint_rule(rule, "rules/builtin.yaml", &mut issues);
+        }
+        issues.retain(|i| i.level == LintLevel::Error);
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+}
@@ -0,0 +1,293 @@
+// Alert risk scoring.
+//
+// Combines the detector's severity and confidence, the criticality of the
+// hosts involved, threat-intel hits and the LLM triage verdict into a 0-100
+// score, as configured in data/scoring.yaml (overridden by SCORING_FILE).
+// A criticality tagged on an asset in the inventory beats the CIDR list.
+// Alerts are scored when stored and rescored once their verdict arrives.
+
+use crate::assets;
+use crate::detection::SuspiciousActivity;
+use crate::intel::{self, cidr_contains, parse_network};
+use crate::stream::split_endpoint;
+use serde::Deserialize;
+use sniff::llm::verdict::{Severity, TriageVerdict};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+const BUILTIN_MODEL: &str = include_str!("../data/scoring.yaml");
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Weights {
+    pub detector: f64,
+    pub asset: f64,
+    pub intel: f64,
+    pub llm: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeverityBases {
+    pub low: f64,
+    pub medium: f64,
+    pub high: f64,
+    pub critical: f64,
+}
+
+/// Other keys, such as a `name` label, are ignored.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssetCriticality {
+    pub cidr: String,
+    pub criticality: f64,
+}
+
+/// A scoring file; sections it leaves out keep the built-in values.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScoringFile {
+    weights: Option<Weights>,
+    severity: Option<SeverityBases>,
+    default_criticality: Option<f64>,
+    assets: Option<Vec<AssetCriticality>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScoringModel {
+    pub weights: Weights,
+    pub severity: SeverityBases,
+    pub default_criticality: f64,
+    pub assets: Vec<AssetCriticality>,
+}
+
+impl ScoringModel {
+    pub fn builtin() -> Self {
+        let file: ScoringFile = serde_yaml::from_str(BUILTIN_MODEL).expect("built-in scoring model is valid YAML");
+        ScoringModel {
+            weights: file.weights.expect("built-in scoring model has weights"),
+            severity: file.severity.expect("built-in scoring model has severity bases"),
+            default_criticality: file.default_criticality.unwrap_or(0.5),
+            assets: file.assets.unwrap_or_default(),
+        }
+    }
+
+    /// The built-in model overridden by SCORING_FILE, if set.
+    pub fn from_env() -> Self {
+        let mut model = Self::builtin();
+        if let Ok(path) = env::var("SCORING_FILE") {
+            if let Err(e) = model.merge_file(&path) {
+                eprintln!("Error loading scoring model {}: {}", path, e);
+            }
+        }
+        model
+    }
+
+    fn merge_file(&mut self, path: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let file: ScoringFile = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+        if let Some(asset) = file.assets.iter().flatten().find(|a| parse_network(&a.cidr).is_none()) {
+            return Err(format!("invalid asset CIDR: {}", asset.cidr).into());
+        }
+        if let Some(weights) = file.weights {
+            self.weights = weights;
+        }
+        if let Some(severity) = file.severity {
+            self.severity = severity;
+        }
+        if let Some(criticality) = file.default_criticality {
+            self.default_criticality = criticality;
+        }
+        if let Some(assets) = file.assets {
+            self.assets = assets;
+        }
+        Ok(())
+    }
+
+    fn severity_base(&self, severity: Severity) -> f64 {
+        match severity {
+            Severity::Low => self.severity.low,
+            Severity::Medium => self.severity.medium,
+            Severity::High => self.severity.high,
+            Severity::Critical => self.severity.critical,
+        }
+    }
+
+    /// Criticality of an address: the inventory's tag for it, else the most
+    /// specific asset CIDR containing it.
+    pub fn criticality(&self, ip: IpAddr) -> f64 {
+        if let Some(criticality) = assets::tagged_criticality(ip) {
+            return criticality;
+        }
+        self.assets
+            .iter()
+            .filter(|a| cidr_contains(&a.cidr, ip))
+            .max_by_key(|a| parse_network(&a.cidr).map(|(_, _, prefix_len)| prefix_len))
+            .map(|a| a.criticality)
+            .unwrap_or(self.default_criticality)
+    }
+
+    /// The alert's 0-100 risk score, counting the LLM verdict once there is one.
+    pub fn score(&self, activity: &SuspiciousActivity, verdict: Option<&TriageVerdict>) -> f64 {
+        let addresses: Vec<IpAddr> = alert_addresses(activity).collect();
+        let detector = self.severity_base(activity.severity) * (0.5 + 0.5 * activity.confidence.clamp(0.0, 1.0));
+        let asset = addresses.iter().map(|ip| self.criticality(*ip)).reduce(f64::max).unwrap_or(self.default_criticality);
+        let intel = if intel_hit(activity, &addresses) { 1.0 } else { 0.0 };
+
+        let mut components = vec![
+            (self.weights.detector, detector),
+            (self.weights.asset, asset),
+            (self.weights.intel, intel),
+        ];
+        if let Some(verdict) = verdict {
+            components.push((self.weights.llm, self.severity_base(verdict.severity) * verdict.confidence.clamp(0.0, 1.0)));
+        }
+        let total_weight: f64 = components.iter().map(|(weight, _)| weight).sum();
+        if total_weight <= 0.0 {
+            return 0.0;
+        }
+        let score = 100.0 * components.iter().map(|(weight, value)| weight * value).sum::<f64>() / total_weight;
+        score.clamp(0.0, 100.0).round()
+    }
+}
+
+/// Addresses named by the alert's source, indicator and evidence peers.
+fn alert_addresses(activity: &SuspiciousActivity) -> impl Iterator<Item = IpAddr> + '_ {
+    [&activity.source, &activity.indicator]
+        .into_iter()
+        .chain(&activity.evidence.peers)
+        .filter_map(|value| value.parse().ok().or_else(|| split_endpoint(value)?.0.parse().ok()))
+}
+
+fn intel_hit(activity: &SuspiciousActivity, addresses: &[IpAddr]) -> bool {
+    if activity.rule_id == "threat_intel" {
+        return true;
+    }
+    let store = intel::store();
+    addresses.iter().any(|ip| store.match_ip(&ip.to_string()).is_some())
+        || activity.evidence.domains.iter().any(|name| store.match_domain(name).is_some())
+}
+
+/// The model loaded at first use.
+pub fn model() -> &'static ScoringModel {
+    static MODEL: OnceLock<ScoringModel> = OnceLock::new();
+    MODEL.get_or_init(ScoringModel::from_env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::Evidence;
+
+    fn activity(severity: Severity, confidence: f64, source: &str) -> SuspiciousActivity {
+        SuspiciousActivity {
+            activity_type: "Port Scan".to_string(),
+            source: source.to_string(),
+            details: String::new(),
+            indicator: "scan".to_string(),
+            magnitude: 1.0,
+            severity,
+            score: 0.0,
+            confidence,
+            evidence: Evidence::default(),
+            rule_id: "port_scan".to_string(),
+            timestamp: 0.0,
+        }
+    }
+
+    fn verdict(severity: Severity, confidence: f64) -> TriageVerdict {
+        TriageVerdict {
+            severity,
+            confidence,
+            category: "reconnaissance".to_string(),
+            rationale: String::new(),
+            suggested_actions: Vec::new(),
+        }
+    }
+
+    fn asset(cidr: &str, criticality: f64) -> AssetCriticality {
+        AssetCriticality { cidr: cidr.to_string(), criticality }
+    }
+
+    #[test]
+    fn builtin_model() {
+        let model = ScoringModel::builtin();
+        let weights = &model.weights;
+        assert_eq!((weights.detector, weights.asset, weights.intel, weights.llm), (0.5, 0.2, 0.15, 0.15));
+        let severity = &model.severity;
+        assert_eq!((severity.low, severity.medium, severity.high, severity.critical), (0.25, 0.5, 0.75, 1.0));
+        assert_eq!(model.default_criticality, 0.5);
+        assert!(model.assets.is_empty());
+    }
+
+    #[test]
+    fn weighted_mean_of_components() {
+        let model = ScoringModel::builtin();
+        // (0.5 * 1.0 + 0.2 * 0.5) / 0.85, the llm weight left out without a verdict
+        let alert = activity(Severity::Critical, 1.0, "10.0.0.5");
+        assert_eq!(model.score(&alert, None), 71.0);
+        // Confidence scales the detector's severity base between half and all of it
+        assert_eq!(model.score(&activity(Severity::Critical, 0.0, "10.0.0.5"), None), 41.0);
+        // 0.5 + 0.1 + 0.15 * (0.75 * 0.8)
+        assert_eq!(model.score(&alert, Some(&verdict(Severity::High, 0.8))), 69.0);
+
+        let mut intel = activity(Severity::Critical, 1.0, "10.0.0.5");
+        intel.rule_id = "threat_intel".to_string();
+        assert_eq!(model.score(&intel, None), 88.0);
+    }
+
+    #[test]
+    fn most_critical_host_counts() {
+        let mut model = ScoringModel::builtin();
+        model.assets = vec![asset("10.0.0.0/8", 0.2), asset("10.0.0.1/32", 1.0)];
+        assert_eq!(model.criticality("10.0.0.1".parse().unwrap()), 1.0);
+        assert_eq!(model.criticality("10.9.0.1".parse().unwrap()), 0.2);
+        assert_eq!(model.criticality("192.0.2.1".parse().unwrap()), 0.5);
+
+        let mut alert = activity(Severity::Medium, 1.0, "10.9.0.1:50000");
+        alert.evidence.peers = vec!["10.0.0.1:22".to_string()];
+        // (0.5 * 0.5 + 0.2 * 1.0) / 0.85
+        assert_eq!(model.score(&alert, None), 53.0);
+    }
+
+    #[test]
+    fn scores_are_clamped_to_0_100() {
+        let mut model = ScoringModel::builtin();
+        model.assets = vec![asset("10.0.0.0/8", 5.0)];
+        assert_eq!(model.score(&activity(Severity::Critical, 1.0, "10.0.0.5"), None), 100.0);
+        // Out-of-range confidences count as 0 or 1
+        assert_eq!(
+            model.score(&activity(Severity::Low, 7.0, "192.0.2.1"), Some(&verdict(Severity::Low, 9.0))),
+            model.score(&activity(Severity::Low, 1.0, "192.0.2.1"), Some(&verdict(Severity::Low, 1.0))),
+        );
+
+        model.assets = vec![asset("10.0.0.0/8", -10.0)];
+        assert_eq!(model.score(&activity(Severity::Low, 0.0, "10.0.0.5"), None), 0.0);
+
+        model.weights = Weights { detector: 0.0, asset: 0.0, intel: 0.0, llm: 0.0 };
+        assert_eq!(model.score(&activity(Severity::Critical, 1.0, "10.0.0.5"), None), 0.0);
+    }
+
+    #[test]
+    fn scoring_file_overrides_by_section() {
+        let path = env::temp_dir().join(format!("sniff-scoring-test-{}.yaml", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        fs::write(&path, "weights: {detector: 1.0, asset: 0.0, intel: 0.0, llm: 0.0}\nassets:\n  - cidr: 10.0.0.1/32\n    name: gateway\n    criticality: 1.0\n").unwrap();
+        let mut model = ScoringModel::builtin();
+        model.merge_file(&path).unwrap();
+        assert_eq!(model.weights.detector, 1.0);
+        assert_eq!(model.severity.high, 0.75);
+        assert_eq!(model.assets.len(), 1);
+        assert_eq!(model.score(&activity(Severity::High, 1.0, "10.0.0.5"), None), 75.0);
+
+        fs::write(&path, "assets:\n  - cidr: 10.0.0.300/32\n    criticality: 1.0\n").unwrap();
+        assert!(model.merge_file(&path).unwrap_err().to_string().contains("10.0.0.300/32"));
+        assert_eq!(model.assets[0].cidr, "10.0.0.1/32");
+
+        fs::write(&path, "weight: {detector: 1.0}\n").unwrap();
+        assert!(model.merge_file(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,10 @@
+pub mod dhcp;
+pub mod dns;
+pub mod domain;
+pub mod fingerprint;
+pub mod netbios;
+pub mod oui;
+
 use pcap::{Capture, Device, Active};
 use crossbeam_channel::Sender;
 use etherparse::{Ethernet2HeaderSlice, EtherType, IpNumber, Ipv4HeaderSlice, Ipv6HeaderSlice, TcpHeaderSlice, UdpHeaderSlice};
@@ -11,9 +18,49 @@ pub struct NetworkEvent {
     pub destination: String,
     pub payload_size: usize,
     pub timestamp: f64,
+    /// Ethernet source address; for routed traffic, the router's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_mac: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arp: Option<ArpInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<dns::DnsMessage>,
+    /// Multicast DNS (UDP 5353), kept apart from unicast DNS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mdns: Option<dns::DnsMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netbios: Option<netbios::NetbiosName>,
+    /// DHCPv4 (UDP 67/68) or DHCPv6 (UDP 546/547)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dhcp: Option<dhcp::DhcpMessage>,
+    /// TTL, DF and TCP options of a connection-opening SYN, for OS fingerprinting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_syn: Option<fingerprint::TcpSyn>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ArpInfo {
+    /// 1 = request, 2 = reply
+    pub operation: u16,
+    pub sender_mac: String,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: String,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpInfo {
+    pub const REQUEST: u16 = 1;
+    pub const REPLY: u16 = 2;
+
+    /// An announcement of the sender's own binding rather than a question:
+    /// sender and target IP are the same.
+    pub fn is_gratuitous(&self) -> bool {
+        self.sender_ip == self.target_ip
+    }
 }
 
 const DNS_PORT: u16 = 53;
+const MDNS_PORT: u16 = 5353;
 
 pub fn start_sniffing(interface: Option<&str>, sender: Sender<NetworkEvent>) -> Result<(), pcap::Error> {
     let mut cap = create_capture(interface)?;
@@ -31,7 +78,7 @@ fn create_capture(interface: Option<&str>) -> Result<Capture<Active>, pcap::Erro
     let device = match interface {
         Some(name) => Device::list()?.into_iter().find(|d| d.name == name),
         None => Device::list()?.into_iter().next(),
-    }.ok_or_else(|| pcap::Error::InvalidString)?;
+    }.ok_or(pcap::Error::InvalidString)?;
 
     Capture::from_device(device)?
         .promisc(false)
@@ -44,6 +91,11 @@ fn parse_packet(packet: &pcap::Packet) -> Option<NetworkEvent> {
     let eth = Ethernet2HeaderSlice::from_slice(packet.data).ok()?;
     let ether_type = eth.ether_type();
 
+    let arp = match ether_type {
+        EtherType::ARP => decode_arp(&packet.data[14..]),
+        _ => None,
+    };
+
     let (protocol, source, destination) = match ether_type {
         EtherType::ARP => parse_arp(&packet.data[14..]),
         EtherType::IPV4 => parse_ipv4(&packet.data[14..])?,
@@ -51,12 +103,38 @@ fn parse_packet(packet: &pcap::Packet) -> Option<NetworkEvent> {
         _ => return None,
     };
 
+    let dns = match protocol.as_str() {
+        "DNS" => decode_dns(&packet.data[14..], ether_type),
+        _ => None,
+    };
+    let tcp_syn = match ether_type {
+        EtherType::IPV4 | EtherType::IPV6 => decode_syn(&packet.data[14..], ether_type),
+        _ => None,
+    };
+    let udp = match protocol.as_str() {
+        "UDP" => udp_payload(&packet.data[14..], ether_type),
+        _ => None,
+    };
+    let on_port = |port: u16| udp.filter(|(src, dst, _)| *src == port || *dst == port).map(|(_, _, data)| data);
+    let mdns = on_port(MDNS_PORT).and_then(dns::parse);
+    let netbios = on_port(netbios::PORT).and_then(netbios::parse);
+    let dhcp = on_port(dhcp::SERVER_PORT)
+        .and_then(dhcp::parse_v4)
+        .or_else(|| on_port(dhcp::V6_SERVER_PORT).and_then(dhcp::parse_v6));
+
     Some(NetworkEvent {
         protocol,
         source,
         destination,
         payload_size: packet.data.len(),
         timestamp: get_timestamp(),
+        source_mac: Some(format_mac(&eth.source())),
+        arp,
+        dns,
+        mdns,
+        netbios,
+        dhcp,
+        tcp_syn,
     })
 }
 
@@ -78,6 +156,145 @@ fn parse_arp(payload: &[u8]) -> (String, String, String) {
     )
 }
 
+fn decode_arp(payload: &[u8]) -> Option<ArpInfo> {
+    if payload.len() < 28 {
+        return None;
+    }
+
+    Some(ArpInfo {
+        operation: u16::from_be_bytes([payload[6], payload[7]]),
+        sender_mac: format_mac(&payload[8..14]),
+        sender_ip: Ipv4Addr::new(payload[14], payload[15], payload[16], payload[17]),
+        target_mac: format_mac(&payload[18..24]),
+        target_ip: Ipv4Addr::new(payload[24], payload[25], payload[26], payload[27]),
+    })
+}
+
+/// The transport protocol and the IP packet's payload, without any Ethernet
+/// padding after the packet.
+fn ip_payload(payload: &[u8], ether_type: EtherType) -> Option<(IpNumber, &[u8])> {
+    match ether_type {
+        EtherType::IPV4 => {
+            let header = Ipv4HeaderSlice::from_slice(payload).ok()?;
+            let end = (header.total_len() as usize).clamp(header.slice().len(), payload.len());
+            Some((header.protocol(), &payload[header.slice().len()..end]))
+        }
+        EtherType::IPV6 => {
+            let header = Ipv6HeaderSlice::from_slice(payload).ok()?;
+            let rest = &payload[header.slice().len()..];
+            Some((header.next_header(), &rest[..rest.len().min(header.payload_length() as usize)]))
+        }
+        _ => None,
+    }
+}
+
+/// A UDP datagram's source port, destination port and payload.
+fn udp_payload(payload: &[u8], ether_type: EtherType) -> Option<(u16, u16, &[u8])> {
+    match ip_payload(payload, ether_type)? {
+        (IpNumber::UDP, payload) => {
+            let header = UdpHeaderSlice::from_slice(payload).ok()?;
+            Some((header.source_port(), header.destination_port(), payload.get(8..)?))
+        }
+        _ => None,
+    }
+}
+
+fn decode_dns(payload: &[u8], ether_type: EtherType) -> Option<dns::DnsMessage> {
+    let (protocol, payload) = ip_payload(payload, ether_type)?;
+    match protocol {
+        IpNumber::UDP => dns::parse(payload.get(8..)?),
+        IpNumber::TCP => {
+            let header = TcpHeaderSlice::from_slice(payload).ok()?;
+            // DNS over TCP prefixes each message with its length. Only a
+            // segment holding exactly one whole message is decoded; the rest
+            // of a message split across segments would be read as a header.
+            let segment = payload.get(header.slice().len()..)?;
+            let len = u16::from_be_bytes([*segment.first()?, *segment.get(1)?]) as usize;
+            let message = &segment[2..];
+            if message.len() != len {
+                return None;
+            }
+            dns::parse(message)
+        }
+        _ => None,
+    }
+}
+
+/// The fingerprintable fields of a SYN without ACK, which only the side
+/// opening a connection sends.
+fn decode_syn(payload: &[u8], ether_type: EtherType) -> Option<fingerprint::TcpSyn> {
+    let mut quirks = Vec::new();
+    let (version, ttl, ip_options_len, segment) = match ether_type {
+        EtherType::IPV4 => {
+            let header = Ipv4HeaderSlice::from_slice(payload).ok()?;
+            if header.protocol() != IpNumber::TCP {
+                return None;
+            }
+            let df = header.dont_fragment();
+            if df {
+                quirks.push("df");
+            }
+            match (df, header.identification()) {
+                (true, id) if id != 0 => quirks.push("id+"),
+                (false, 0) => quirks.push("id-"),
+                _ => {}
+            }
+            if header.ecn().value() != 0 {
+                quirks.push("ecn");
+            }
+            // Short frames carry Ethernet padding past the IP packet
+            let end = (header.total_len() as usize).min(payload.len());
+            let segment = payload.get(header.slice().len()..end)?;
+            (4, header.ttl(), header.options().len() as u8, segment)
+        }
+        EtherType::IPV6 => {
+            let header = Ipv6HeaderSlice::from_slice(payload).ok()?;
+            if header.next_header() != IpNumber::TCP {
+                return None;
+            }
+            if header.flow_label().value() != 0 {
+                quirks.push("flow");
+            }
+            if header.traffic_class() & 0x03 != 0 {
+                quirks.push("ecn");
+            }
+            let end = (header.slice().len() + header.payload_length() as usize).min(payload.len());
+            (6, header.hop_limit(), 0, payload.get(header.slice().len()..end)?)
+        }
+        _ => return None,
+    };
+
+    let tcp = TcpHeaderSlice::from_slice(segment).ok()?;
+    if !tcp.syn() || tcp.ack() {
+        return None;
+    }
+    if tcp.ece() || tcp.cwr() {
+        quirks.push("ecn");
+    }
+    if tcp.sequence_number() == 0 {
+        quirks.push("seq-");
+    }
+    if tcp.acknowledgment_number() != 0 {
+        quirks.push("ack+");
+    }
+    let options = fingerprint::read_options(tcp.options());
+    quirks.extend(options.quirks);
+    quirks.sort();
+    quirks.dedup();
+
+    Some(fingerprint::TcpSyn {
+        version,
+        ttl,
+        ip_options_len,
+        window: tcp.window_size(),
+        mss: options.mss,
+        window_scale: options.window_scale,
+        layout: options.layout,
+        quirks: quirks.into_iter().map(str::to_string).collect(),
+        payload: segment.len() > tcp.slice().len(),
+    })
+}
+
 fn parse_ipv4(payload: &[u8]) -> Option<(String, String, String)> {
     let ip_header = Ipv4HeaderSlice::from_slice(payload).ok()?;
     let src_ip = ip_header.source_addr();
@@ -161,4 +378,50 @@ fn get_timestamp() -> f64 {
         .expect("Time went backwards");
 
     now.as_secs() as f64 + (now.subsec_micros() as f64 / 1_000_000.0)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal DNS query for "a.io"
+    const QUERY: [u8; 22] = [0x12, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, b'a', 2, b'i', b'o', 0, 0, 1, 0, 1];
+
+    fn tcp_packet(segment: &[u8], padding: usize) -> Vec<u8> {
+        let total = (40 + segment.len()) as u16;
+        let mut packet = vec![0x45, 0];
+        packet.extend(total.to_be_bytes());
+        packet.extend([0, 0, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 5, 10, 0, 0, 53]);
+        packet.extend([0xC3, 0x50, 0, 53, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xFF, 0xFF, 0, 0, 0, 0]);
+        packet.extend(segment);
+        packet.extend(vec![0; padding]);
+        packet
+    }
+
+    fn prefixed(message: &[u8]) -> Vec<u8> {
+        let mut segment = (message.len() as u16).to_be_bytes().to_vec();
+        segment.extend(message);
+        segment
+    }
+
+    #[test]
+    fn decodes_whole_dns_message_over_tcp() {
+        let message = decode_dns(&tcp_packet(&prefixed(&QUERY), 0), EtherType::IPV4).unwrap();
+        assert_eq!(message.query_name(), Some("a.io"));
+        // Ethernet padding after the IP packet is not part of the segment
+        assert!(decode_dns(&tcp_packet(&prefixed(&QUERY), 6), EtherType::IPV4).is_some());
+    }
+
+    #[test]
+    fn skips_tcp_segments_that_are_not_one_dns_message() {
+        // First half of a message split across segments
+        let segment = prefixed(&QUERY);
+        assert!(decode_dns(&tcp_packet(&segment[..14], 0), EtherType::IPV4).is_none());
+        // A continuation segment, which starts mid-message
+        assert!(decode_dns(&tcp_packet(&QUERY[4..], 0), EtherType::IPV4).is_none());
+        // Two messages in one segment
+        let mut segment = prefixed(&QUERY);
+        segment.extend(prefixed(&QUERY));
+        assert!(decode_dns(&tcp_packet(&segment, 0), EtherType::IPV4).is_none());
+        assert!(decode_dns(&tcp_packet(&[0], 0), EtherType::IPV4).is_none());
+    }
+}
@@ -0,0 +1,424 @@
+// DHCP decoding: DHCPv4 (RFC 2131/2132) and DHCPv6 (RFC 8415), enough for
+// rogue server and starvation detection and the asset inventory. From v4 the
+// BOOTP header and options 12 (hostname), 50 (requested address), 53
+// (message type), 54 (server identifier) and 60 (vendor class); from v6 the
+// client and server DUIDs, IA_NA addresses, the vendor class and the client
+// FQDN. Message types keep each version's own numbering.
+
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub const SERVER_PORT: u16 = 67;
+pub const V6_SERVER_PORT: u16 = 547;
+
+pub const DISCOVER: u8 = 1;
+pub const OFFER: u8 = 2;
+pub const REQUEST: u8 = 3;
+pub const DECLINE: u8 = 4;
+pub const ACK: u8 = 5;
+pub const NAK: u8 = 6;
+pub const RELEASE: u8 = 7;
+pub const INFORM: u8 = 8;
+
+pub const V6_SOLICIT: u8 = 1;
+pub const V6_ADVERTISE: u8 = 2;
+pub const V6_REQUEST: u8 = 3;
+pub const V6_RENEW: u8 = 5;
+pub const V6_REBIND: u8 = 6;
+pub const V6_REPLY: u8 = 7;
+
+const BOOTP_LEN: usize = 236;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTION_PAD: u8 = 0;
+const OPTION_HOSTNAME: u8 = 12;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_VENDOR_CLASS: u8 = 60;
+const OPTION_END: u8 = 255;
+
+const V6_OPTION_CLIENT_ID: u16 = 1;
+const V6_OPTION_SERVER_ID: u16 = 2;
+const V6_OPTION_IA_NA: u16 = 3;
+const V6_OPTION_IAADDR: u16 = 5;
+const V6_OPTION_VENDOR_CLASS: u16 = 16;
+const V6_OPTION_CLIENT_FQDN: u16 = 39;
+const V6_OPTION_RELAY_MSG: u16 = 9;
+// Relay-forward and relay-reply wrap the client's message
+const V6_RELAY_FORW: u8 = 12;
+const V6_RELAY_REPL: u8 = 13;
+// Relays drop messages past this many hops (HOP_COUNT_LIMIT, RFC 8415)
+const V6_MAX_RELAY_DEPTH: usize = 8;
+const HARDWARE_ETHERNET: u16 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DhcpMessage {
+    /// 4 or 6
+    pub version: u8,
+    pub message_type: u8,
+    pub transaction_id: u32,
+    /// chaddr, or the link-layer address in a v6 client DUID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_mac: Option<String>,
+    /// ciaddr: the address a bound client is renewing from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<IpAddr>,
+    /// yiaddr, or the first IA_NA address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assigned_ip: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requested_ip: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor_class: Option<String>,
+    /// The server identifier address (v4) or DUID in hex (v6)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_id: Option<String>,
+}
+
+impl DhcpMessage {
+    /// An OFFER/ACK/NAK or ADVERTISE/REPLY, which only servers send.
+    pub fn is_from_server(&self) -> bool {
+        match self.version {
+            4 => matches!(self.message_type, OFFER | ACK | NAK),
+            _ => matches!(self.message_type, V6_ADVERTISE | V6_REPLY),
+        }
+    }
+
+    /// A DISCOVER or SOLICIT, a client looking for a lease.
+    pub fn is_discover(&self) -> bool {
+        match self.version {
+            4 => self.message_type == DISCOVER,
+            _ => self.message_type == V6_SOLICIT,
+        }
+    }
+
+    /// A client asking for, renewing or confirming its lease.
+    pub fn is_request(&self) -> bool {
+        match self.version {
+            4 => matches!(self.message_type, REQUEST | INFORM),
+            _ => matches!(self.message_type, V6_REQUEST | V6_RENEW | V6_REBIND),
+        }
+    }
+
+    /// A server confirming a lease.
+    pub fn is_ack(&self) -> bool {
+        match self.version {
+            4 => self.message_type == ACK,
+            _ => self.message_type == V6_REPLY,
+        }
+    }
+
+    pub fn message_name(&self) -> &'static str {
+        match (self.version, self.message_type) {
+            (4, DISCOVER) => "DISCOVER",
+            (4, OFFER) => "OFFER",
+            (4, REQUEST) => "REQUEST",
+            (4, DECLINE) => "DECLINE",
+            (4, ACK) => "ACK",
+            (4, NAK) => "NAK",
+            (4, RELEASE) => "RELEASE",
+            (4, INFORM) => "INFORM",
+            (6, V6_SOLICIT) => "SOLICIT",
+            (6, V6_ADVERTISE) => "ADVERTISE",
+            (6, V6_REQUEST) => "REQUEST",
+            (6, V6_RENEW) => "RENEW",
+            (6, V6_REBIND) => "REBIND",
+            (6, V6_REPLY) => "REPLY",
+            _ => "OTHER",
+        }
+    }
+}
+
+/// Decodes a DHCPv4 message from a UDP payload.
+pub fn parse_v4(data: &[u8]) -> Option<DhcpMessage> {
+    if data.len() < BOOTP_LEN + MAGIC_COOKIE.len() || data[BOOTP_LEN..BOOTP_LEN + 4] != MAGIC_COOKIE {
+        return None;
+    }
+    let hardware_len = data[2] as usize;
+    let client_mac = (data[1] == HARDWARE_ETHERNET as u8 && hardware_len == 6).then(|| format_mac(&data[28..34]));
+
+    let mut message = DhcpMessage {
+        version: 4,
+        message_type: 0,
+        transaction_id: u32::from_be_bytes(data[4..8].try_into().ok()?),
+        client_mac,
+        client_ip: read_v4(&data[12..16]),
+        assigned_ip: read_v4(&data[16..20]),
+        requested_ip: None,
+        hostname: None,
+        vendor_class: None,
+        server_id: None,
+    };
+
+    let mut offset = BOOTP_LEN + MAGIC_COOKIE.len();
+    while let Some(&code) = data.get(offset) {
+        match code {
+            OPTION_PAD => {
+                offset += 1;
+                continue;
+            }
+            OPTION_END => break,
+            _ => {}
+        }
+        let len = *data.get(offset + 1)? as usize;
+        let value = data.get(offset + 2..offset + 2 + len)?;
+        match code {
+            OPTION_MESSAGE_TYPE => message.message_type = *value.first()?,
+            OPTION_HOSTNAME => message.hostname = read_text(value),
+            OPTION_VENDOR_CLASS => message.vendor_class = read_text(value),
+            OPTION_REQUESTED_IP => message.requested_ip = read_v4(value),
+            OPTION_SERVER_ID => message.server_id = read_v4(value).map(|ip| ip.to_string()),
+            _ => {}
+        }
+        offset += 2 + len;
+    }
+    // Plain BOOTP has no message type
+    (message.message_type != 0).then_some(message)
+}
+
+/// Decodes a DHCPv6 message from a UDP payload, unwrapping relay messages.
+pub fn parse_v6(data: &[u8]) -> Option<DhcpMessage> {
+    let mut data = data;
+    let mut depth = 0;
+    let mut message_type = *data.first()?;
+    while message_type == V6_RELAY_FORW || message_type == V6_RELAY_REPL {
+        depth += 1;
+        if depth > V6_MAX_RELAY_DEPTH {
+            return None;
+        }
+        // hop count, link address and peer address, then options
+        data = v6_options(data.get(34..)?).find(|(code, _)| *code == V6_OPTION_RELAY_MSG)?.1;
+        message_type = *data.first()?;
+    }
+
+    let mut message = DhcpMessage {
+        version: 6,
+        message_type,
+        transaction_id: u32::from_be_bytes([0, *data.get(1)?, *data.get(2)?, *data.get(3)?]),
+        client_mac: None,
+        client_ip: None,
+        assigned_ip: None,
+        requested_ip: None,
+        hostname: None,
+        vendor_class: None,
+        server_id: None,
+    };
+    for (code, value) in v6_options(data.get(4..)?) {
+        match code {
+            V6_OPTION_CLIENT_ID => message.client_mac = duid_mac(value),
+            V6_OPTION_SERVER_ID => message.server_id = Some(hex(value)),
+            V6_OPTION_IA_NA if value.len() > 12 => {
+                let address = v6_options(&value[12..])
+                    .find(|(code, value)| *code == V6_OPTION_IAADDR && value.len() >= 16)
+                    .and_then(|(_, value)| <[u8; 16]>::try_from(&value[..16]).ok())
+                    .map(|octets| IpAddr::V6(Ipv6Addr::from(octets)));
+                // Clients put the address they want in their own IA_NA
+                if message.is_from_server() {
+                    message.assigned_ip = address;
+                } else {
+                    message.requested_ip = address;
+                }
+            }
+            V6_OPTION_VENDOR_CLASS if value.len() > 6 => {
+                let len = u16::from_be_bytes([value[4], value[5]]) as usize;
+                message.vendor_class = value.get(6..6 + len).and_then(read_text);
+            }
+            V6_OPTION_CLIENT_FQDN if value.len() > 1 => message.hostname = read_wire_name(&value[1..]),
+            _ => {}
+        }
+    }
+    Some(message)
+}
+
+fn v6_options(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let code = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+        let len = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as usize;
+        let value = data.get(4..4 + len)?;
+        data = &data[4 + len..];
+        Some((code, value))
+    })
+}
+
+/// The link-layer address in a DUID-LLT (type 1) or DUID-LL (type 3).
+fn duid_mac(duid: &[u8]) -> Option<String> {
+    let duid_type = u16::from_be_bytes([*duid.first()?, *duid.get(1)?]);
+    let hardware = u16::from_be_bytes([*duid.get(2)?, *duid.get(3)?]);
+    let address = match duid_type {
+        1 => duid.get(8..)?,
+        3 => duid.get(4..)?,
+        _ => return None,
+    };
+    (hardware == HARDWARE_ETHERNET && address.len() == 6).then(|| format_mac(address))
+}
+
+/// An uncompressed DNS wire-format name, as in the client FQDN option.
+fn read_wire_name(data: &[u8]) -> Option<String> {
+    let mut labels = Vec::new();
+    let mut offset = 0;
+    while let Some(&len) = data.get(offset) {
+        if len == 0 {
+            break;
+        }
+        labels.push(String::from_utf8_lossy(data.get(offset + 1..offset + 1 + len as usize)?).to_lowercase());
+        offset += 1 + len as usize;
+    }
+    (!labels.is_empty()).then(|| labels.join("."))
+}
+
+fn read_v4(value: &[u8]) -> Option<IpAddr> {
+    let octets: [u8; 4] = value.get(..4)?.try_into().ok()?;
+    let ip = Ipv4Addr::from(octets);
+    (!ip.is_unspecified()).then_some(IpAddr::V4(ip))
+}
+
+fn read_text(value: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(value).trim_matches(char::from(0)).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn format_mac(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    fn v4_packet(options: &[u8]) -> Vec<u8> {
+        let mut data = vec![0u8; BOOTP_LEN];
+        data[0] = 1;
+        data[1] = HARDWARE_ETHERNET as u8;
+        data[2] = 6;
+        data[4..8].copy_from_slice(&0xdeadbeef_u32.to_be_bytes());
+        data[16..20].copy_from_slice(&[192, 168, 1, 50]);
+        data[28..34].copy_from_slice(&MAC);
+        data.extend_from_slice(&MAGIC_COOKIE);
+        data.extend_from_slice(options);
+        data
+    }
+
+    fn v6_option(code: u16, value: &[u8]) -> Vec<u8> {
+        let mut option = code.to_be_bytes().to_vec();
+        option.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        option.extend_from_slice(value);
+        option
+    }
+
+    fn v6_packet(message_type: u8, options: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![message_type, 0x12, 0x34, 0x56];
+        data.extend(options.concat());
+        data
+    }
+
+    fn relay(inner: &[u8]) -> Vec<u8> {
+        let mut data = vec![V6_RELAY_FORW, 0];
+        data.extend_from_slice(&[0u8; 32]);
+        data.extend(v6_option(V6_OPTION_RELAY_MSG, inner));
+        data
+    }
+
+    fn solicit() -> Vec<u8> {
+        let duid_ll = [&[0, 3, 0, 1][..], &MAC].concat();
+        let mut ia_na = vec![0u8; 12];
+        let address: Ipv6Addr = "2001:db8::42".parse().unwrap();
+        ia_na.extend(v6_option(V6_OPTION_IAADDR, &[&address.octets()[..], &[0u8; 8]].concat()));
+        let vendor_class = [&[0, 0, 0x01, 0x37, 0, 7][..], b"android"].concat();
+        let fqdn = [&[0u8][..], b"\x05phone\x04home\x00"].concat();
+        v6_packet(V6_SOLICIT, &[
+            v6_option(V6_OPTION_CLIENT_ID, &duid_ll),
+            v6_option(V6_OPTION_IA_NA, &ia_na),
+            v6_option(V6_OPTION_VENDOR_CLASS, &vendor_class),
+            v6_option(V6_OPTION_CLIENT_FQDN, &fqdn),
+        ])
+    }
+
+    #[test]
+    fn parses_v4_options() {
+        let mut options = vec![OPTION_MESSAGE_TYPE, 1, OFFER, OPTION_PAD];
+        options.extend_from_slice(&[OPTION_SERVER_ID, 4, 192, 168, 1, 1]);
+        options.extend_from_slice(&[OPTION_REQUESTED_IP, 4, 192, 168, 1, 60]);
+        options.extend_from_slice(&[OPTION_HOSTNAME, 6]);
+        options.extend_from_slice(b"laptop");
+        options.extend_from_slice(&[OPTION_VENDOR_CLASS, 8]);
+        options.extend_from_slice(b"MSFT 5.0");
+        options.extend_from_slice(&[OPTION_END, OPTION_MESSAGE_TYPE, 1, ACK]);
+
+        let message = parse_v4(&v4_packet(&options)).unwrap();
+        assert_eq!(message.message_name(), "OFFER");
+        assert!(message.is_from_server());
+        assert_eq!(message.transaction_id, 0xdeadbeef);
+        assert_eq!(message.client_mac.as_deref(), Some("02:11:22:33:44:55"));
+        assert_eq!(message.client_ip, None);
+        assert_eq!(message.assigned_ip, Some("192.168.1.50".parse().unwrap()));
+        assert_eq!(message.requested_ip, Some("192.168.1.60".parse().unwrap()));
+        assert_eq!(message.server_id.as_deref(), Some("192.168.1.1"));
+        assert_eq!(message.hostname.as_deref(), Some("laptop"));
+        assert_eq!(message.vendor_class.as_deref(), Some("MSFT 5.0"));
+    }
+
+    #[test]
+    fn rejects_bootp_and_truncated_v4() {
+        assert!(parse_v4(&v4_packet(&[OPTION_END])).is_none());
+        assert!(parse_v4(&v4_packet(&[OPTION_MESSAGE_TYPE, 1, DISCOVER, OPTION_HOSTNAME, 10, b'a'])).is_none());
+        assert!(parse_v4(&v4_packet(&[OPTION_MESSAGE_TYPE, 1, DISCOVER])[..BOOTP_LEN]).is_none());
+
+        let mut wrong_cookie = v4_packet(&[OPTION_MESSAGE_TYPE, 1, DISCOVER]);
+        wrong_cookie[BOOTP_LEN] = 0;
+        assert!(parse_v4(&wrong_cookie).is_none());
+        assert!(parse_v4(&v4_packet(&[OPTION_MESSAGE_TYPE, 1, DISCOVER])).unwrap().is_discover());
+    }
+
+    #[test]
+    fn parses_v6_solicit() {
+        let message = parse_v6(&solicit()).unwrap();
+        assert_eq!(message.version, 6);
+        assert!(message.is_discover());
+        assert_eq!(message.transaction_id, 0x123456);
+        assert_eq!(message.client_mac.as_deref(), Some("02:11:22:33:44:55"));
+        assert_eq!(message.requested_ip, Some("2001:db8::42".parse().unwrap()));
+        assert_eq!(message.assigned_ip, None);
+        assert_eq!(message.vendor_class.as_deref(), Some("android"));
+        assert_eq!(message.hostname.as_deref(), Some("phone.home"));
+    }
+
+    #[test]
+    fn v6_servers_assign_addresses() {
+        let duid_llt = [&[0, 1, 0, 1, 0x2a, 0x3b, 0x4c, 0x5d][..], &MAC].concat();
+        let mut ia_na = vec![0u8; 12];
+        ia_na.extend(v6_option(V6_OPTION_IAADDR, &[&"2001:db8::9".parse::<Ipv6Addr>().unwrap().octets()[..], &[0u8; 8]].concat()));
+        let reply = v6_packet(V6_REPLY, &[v6_option(V6_OPTION_SERVER_ID, &duid_llt), v6_option(V6_OPTION_IA_NA, &ia_na)]);
+
+        let message = parse_v6(&reply).unwrap();
+        assert!(message.is_ack());
+        assert_eq!(message.server_id.as_deref(), Some("000100012a3b4c5d021122334455"));
+        assert_eq!(message.assigned_ip, Some("2001:db8::9".parse().unwrap()));
+    }
+
+    #[test]
+    fn unwraps_v6_relays() {
+        let once = relay(&solicit());
+        let message = parse_v6(&once).unwrap();
+        assert_eq!(message.message_name(), "SOLICIT");
+        assert_eq!(message.client_mac.as_deref(), Some("02:11:22:33:44:55"));
+
+        let mut nested = solicit();
+        for _ in 0..V6_MAX_RELAY_DEPTH {
+            nested = relay(&nested);
+        }
+        assert!(parse_v6(&nested).is_some());
+        assert!(parse_v6(&relay(&nested)).is_none());
+
+        // A relay without a relayed message, or cut short
+        assert!(parse_v6(&[V6_RELAY_FORW, 0]).is_none());
+        assert!(parse_v6(&once[..once.len() - 1]).is_none());
+    }
+}
@@ -0,0 +1,418 @@
+// DNS wire-format decoding (RFC 1035), enough for detection: the header,
+// questions and the answer/authority records with their data rendered as
+// text. Compression pointers are followed with a hop limit so a malicious
+// packet cannot loop the decoder.
+
+use super::domain::DomainName;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const HEADER_LEN: usize = 12;
+const MAX_POINTER_HOPS: usize = 16;
+const MAX_NAME_LEN: usize = 255;
+// Caps on records decoded per section, well above anything legitimate
+const MAX_RECORDS: usize = 64;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_NULL: u16 = 10;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_SERVFAIL: u8 = 2;
+pub const RCODE_NXDOMAIN: u8 = 3;
+
+const TYPE_NAMES: [(u16, &str); 8] = [
+    (TYPE_A, "A"),
+    (TYPE_NS, "NS"),
+    (TYPE_CNAME, "CNAME"),
+    (TYPE_NULL, "NULL"),
+    (TYPE_PTR, "PTR"),
+    (TYPE_MX, "MX"),
+    (TYPE_TXT, "TXT"),
+    (TYPE_AAAA, "AAAA"),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub is_response: bool,
+    pub opcode: u8,
+    pub rcode: u8,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: u16,
+    /// `name`'s registrable domain per the Public Suffix List
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registered_domain: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsRecord {
+    pub name: String,
+    pub rtype: u16,
+    pub ttl: u32,
+    /// Addresses and names as text, TXT strings joined, otherwise empty
+    pub data: String,
+    /// Length of the raw RDATA
+    pub data_len: usize,
+}
+
+impl DnsMessage {
+    /// The first question's name, which is the one every resolver uses.
+    pub fn query_name(&self) -> Option<&str> {
+        self.questions.first().map(|q| q.name.as_str())
+    }
+
+    pub fn query_type(&self) -> Option<u16> {
+        self.questions.first().map(|q| q.qtype)
+    }
+
+    /// The first A or AAAA answer.
+    pub fn resolved_ip(&self) -> Option<&str> {
+        self.answers
+            .iter()
+            .find(|r| r.rtype == TYPE_A || r.rtype == TYPE_AAAA)
+            .map(|r| r.data.as_str())
+    }
+}
+
+/// The address a reverse-lookup name stands for, e.g. 4.3.2.1.in-addr.arpa.
+pub fn ptr_address(name: &str) -> Option<IpAddr> {
+    let name = name.trim_end_matches('.').to_lowercase();
+    if let Some(reversed) = name.strip_suffix(".in-addr.arpa") {
+        let octets: Vec<u8> = reversed.split('.').rev().map(|o| o.parse().ok()).collect::<Option<_>>()?;
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+    let reversed = name.strip_suffix(".ip6.arpa")?;
+    let nibbles: String = reversed.split('.').rev().collect();
+    if nibbles.len() != 32 {
+        return None;
+    }
+    Some(IpAddr::V6(Ipv6Addr::from(u128::from_str_radix(&nibbles, 16).ok()?)))
+}
+
+/// "A", "TXT"... or "TYPE65" for types without a name here.
+pub fn type_name(rtype: u16) -> String {
+    TYPE_NAMES
+        .iter()
+        .find(|(t, _)| *t == rtype)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("TYPE{}", rtype))
+}
+
+/// Type numbers and names, for mapping `qtype` in stored events.
+pub fn type_names() -> &'static [(u16, &'static str)] {
+    &TYPE_NAMES
+}
+
+/// Decodes a DNS message. Over TCP, strip the two-byte length prefix first.
+pub fn parse(data: &[u8]) -> Option<DnsMessage> {
+    if data.len() < HEADER_LEN {
+        return None;
+    }
+
+    let flags = u16::from_be_bytes([data[2], data[3]]);
+    let qdcount = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let ancount = u16::from_be_bytes([data[6], data[7]]) as usize;
+    let nscount = u16::from_be_bytes([data[8], data[9]]) as usize;
+
+    let mut offset = HEADER_LEN;
+    let mut questions = Vec::new();
+    for _ in 0..qdcount.min(MAX_RECORDS) {
+        let (name, next) = read_name(data, offset)?;
+        let qtype = read_u16(data, next)?;
+        let registered_domain = DomainName::parse(&name).registrable;
+        questions.push(DnsQuestion { name, qtype, registered_domain });
+        offset = next + 4;
+    }
+
+    let mut answers = Vec::new();
+    for _ in 0..ancount.min(MAX_RECORDS) {
+        let (record, next) = read_record(data, offset)?;
+        answers.push(record);
+        offset = next;
+    }
+
+    // Authority records are a bonus; a truncated section keeps what decoded
+    let mut authorities = Vec::new();
+    for _ in 0..nscount.min(MAX_RECORDS) {
+        match read_record(data, offset) {
+            Some((record, next)) => {
+                authorities.push(record);
+                offset = next;
+            }
+            None => break,
+        }
+    }
+
+    Some(DnsMessage {
+        id: u16::from_be_bytes([data[0], data[1]]),
+        is_response: flags & 0x8000 != 0,
+        opcode: ((flags >> 11) & 0x0F) as u8,
+        rcode: (flags & 0x000F) as u8,
+        questions,
+        answers,
+        authorities,
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// Reads a possibly compressed name, returning it lowercased without the
+/// trailing dot and the offset just past it in the original position.
+fn read_name(data: &[u8], start: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut offset = start;
+    let mut end = None;
+    let mut hops = 0;
+    let mut length = 0;
+
+    loop {
+        let len = *data.get(offset)? as usize;
+        match len & 0xC0 {
+            0x00 if len == 0 => {
+                end.get_or_insert(offset + 1);
+                break;
+            }
+            0x00 => {
+                let label = data.get(offset + 1..offset + 1 + len)?;
+                length += len + 1;
+                if length > MAX_NAME_LEN {
+                    return None;
+                }
+                labels.push(String::from_utf8_lossy(label).to_lowercase());
+                offset += len + 1;
+            }
+            0xC0 => {
+                hops += 1;
+                if hops > MAX_POINTER_HOPS {
+                    return None;
+                }
+                let pointer = (read_u16(data, offset)? & 0x3FFF) as usize;
+                end.get_or_insert(offset + 2);
+                offset = pointer;
+            }
+            _ => return None,
+        }
+    }
+
+    Some((labels.join("."), end?))
+}
+
+fn read_record(data: &[u8], offset: usize) -> Option<(DnsRecord, usize)> {
+    let (name, next) = read_name(data, offset)?;
+    let rtype = read_u16(data, next)?;
+    let ttl = read_u32(data, next + 4)?;
+    let rdlength = read_u16(data, next + 8)? as usize;
+    let rdata_start = next + 10;
+    let rdata = data.get(rdata_start..rdata_start + rdlength)?;
+
+    let data_text = match rtype {
+        TYPE_A if rdlength == 4 => Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]).to_string(),
+        TYPE_AAAA if rdlength == 16 => {
+            let octets: [u8; 16] = rdata.try_into().ok()?;
+            Ipv6Addr::from(octets).to_string()
+        }
+        TYPE_NS | TYPE_CNAME | TYPE_PTR => read_name(data, rdata_start).map(|(n, _)| n).unwrap_or_default(),
+        TYPE_MX => read_name(data, rdata_start + 2).map(|(n, _)| n).unwrap_or_default(),
+        TYPE_TXT => read_character_strings(rdata),
+        _ => String::new(),
+    };
+
+    let record = DnsRecord {
+        name,
+        rtype,
+        ttl,
+        data: data_text,
+        data_len: rdlength,
+    };
+    Some((record, rdata_start + rdlength))
+}
+
+fn read_character_strings(rdata: &[u8]) -> String {
+    let mut parts = Vec::new();
+    let mut offset = 0;
+    while let Some(&len) = rdata.get(offset) {
+        let end = (offset + 1 + len as usize).min(rdata.len());
+        parts.push(String::from_utf8_lossy(&rdata[offset + 1..end]).into_owned());
+        offset = end;
+    }
+    parts.join("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(flags: u16, questions: u16, answers: u16, authorities: u16) -> Vec<u8> {
+        let mut data = vec![0x12, 0x34];
+        for value in [flags, questions, answers, authorities, 0] {
+            data.extend(value.to_be_bytes());
+        }
+        data
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        for label in name.split('.') {
+            data.push(label.len() as u8);
+            data.extend(label.as_bytes());
+        }
+        data.push(0);
+        data
+    }
+
+    fn question(data: &mut Vec<u8>, qname: &str, qtype: u16) {
+        data.extend(name(qname));
+        data.extend(qtype.to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+    }
+
+    // A record whose name points back at the question
+    fn record(data: &mut Vec<u8>, rtype: u16, rdata: &[u8]) {
+        data.extend([0xC0, 0x0C]);
+        data.extend(rtype.to_be_bytes());
+        data.extend(1u16.to_be_bytes());
+        data.extend(300u32.to_be_bytes());
+        data.extend((rdata.len() as u16).to_be_bytes());
+        data.extend(rdata);
+    }
+
+    #[test]
+    fn parses_query() {
+        let mut data = header(0x0100, 1, 0, 0);
+        question(&mut data, "WWW.Example.com", TYPE_A);
+        let message = parse(&data).unwrap();
+        assert_eq!(message.id, 0x1234);
+        assert!(!message.is_response);
+        assert_eq!(message.query_name(), Some("www.example.com"));
+        assert_eq!(message.query_type(), Some(TYPE_A));
+        assert_eq!(message.questions[0].registered_domain.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn parses_compressed_answers() {
+        let mut data = header(0x8180, 1, 3, 0);
+        question(&mut data, "www.example.com", TYPE_A);
+        // CNAME data "cdn" + pointer to "example.com" at offset 16
+        record(&mut data, TYPE_CNAME, &[3, b'c', b'd', b'n', 0xC0, 16]);
+        record(&mut data, TYPE_A, &[93, 184, 216, 34]);
+        record(&mut data, TYPE_TXT, &[2, b'h', b'i', 9, b'x']);
+
+        let message = parse(&data).unwrap();
+        assert!(message.is_response);
+        assert_eq!(message.rcode, RCODE_NOERROR);
+        assert_eq!(message.answers.len(), 3);
+        assert_eq!(message.answers[0].name, "www.example.com");
+        assert_eq!(message.answers[0].data, "cdn.example.com");
+        assert_eq!(message.resolved_ip(), Some("93.184.216.34"));
+        // A character-string longer than the record is cut at its end
+        assert_eq!(message.answers[2].data, "hix");
+    }
+
+    #[test]
+    fn rejects_pointer_loops() {
+        // The question name points at itself
+        let mut data = header(0x0100, 1, 0, 0);
+        data.extend([0xC0, 0x0C, 0, 1, 0, 1]);
+        assert!(parse(&data).is_none());
+
+        // Two names pointing at each other
+        let mut data = header(0x0100, 1, 0, 0);
+        data.extend([1, b'a', 0xC0, 0x10, 1, b'b', 0xC0, 0x0C, 0, 1, 0, 1]);
+        assert!(read_name(&data, 12).is_none());
+    }
+
+    #[test]
+    fn pointer_chains_have_a_hop_limit() {
+        // Each pointer jumps two bytes back until the name at offset 12
+        let mut data = header(0x0100, 0, 0, 0);
+        data.extend(name("a"));
+        let first = data.len();
+        data.extend([0xC0, 12]);
+        for hop in 0..MAX_POINTER_HOPS {
+            let target = (first + 2 * hop) as u16;
+            data.extend((0xC000 | target).to_be_bytes());
+        }
+        assert_eq!(read_name(&data, first + 2 * (MAX_POINTER_HOPS - 1)).map(|(n, _)| n).as_deref(), Some("a"));
+        assert!(read_name(&data, first + 2 * MAX_POINTER_HOPS).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        assert!(parse(&[0x12, 0x34, 0x01]).is_none());
+
+        let mut data = header(0x0100, 1, 0, 0);
+        question(&mut data, "www.example.com", TYPE_A);
+        for len in HEADER_LEN..data.len() - 4 {
+            assert!(parse(&data[..len]).is_none(), "parsed {} bytes", len);
+        }
+
+        // An A record claiming more data than the packet holds
+        let mut data = header(0x8180, 1, 1, 0);
+        question(&mut data, "example.com", TYPE_A);
+        record(&mut data, TYPE_A, &[10, 0, 0, 1]);
+        let end = data.len();
+        data[end - 5] = 0xFF;
+        assert!(parse(&data).is_none());
+    }
+
+    #[test]
+    fn truncated_authority_keeps_answers() {
+        let mut data = header(0x8180, 1, 1, 2);
+        question(&mut data, "example.com", TYPE_A);
+        record(&mut data, TYPE_A, &[10, 0, 0, 1]);
+        record(&mut data, TYPE_NS, &[0xC0, 0x0C]);
+        data.extend([0xC0]);
+        let message = parse(&data).unwrap();
+        assert_eq!(message.answers.len(), 1);
+        assert_eq!(message.authorities.len(), 1);
+    }
+
+    #[test]
+    fn rejects_oversized_names_and_reserved_labels() {
+        let long = vec!["a".repeat(63); 5].join(".");
+        let mut data = header(0x0100, 1, 0, 0);
+        question(&mut data, &long, TYPE_A);
+        assert!(parse(&data).is_none());
+
+        let mut data = header(0x0100, 1, 0, 0);
+        data.extend([0x41, b'a', 0, 0, 1, 0, 1]);
+        assert!(parse(&data).is_none());
+    }
+
+    #[test]
+    fn record_counts_are_capped() {
+        let mut data = header(0x8180, 1, u16::MAX, 0);
+        question(&mut data, "example.com", TYPE_A);
+        for _ in 0..MAX_RECORDS {
+            record(&mut data, TYPE_A, &[10, 0, 0, 1]);
+        }
+        assert_eq!(parse(&data).unwrap().answers.len(), MAX_RECORDS);
+    }
+
+    #[test]
+    fn maps_reverse_names_and_types() {
+        assert_eq!(ptr_address("1.0.168.192.in-addr.arpa"), Some("192.168.0.1".parse().unwrap()));
+        assert_eq!(ptr_address("example.com"), None);
+        assert_eq!(type_name(TYPE_AAAA), "AAAA");
+    }
+}
@@ -0,0 +1,222 @@
+// Domain names split along the Public Suffix List.
+//
+// "a.b.github.io" is subdomain "a", registrable domain "b.github.io" and
+// public suffix "github.io"; "foo.co.uk" is registrable as a whole. Both the
+// ICANN and private sections of the list are used, so hosting platforms count
+// as suffixes and each tenant is its own registrable domain.
+//
+// The list is compiled in from data/public_suffix_list.dat. A newer copy at
+// PUBLIC_SUFFIX_LIST (default public_suffix_list.dat) replaces it at startup;
+// `sniff update-suffix-list` downloads one there.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const BUILTIN_LIST: &str = include_str!("../../data/public_suffix_list.dat");
+const DEFAULT_LIST_PATH: &str = "public_suffix_list.dat";
+pub const LIST_URL: &str = "https://publicsuffix.org/list/public_suffix_list.dat";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DomainName {
+    /// The full name, lowercased and without a trailing dot
+    pub name: String,
+    /// Labels in front of the registrable domain, possibly empty
+    pub subdomain: String,
+    /// Public suffix plus one label; None when the name is itself a suffix
+    pub registrable: Option<String>,
+    pub suffix: String,
+}
+
+impl DomainName {
+    /// Splits `name` using the loaded Public Suffix List.
+    pub fn parse(name: &str) -> Self {
+        suffix_list().split(name)
+    }
+
+    /// The label just left of the suffix, e.g. "example" in www.example.co.uk.
+    pub fn registrable_label(&self) -> Option<&str> {
+        let registrable = self.registrable.as_deref()?;
+        registrable.split('.').next()
+    }
+}
+
+pub struct SuffixList {
+    rules: HashSet<String>,
+    // "*.ck" is stored as "ck"
+    wildcards: HashSet<String>,
+    // "!www.ck" is stored as "www.ck"
+    exceptions: HashSet<String>,
+}
+
+impl SuffixList {
+    /// Parses the list's text format: one rule per line, `//` comments.
+    pub fn parse(text: &str) -> Self {
+        let mut list = SuffixList {
+            rules: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new(),
+        };
+        for line in text.lines() {
+            let rule = line.split_whitespace().next().unwrap_or("");
+            if rule.is_empty() || rule.starts_with("//") {
+                continue;
+            }
+            let rule = rule.to_lowercase();
+            if let Some(exception) = rule.strip_prefix('!') {
+                list.exceptions.insert(exception.to_string());
+            } else if let Some(wildcard) = rule.strip_prefix("*.") {
+                list.wildcards.insert(wildcard.to_string());
+            } else {
+                list.rules.insert(rule);
+            }
+        }
+        list
+    }
+
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_LIST)
+    }
+
+    /// Loads PUBLIC_SUFFIX_LIST if it exists, otherwise the built-in list.
+    pub fn from_env() -> Self {
+        let path = list_path();
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let list = Self::parse(&text);
+                println!("Loaded {} public suffix rules from {}", list.len(), path);
+                list
+            }
+            Err(_) => Self::builtin(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len() + self.wildcards.len() + self.exceptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn split(&self, name: &str) -> DomainName {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let labels: Vec<&str> = name.split('.').collect();
+        let suffix_start = self.suffix_start(&labels);
+
+        let suffix = labels[suffix_start..].join(".");
+        let (subdomain, registrable) = match suffix_start {
+            0 => (String::new(), None),
+            start => (labels[..start - 1].join("."), Some(labels[start - 1..].join("."))),
+        };
+        DomainName {
+            name,
+            subdomain,
+            registrable,
+            suffix,
+        }
+    }
+
+    /// Index of the first label of the public suffix. The longest matching
+    /// rule wins, exceptions over everything; unlisted TLDs are suffixes.
+    fn suffix_start(&self, labels: &[&str]) -> usize {
+        for start in 0..labels.len() {
+            let candidate = labels[start..].join(".");
+            if self.exceptions.contains(&candidate) {
+                return start + 1;
+            }
+            if self.rules.contains(&candidate) {
+                return start;
+            }
+            if start + 1 < labels.len() && self.wildcards.contains(&labels[start + 1..].join(".")) {
+                return start;
+            }
+        }
+        labels.len().saturating_sub(1)
+    }
+}
+
+/// The list loaded at first use.
+pub fn suffix_list() -> &'static SuffixList {
+    static LIST: OnceLock<SuffixList> = OnceLock::new();
+    LIST.get_or_init(SuffixList::from_env)
+}
+
+/// Fetches the current list from publicsuffix.org into `path`, returning the
+/// number of rules.
+pub async fn download(path: &Path) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let text = reqwest::get(LIST_URL).await?.error_for_status()?.text().await?;
+    let list = SuffixList::parse(&text);
+    if list.is_empty() {
+        return Err("downloaded list has no rules".into());
+    }
+    fs::write(path, text)?;
+    Ok(list.len())
+}
+
+/// The path `from_env` reads, for writing an update to.
+pub fn list_path() -> String {
+    env::var("PUBLIC_SUFFIX_LIST").unwrap_or_else(|_| DEFAULT_LIST_PATH.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(name: &str) -> (String, Option<String>, String) {
+        let domain = SuffixList::builtin().split(name);
+        (domain.subdomain, domain.registrable, domain.suffix)
+    }
+
+    fn parts(subdomain: &str, registrable: Option<&str>, suffix: &str) -> (String, Option<String>, String) {
+        (subdomain.to_string(), registrable.map(str::to_string), suffix.to_string())
+    }
+
+    #[test]
+    fn documented_examples() {
+        assert_eq!(split("a.b.github.io"), parts("a", Some("b.github.io"), "github.io"));
+        assert_eq!(split("foo.co.uk"), parts("", Some("foo.co.uk"), "co.uk"));
+        assert_eq!(SuffixList::builtin().split("www.example.co.uk").registrable_label(), Some("example"));
+    }
+
+    #[test]
+    fn wildcard_and_exception_rules() {
+        // *.ck makes every second-level name a suffix, except !www.ck
+        assert_eq!(split("shop.foo.ck"), parts("", Some("shop.foo.ck"), "foo.ck"));
+        assert_eq!(split("a.shop.foo.ck"), parts("a", Some("shop.foo.ck"), "foo.ck"));
+        assert_eq!(split("foo.ck"), parts("", None, "foo.ck"));
+        assert_eq!(split("www.ck"), parts("", Some("www.ck"), "ck"));
+        assert_eq!(split("mail.www.ck"), parts("mail", Some("www.ck"), "ck"));
+    }
+
+    #[test]
+    fn suffixes_and_unlisted_names() {
+        assert_eq!(split("co.uk"), parts("", None, "co.uk"));
+        assert_eq!(split("github.io"), parts("", None, "github.io"));
+        // An unlisted TLD is treated as a suffix of one label
+        assert_eq!(split("printer.office.lan"), parts("printer", Some("office.lan"), "lan"));
+        assert_eq!(split("localhost"), parts("", None, "localhost"));
+        assert_eq!(split(""), parts("", None, ""));
+    }
+
+    #[test]
+    fn normalizes_case_and_trailing_dot() {
+        let domain = SuffixList::builtin().split("WWW.Example.COM.");
+        assert_eq!(domain.name, "www.example.com");
+        assert_eq!(domain.registrable.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn parses_list_format() {
+        let list = SuffixList::parse("// comment\n\ncom\n*.Example  trailing words\n!WWW.example\n");
+        assert_eq!(list.len(), 3);
+        assert!(!list.is_empty());
+        assert_eq!(list.split("a.b.example").registrable.as_deref(), Some("a.b.example"));
+        assert_eq!(list.split("x.www.example").registrable.as_deref(), Some("www.example"));
+        assert!(SuffixList::parse("// only comments\n").is_empty());
+    }
+}
@@ -0,0 +1,472 @@
+// Passive OS fingerprinting from client SYN packets, p0f style.
+//
+// A SYN shows how its sender's TCP stack is tuned: the initial TTL, whether
+// it sets DF and a non-zero IP ID, the window size (often a multiple of the
+// MSS), the window scale and the order of its TCP options. Together these
+// tell Windows from Linux from macOS without sending the host anything.
+//
+// Signatures use p0f 3 syntax. A starter set is compiled in from
+// data/os_signatures.fp; a p0f.fp at OS_SIGNATURES (default p0f.fp) replaces
+// it at startup. Only its [tcp:request] section is read. A SYN matching no
+// signature exactly is matched again ignoring the IP ID/DF/ECN quirks, which
+// firewalls and NAT rewrite, and the result is marked fuzzy.
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::sync::OnceLock;
+
+const BUILTIN_SIGNATURES: &str = include_str!("../../data/os_signatures.fp");
+const DEFAULT_SIGNATURES_PATH: &str = "p0f.fp";
+const SECTION: &str = "[tcp:request]";
+const INITIAL_TTLS: [u8; 4] = [32, 64, 128, 255];
+// A SYN whose TTL is further below a signature's initial TTL than this
+// cannot be from that stack
+const MAX_DISTANCE: u8 = 35;
+// Quirks middleboxes rewrite, ignored by fuzzy matching
+const FUZZY_QUIRKS: [&str; 4] = ["df", "id+", "id-", "ecn"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TcpSyn {
+    /// 4 or 6
+    pub version: u8,
+    /// TTL or hop limit as received
+    pub ttl: u8,
+    pub ip_options_len: u8,
+    pub window: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mss: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_scale: Option<u8>,
+    /// Option kinds in order, e.g. ["mss", "nop", "ws", "nop", "nop", "sok"]
+    pub layout: Vec<String>,
+    /// Sorted p0f quirks, e.g. ["df", "id+"]
+    pub quirks: Vec<String>,
+    pub payload: bool,
+}
+
+impl TcpSyn {
+    /// The TTL the sender most likely started from.
+    pub fn initial_ttl(&self) -> u8 {
+        INITIAL_TTLS.into_iter().find(|ttl| *ttl >= self.ttl).unwrap_or(u8::MAX)
+    }
+
+    /// Routers between the sender and the capture point.
+    pub fn distance(&self) -> u8 {
+        self.initial_ttl() - self.ttl
+    }
+
+    /// The SYN in p0f signature syntax, with the distance after the TTL.
+    pub fn signature(&self) -> String {
+        format!(
+            "{}:{}+{}:{}:{}:{},{}:{}:{}:{}",
+            self.version,
+            self.initial_ttl(),
+            self.distance(),
+            self.ip_options_len,
+            self.mss.map(|mss| mss.to_string()).unwrap_or_else(|| "*".to_string()),
+            self.window,
+            self.window_scale.unwrap_or(0),
+            self.layout.join(","),
+            self.quirks.join(","),
+            if self.payload { "+" } else { "0" }
+        )
+    }
+}
+
+/// What a SYN's TCP options say: their order, the MSS and window scale, and
+/// the option quirks (ts1-, ts2+, exws, opt+, bad).
+#[derive(Debug, Default)]
+pub struct TcpOptions {
+    pub layout: Vec<String>,
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+    pub quirks: Vec<&'static str>,
+}
+
+pub fn read_options(data: &[u8]) -> TcpOptions {
+    let mut options = TcpOptions::default();
+    let mut offset = 0;
+    while let Some(&kind) = data.get(offset) {
+        match kind {
+            0 => {
+                let padding = &data[offset + 1..];
+                options.layout.push(format!("eol+{}", padding.len()));
+                if padding.iter().any(|b| *b != 0) {
+                    options.quirks.push("opt+");
+                }
+                break;
+            }
+            1 => {
+                options.layout.push("nop".to_string());
+                offset += 1;
+                continue;
+            }
+            _ => {}
+        }
+        let len = data.get(offset + 1).copied().unwrap_or(0) as usize;
+        let Some(value) = data.get(offset + 2..offset + len.max(2)).filter(|_| len >= 2) else {
+            options.quirks.push("bad");
+            break;
+        };
+        match (kind, value.len()) {
+            (2, 2) => {
+                options.layout.push("mss".to_string());
+                options.mss = Some(u16::from_be_bytes([value[0], value[1]]));
+            }
+            (3, 1) => {
+                options.layout.push("ws".to_string());
+                options.window_scale = Some(value[0]);
+                if value[0] > 14 {
+                    options.quirks.push("exws");
+                }
+            }
+            (4, 0) => options.layout.push("sok".to_string()),
+            (5, _) => options.layout.push("sack".to_string()),
+            (8, 8) => {
+                options.layout.push("ts".to_string());
+                if value[..4] == [0; 4] {
+                    options.quirks.push("ts1-");
+                }
+                // A SYN has nothing to echo yet
+                if value[4..] != [0; 4] {
+                    options.quirks.push("ts2+");
+                }
+            }
+            _ => options.layout.push(format!("?{}", kind)),
+        }
+        offset += len;
+    }
+    options
+}
+
+/// The OS a signature names.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OsMatch {
+    /// p0f class: win, unix, ...
+    pub class: String,
+    pub name: String,
+    #[serde(default)]
+    pub flavor: String,
+    /// Only a catch-all signature for the OS matched
+    #[serde(default)]
+    pub generic: bool,
+    /// Matched only with the IP ID/DF/ECN quirks ignored
+    #[serde(default)]
+    pub fuzzy: bool,
+}
+
+impl fmt::Display for OsMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.flavor.is_empty() {
+            true => write!(f, "{}", self.name),
+            false => write!(f, "{} {}", self.name, self.flavor),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum WindowSize {
+    Any,
+    Exact(u16),
+    MssMultiple(u32),
+    MtuMultiple(u32),
+    Modulo(u16),
+}
+
+#[derive(Debug, Clone)]
+struct Signature {
+    label: usize,
+    version: Option<u8>,
+    ttl: u8,
+    ip_options_len: Option<u8>,
+    mss: Option<u16>,
+    window: WindowSize,
+    scale: Option<u8>,
+    layout: Vec<String>,
+    quirks: Vec<String>,
+    payload: Option<bool>,
+}
+
+impl Signature {
+    /// ver:ittl:olen:mss:wsize,scale:olayout:quirks:pclass
+    fn parse(text: &str, label: usize) -> Option<Self> {
+        let fields: Vec<&str> = text.split(':').map(str::trim).collect();
+        let [version, ttl, olen, mss, window, layout, quirks, pclass] = fields[..] else { return None };
+        let (window, scale) = window.split_once(',')?;
+        let window = match window {
+            "*" => WindowSize::Any,
+            w if w.starts_with("mss*") => WindowSize::MssMultiple(w[4..].parse().ok()?),
+            w if w.starts_with("mtu*") => WindowSize::MtuMultiple(w[4..].parse().ok()?),
+            w if w.starts_with('%') => WindowSize::Modulo(w[1..].parse().ok()?),
+            w => WindowSize::Exact(w.parse().ok()?),
+        };
+        // "64-" marks a TTL p0f saw bent by the stack; the number is what counts
+        let ttl_digits: String = ttl.chars().take_while(char::is_ascii_digit).collect();
+        let mut quirks: Vec<String> = quirks.split(',').filter(|q| !q.is_empty()).map(str::to_string).collect();
+        quirks.sort();
+        Some(Signature {
+            label,
+            version: wildcard(version)?,
+            ttl: ttl_digits.parse().ok()?,
+            ip_options_len: wildcard(olen)?,
+            mss: wildcard(mss)?,
+            window,
+            scale: wildcard(scale)?,
+            layout: layout.split(',').filter(|o| !o.is_empty()).map(str::to_string).collect(),
+            quirks,
+            payload: match pclass {
+                "0" => Some(false),
+                "+" => Some(true),
+                _ => None,
+            },
+        })
+    }
+
+    fn matches(&self, syn: &TcpSyn, fuzzy: bool) -> bool {
+        let window = match self.window {
+            WindowSize::Any => true,
+            WindowSize::Exact(window) => syn.window == window,
+            // A signature may name any multiplier; one that overflows cannot match
+            WindowSize::MssMultiple(n) => syn.mss.is_some_and(|mss| (mss as u32).checked_mul(n) == Some(syn.window as u32)),
+            WindowSize::MtuMultiple(n) => {
+                let header = if syn.version == 4 { 40 } else { 60 };
+                syn.mss.is_some_and(|mss| (mss as u32 + header).checked_mul(n) == Some(syn.window as u32))
+            }
+            WindowSize::Modulo(n) => n != 0 && syn.window.is_multiple_of(n),
+        };
+        let quirks = match fuzzy {
+            false => self.quirks == syn.quirks,
+            true => {
+                let relevant = |quirks: &[String]| -> Vec<String> {
+                    quirks.iter().filter(|q| !FUZZY_QUIRKS.contains(&q.as_str())).cloned().collect()
+                };
+                relevant(&self.quirks) == relevant(&syn.quirks)
+            }
+        };
+        window
+            && quirks
+            && self.version.is_none_or(|version| version == syn.version)
+            && syn.ttl <= self.ttl
+            && self.ttl - syn.ttl <= MAX_DISTANCE
+            && self.ip_options_len.is_none_or(|len| len == syn.ip_options_len)
+            && self.mss.is_none_or(|mss| Some(mss) == syn.mss)
+            && self.scale.is_none_or(|scale| scale == syn.window_scale.unwrap_or(0))
+            && self.layout == syn.layout
+            && self.payload.is_none_or(|payload| payload == syn.payload)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SignatureDatabase {
+    labels: Vec<OsMatch>,
+    signatures: Vec<Signature>,
+}
+
+impl SignatureDatabase {
+    /// Parses the [tcp:request] section of a p0f.fp file. Signatures this
+    /// matcher cannot read are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut database = SignatureDatabase::default();
+        let mut in_section = false;
+        let mut label = None;
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.starts_with('[') {
+                in_section = line == SECTION;
+                label = None;
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=').filter(|_| in_section) else { continue };
+            match key.trim() {
+                "label" => {
+                    label = parse_label(value.trim()).map(|os| {
+                        database.labels.push(os);
+                        database.labels.len() - 1
+                    });
+                }
+                "sig" => {
+                    if let Some(signature) = label.and_then(|label| Signature::parse(value, label)) {
+                        database.signatures.push(signature);
+                    }
+                }
+                _ => {}
+            }
+        }
+        database
+    }
+
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_SIGNATURES)
+    }
+
+    /// Loads OS_SIGNATURES if it exists and has signatures, otherwise the
+    /// built-in ones.
+    pub fn from_env() -> Self {
+        let path = env::var("OS_SIGNATURES").unwrap_or_else(|_| DEFAULT_SIGNATURES_PATH.to_string());
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let database = Self::parse(&text);
+                if database.is_empty() {
+                    eprintln!("No usable OS signatures in {}, using the built-in set", path);
+                    return Self::builtin();
+                }
+                println!("Loaded {} OS signatures from {}", database.len(), path);
+                database
+            }
+            Err(_) => Self::builtin(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.signatures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    /// The first signature the SYN matches exactly, else the first it
+    /// matches fuzzily.
+    pub fn identify(&self, syn: &TcpSyn) -> Option<OsMatch> {
+        [false, true].into_iter().find_map(|fuzzy| {
+            let signature = self.signatures.iter().find(|s| s.matches(syn, fuzzy))?;
+            let mut os = self.labels[signature.label].clone();
+            os.fuzzy = fuzzy;
+            Some(os)
+        })
+    }
+}
+
+/// The signatures loaded at first use.
+pub fn database() -> &'static SignatureDatabase {
+    static DATABASE: OnceLock<SignatureDatabase> = OnceLock::new();
+    DATABASE.get_or_init(SignatureDatabase::from_env)
+}
+
+/// The OS that most likely sent `syn`, per the loaded signatures.
+pub fn identify(syn: &TcpSyn) -> Option<OsMatch> {
+    database().identify(syn)
+}
+
+/// "s:unix:Linux:3.11 and newer"
+fn parse_label(text: &str) -> Option<OsMatch> {
+    let mut parts = text.splitn(4, ':');
+    let generic = match parts.next()? {
+        "s" => false,
+        "g" => true,
+        _ => return None,
+    };
+    Some(OsMatch {
+        class: parts.next()?.to_string(),
+        name: parts.next()?.to_string(),
+        flavor: parts.next().unwrap_or("").to_string(),
+        generic,
+        fuzzy: false,
+    })
+}
+
+/// `*` as None, anything else parsed; None if it doesn't parse.
+fn wildcard<T: std::str::FromStr>(field: &str) -> Option<Option<T>> {
+    match field {
+        "*" => Some(None),
+        value => value.parse().ok().map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // mss 1460, sackOK, timestamps, nop, window scale 7: a Linux SYN
+    const LINUX_OPTIONS: [u8; 20] = [2, 4, 0x05, 0xb4, 4, 2, 8, 10, 0, 0, 0x12, 0x34, 0, 0, 0, 0, 1, 3, 3, 7];
+
+    fn syn(ttl: u8, window: u16, options: &[u8], quirks: &[&str]) -> TcpSyn {
+        let options = read_options(options);
+        let mut quirks: Vec<String> = quirks.iter().map(|q| q.to_string()).chain(options.quirks.iter().map(|q| q.to_string())).collect();
+        quirks.sort();
+        TcpSyn {
+            version: 4,
+            ttl,
+            ip_options_len: 0,
+            window,
+            mss: options.mss,
+            window_scale: options.window_scale,
+            layout: options.layout,
+            quirks,
+            payload: false,
+        }
+    }
+
+    #[test]
+    fn reads_option_layout() {
+        let options = read_options(&LINUX_OPTIONS);
+        assert_eq!(options.layout, ["mss", "sok", "ts", "nop", "ws"]);
+        assert_eq!(options.mss, Some(1460));
+        assert_eq!(options.window_scale, Some(7));
+        assert!(options.quirks.is_empty());
+    }
+
+    #[test]
+    fn reads_option_quirks() {
+        // Zero own timestamp, non-zero echo, excessive scale, padding after EOL
+        let options = read_options(&[8, 10, 0, 0, 0, 0, 0, 0, 0, 1, 3, 3, 15, 0, 0, 9]);
+        assert_eq!(options.layout, ["ts", "ws", "eol+2"]);
+        assert_eq!(options.quirks, ["ts1-", "ts2+", "exws", "opt+"]);
+
+        // Lengths that run past the options or are too short to be valid
+        assert_eq!(read_options(&[2, 4, 0x05]).quirks, ["bad"]);
+        assert_eq!(read_options(&[1, 2, 1]).quirks, ["bad"]);
+        assert_eq!(read_options(&[2]).quirks, ["bad"]);
+        assert_eq!(read_options(&[99, 2, 1]).layout, ["?99", "nop"]);
+        assert_eq!(read_options(&[]).layout, Vec::<String>::new());
+    }
+
+    #[test]
+    fn syn_signature_and_distance() {
+        let syn = syn(57, 64240, &LINUX_OPTIONS, &["df", "id+"]);
+        assert_eq!(syn.initial_ttl(), 64);
+        assert_eq!(syn.distance(), 7);
+        assert_eq!(syn.signature(), "4:64+7:0:1460:64240,7:mss,sok,ts,nop,ws:df,id+:0");
+    }
+
+    #[test]
+    fn identifies_builtin_signatures() {
+        let database = SignatureDatabase::builtin();
+        let linux = database.identify(&syn(64, 64240, &LINUX_OPTIONS, &["df", "id+"])).unwrap();
+        assert_eq!(linux.to_string(), "Linux 4.x and newer");
+        assert!(!linux.fuzzy);
+
+        // A middlebox cleared DF and the IP ID
+        let rewritten = database.identify(&syn(64, 64240, &LINUX_OPTIONS, &[])).unwrap();
+        assert_eq!(rewritten.name, "Linux");
+        assert!(rewritten.fuzzy);
+
+        // Too far below the initial TTL
+        assert!(database.identify(&syn(20, 64240, &LINUX_OPTIONS, &["df", "id+"])).is_none());
+    }
+
+    #[test]
+    fn parses_p0f_files() {
+        let database = SignatureDatabase::parse(
+            "[tcp:response]\nlabel = s:unix:Other:x\nsig = *:64:0:*:*,0:mss:df:0\n\
+             [tcp:request]\nlabel = g:unix:Linux:\nsig = *:64-:0:*:%8192,*:mss:: ; comment\nsig = not a signature\n\
+             label = bad\nsig = *:64:0:*:*,0:mss::0\n",
+        );
+        assert_eq!(database.len(), 1);
+        let os = database.identify(&syn(60, 16384, &[2, 4, 0x05, 0xb4], &[])).unwrap();
+        assert!(os.generic);
+        assert_eq!(os.to_string(), "Linux");
+    }
+
+    #[test]
+    fn large_window_multipliers_do_not_overflow() {
+        let database = SignatureDatabase::parse(&format!(
+            "[tcp:request]\nlabel = s:unix:Test:\nsig = *:64:0:*:mss*{0},0:mss::0\nsig = *:64:0:*:mtu*{0},0:mss::0\n",
+            u32::MAX
+        ));
+        assert_eq!(database.len(), 2);
+        assert!(database.identify(&syn(64, 65535, &[2, 4, 0xff, 0xff], &[])).is_none());
+    }
+}
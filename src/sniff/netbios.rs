@@ -0,0 +1,130 @@
+// NetBIOS Name Service decoding (RFC 1002), enough for the asset inventory:
+// the first name in a packet, decoded from its half-ASCII form, and the
+// address from the first NB resource record. Registrations and refreshes
+// carry the sender's own name; positive query responses the name the
+// responder owns.
+
+use serde::Serialize;
+use std::net::Ipv4Addr;
+
+pub const PORT: u16 = 137;
+
+pub const OPCODE_QUERY: u8 = 0;
+pub const OPCODE_REGISTRATION: u8 = 5;
+pub const OPCODE_REFRESH: u8 = 8;
+// Windows sends refreshes with the obsolete opcode 9
+pub const OPCODE_REFRESH_ALT: u8 = 9;
+
+const HEADER_LEN: usize = 12;
+const ENCODED_NAME_LEN: usize = 32;
+const TYPE_NB: u16 = 0x0020;
+const NB_FLAG_GROUP: u16 = 0x8000;
+const MAX_SCOPE_LABELS: usize = 16;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetbiosName {
+    pub is_response: bool,
+    pub opcode: u8,
+    pub rcode: u8,
+    /// The name without its padding, e.g. "WORKSTATION1"
+    pub name: String,
+    /// The 16th byte: 0x00 workstation, 0x20 file server, 0x1B/0x1C/0x1D domain roles
+    pub suffix: u8,
+    /// Whether the name is a group name rather than one host's
+    pub group: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<Ipv4Addr>,
+}
+
+impl NetbiosName {
+    /// Whether the packet tells us the name belongs to `address` (or the
+    /// sender): a registration, a refresh or a positive query response.
+    pub fn is_claim(&self) -> bool {
+        match (self.is_response, self.opcode) {
+            (false, OPCODE_REGISTRATION | OPCODE_REFRESH | OPCODE_REFRESH_ALT) => true,
+            (true, OPCODE_QUERY) => self.rcode == 0 && self.address.is_some(),
+            _ => false,
+        }
+    }
+
+    /// A unique workstation or server name, the one that names the host.
+    pub fn is_host_name(&self) -> bool {
+        !self.group && (self.suffix == 0x00 || self.suffix == 0x20)
+    }
+}
+
+pub fn parse(data: &[u8]) -> Option<NetbiosName> {
+    if data.len() < HEADER_LEN {
+        return None;
+    }
+    let flags = u16::from_be_bytes([data[2], data[3]]);
+    let qdcount = u16::from_be_bytes([data[4], data[5]]);
+    let records = u16::from_be_bytes([data[6], data[7]]) as usize
+        + u16::from_be_bytes([data[8], data[9]]) as usize
+        + u16::from_be_bytes([data[10], data[11]]) as usize;
+
+    // The first name is the question's, or with no question the first record's
+    let (raw, next) = read_name(data, HEADER_LEN)?;
+    let mut nb_flags = 0;
+    let mut address = None;
+    if records > 0 {
+        let next = match qdcount {
+            0 => next,
+            _ => skip_name(data, next + 4)?, // past the question type and class
+        };
+        let rtype = u16::from_be_bytes(data.get(next..next + 2)?.try_into().ok()?);
+        let rdlength = u16::from_be_bytes(data.get(next + 8..next + 10)?.try_into().ok()?) as usize;
+        let rdata = data.get(next + 10..next + 10 + rdlength)?;
+        if rtype == TYPE_NB && rdata.len() >= 6 {
+            nb_flags = u16::from_be_bytes([rdata[0], rdata[1]]);
+            address = Some(Ipv4Addr::new(rdata[2], rdata[3], rdata[4], rdata[5]));
+        }
+    }
+
+    let name = String::from_utf8_lossy(&raw[..15]).trim_end().to_string();
+    if name.is_empty() || name.starts_with('*') {
+        return None;
+    }
+    Some(NetbiosName {
+        is_response: flags & 0x8000 != 0,
+        opcode: ((flags >> 11) & 0x0F) as u8,
+        rcode: (flags & 0x000F) as u8,
+        name,
+        suffix: raw[15],
+        group: nb_flags & NB_FLAG_GROUP != 0,
+        address,
+    })
+}
+
+/// Decodes the first-level encoded 16-byte name at `offset`, skipping any
+/// scope labels, and returns it with the offset past the name.
+fn read_name(data: &[u8], offset: usize) -> Option<([u8; 16], usize)> {
+    if *data.get(offset)? as usize != ENCODED_NAME_LEN {
+        return None;
+    }
+    let encoded = data.get(offset + 1..offset + 1 + ENCODED_NAME_LEN)?;
+    let mut raw = [0u8; 16];
+    for (i, pair) in encoded.chunks(2).enumerate() {
+        let (high, low) = (pair[0].checked_sub(b'A')?, pair[1].checked_sub(b'A')?);
+        if high > 0x0F || low > 0x0F {
+            return None;
+        }
+        raw[i] = (high << 4) | low;
+    }
+    let end = skip_name(data, offset)?;
+    Some((raw, end))
+}
+
+/// The offset past a name, which may be a compression pointer.
+fn skip_name(data: &[u8], mut offset: usize) -> Option<usize> {
+    for _ in 0..MAX_SCOPE_LABELS {
+        let len = *data.get(offset)? as usize;
+        match len & 0xC0 {
+            0x00 if len == 0 => return Some(offset + 1),
+            0x00 => offset += len + 1,
+            0xC0 => return Some(offset + 2),
+            _ => return None,
+        }
+    }
+    None
+}
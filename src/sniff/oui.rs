@@ -0,0 +1,154 @@
+// MAC address vendors from the IEEE registry.
+//
+// Assignments are 24-bit (MA-L), 28-bit (MA-M) or 36-bit (MA-S) prefixes; a
+// lookup takes the longest one that matches. A starter registry is compiled
+// in from data/oui.csv. A full copy at OUI_DATABASE (default oui.csv)
+// replaces it at startup; `sniff update-oui` downloads one there.
+//
+// Addresses with the locally administered bit set are not from any vendor's
+// block. Phones and laptops use random ones of these for privacy and rotate
+// them, so a new MAC on a known address is not necessarily a new device.
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const BUILTIN_DATABASE: &str = include_str!("../../data/oui.csv");
+const DEFAULT_DATABASE_PATH: &str = "oui.csv";
+pub const REGISTRY_URLS: [&str; 3] = [
+    "https://standards-oui.ieee.org/oui/oui.csv",
+    "https://standards-oui.ieee.org/oui28/mam.csv",
+    "https://standards-oui.ieee.org/oui36/oui36.csv",
+];
+const HEADER: &str = "Registry,Assignment,Organization Name,Organization Address";
+
+#[derive(Debug, Default)]
+pub struct OuiDatabase {
+    // Prefix length in bits -> prefix -> organization
+    blocks: HashMap<u32, HashMap<u64, String>>,
+}
+
+impl OuiDatabase {
+    /// Parses registry CSV: Registry, Assignment (hex prefix), Organization
+    /// Name, Organization Address. Lines starting with `#` are comments.
+    pub fn parse(text: &str) -> Self {
+        let mut database = OuiDatabase::default();
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .flexible(true)
+            .from_reader(text.as_bytes());
+        for record in reader.records().flatten() {
+            let (Some(assignment), Some(organization)) = (record.get(1), record.get(2)) else { continue };
+            let assignment = assignment.trim();
+            let bits = assignment.len() as u32 * 4;
+            if ![24, 28, 36].contains(&bits) {
+                continue;
+            }
+            if let Ok(prefix) = u64::from_str_radix(assignment, 16) {
+                database.blocks.entry(bits).or_default().insert(prefix, organization.trim().to_string());
+            }
+        }
+        database
+    }
+
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_DATABASE)
+    }
+
+    /// Loads OUI_DATABASE if it exists, otherwise the built-in registry.
+    pub fn from_env() -> Self {
+        let path = database_path();
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let database = Self::parse(&text);
+                println!("Loaded {} MAC address assignments from {}", database.len(), path);
+                database
+            }
+            Err(_) => Self::builtin(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The organization the address's block is assigned to.
+    pub fn vendor(&self, mac: &str) -> Option<&str> {
+        let address = mac_bits(mac)?;
+        [36, 28, 24]
+            .into_iter()
+            .find_map(|bits| self.blocks.get(&bits)?.get(&(address >> (48 - bits))))
+            .map(String::as_str)
+    }
+}
+
+/// The registry loaded at first use.
+pub fn database() -> &'static OuiDatabase {
+    static DATABASE: OnceLock<OuiDatabase> = OnceLock::new();
+    DATABASE.get_or_init(OuiDatabase::from_env)
+}
+
+/// The vendor of `mac` per the loaded registry.
+pub fn vendor(mac: &str) -> Option<&'static str> {
+    database().vendor(mac)
+}
+
+/// Whether `mac` has the locally administered bit set, as randomized
+/// (private) addresses do.
+pub fn is_randomized(mac: &str) -> bool {
+    first_octet(mac).is_some_and(|octet| octet & 0x02 != 0 && octet & 0x01 == 0)
+}
+
+/// `mac` followed by its vendor, or a note that it is randomized.
+pub fn describe(mac: &str) -> String {
+    match vendor(mac) {
+        Some(vendor) => format!("{} ({})", mac, vendor),
+        None if is_randomized(mac) => format!("{} (randomized)", mac),
+        None => mac.to_string(),
+    }
+}
+
+fn first_octet(mac: &str) -> Option<u8> {
+    u8::from_str_radix(mac.get(..2)?, 16).ok()
+}
+
+/// The 48 address bits of "AA:BB:CC:DD:EE:FF" (or with dashes).
+fn mac_bits(mac: &str) -> Option<u64> {
+    let hex: String = mac.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if hex.len() != 12 {
+        return None;
+    }
+    u64::from_str_radix(&hex, 16).ok()
+}
+
+/// Fetches the MA-L, MA-M and MA-S registries into one CSV at `path`,
+/// returning the number of assignments.
+pub async fn download(path: &Path) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let mut combined = format!("{}\n", HEADER);
+    for url in REGISTRY_URLS {
+        let text = reqwest::get(url).await?.error_for_status()?.text().await?;
+        // Each export starts with the same header row
+        for line in text.lines().skip(1) {
+            combined.push_str(line);
+            combined.push('\n');
+        }
+    }
+    let database = OuiDatabase::parse(&combined);
+    if database.is_empty() {
+        return Err("downloaded registry has no assignments".into());
+    }
+    fs::write(path, combined)?;
+    Ok(database.len())
+}
+
+/// The path `from_env` reads, for writing an update to.
+pub fn database_path() -> String {
+    env::var("OUI_DATABASE").unwrap_or_else(|_| DEFAULT_DATABASE_PATH.to_string())
+}
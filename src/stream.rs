@@ -0,0 +1,564 @@
+// Streaming detection: detectors see every event as it comes off the capture
+// channel and keep their own sliding-window state, so an alert fires on the
+// event that crosses the threshold instead of on the next Mongo pass.
+//
+// Rules from the RuleSet run through RuleDetector; detectors that need more
+// than a window aggregate implement StreamDetector and are added to the
+// StreamEngine alongside it.
+
+use crate::detection::Detection;
+use crate::rules::{self, AggregateKind, FieldFilter, FilterOp, Rule, RuleSet};
+use crate::sniff::{dns, NetworkEvent};
+use regex::Regex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+// Each window is split into this many buckets; expired buckets drop off whole
+const BUCKETS_PER_WINDOW: f64 = 30.0;
+// A group that is already alerting fires again once its value doubles
+const REFIRE_FACTOR: f64 = 2.0;
+// Cap on values remembered per group for {{values}}
+const MAX_COLLECTED: usize = 64;
+// HyperLogLog precision: 2^8 registers, ~6.5% standard error
+const HLL_PRECISION: u32 = 8;
+
+pub trait StreamDetector: Send {
+    /// Called for every captured event.
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>);
+
+    /// Called about once a second, for detectors that evaluate on a timer or
+    /// need to expire state while the network is quiet.
+    fn tick(&mut self, _now: f64, _detections: &mut Vec<Detection>) {}
+}
+
+pub struct StreamEngine {
+    detectors: Vec<Box<dyn StreamDetector>>,
+}
+
+impl StreamEngine {
+    pub fn new() -> Self {
+        StreamEngine { detectors: Vec::new() }
+    }
+
+    pub fn with_rules(rules: &RuleSet) -> Self {
+        let mut engine = Self::new();
+        for rule in rules.rules() {
+            engine.add(Box::new(RuleDetector::new(rule.clone())));
+        }
+        engine
+    }
+
+    pub fn add(&mut self, detector: Box<dyn StreamDetector>) {
+        self.detectors.push(detector);
+    }
+
+    pub fn observe(&mut self, event: &NetworkEvent) -> Vec<Detection> {
+        let mut detections = Vec::new();
+        for detector in &mut self.detectors {
+            detector.observe(event, &mut detections);
+        }
+        detections
+    }
+
+    pub fn tick(&mut self, now: f64) -> Vec<Detection> {
+        let mut detections = Vec::new();
+        for detector in &mut self.detectors {
+            detector.tick(now, &mut detections);
+        }
+        detections
+    }
+}
+
+/// A field value read from an event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Str(String),
+    Num(f64),
+}
+
+impl FieldValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FieldValue::Num(n) => Some(*n),
+            FieldValue::Str(s) => s.parse().ok(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Str(s) => f.write_str(s),
+            FieldValue::Num(n) => f.write_str(&rules::format_number(*n)),
+        }
+    }
+}
+
+/// Splits "ip:port" into its parts.
+pub fn split_endpoint(endpoint: &str) -> Option<(&str, u16)> {
+    let (ip, port) = endpoint.rsplit_once(':')?;
+    Some((ip, port.parse().ok()?))
+}
+
+/// Splits ARP's "MAC (IP)" into its parts.
+pub fn split_arp_endpoint(endpoint: &str) -> Option<(&str, &str)> {
+    let (mac, rest) = endpoint.split_once(" (")?;
+    Some((mac, rest.strip_suffix(')')?))
+}
+
+/// Reads a rule field from an event, including the derived address fields.
+pub fn event_field(event: &NetworkEvent, field: &str) -> Option<FieldValue> {
+    let value = match field {
+        "protocol" => FieldValue::Str(event.protocol.clone()),
+        "source" => FieldValue::Str(event.source.clone()),
+        "destination" => FieldValue::Str(event.destination.clone()),
+        "payload_size" => FieldValue::Num(event.payload_size as f64),
+        "timestamp" => FieldValue::Num(event.timestamp),
+        "src_ip" => FieldValue::Str(endpoint_ip(&event.source).to_string()),
+        "dest_ip" => FieldValue::Str(endpoint_ip(&event.destination).to_string()),
+        "src_port" => FieldValue::Num(split_endpoint(&event.source)?.1 as f64),
+        "dest_port" => FieldValue::Num(split_endpoint(&event.destination)?.1 as f64),
+        "src_mac" => FieldValue::Str(split_arp_endpoint(&event.source)?.0.to_string()),
+        "dest_mac" => FieldValue::Str(split_arp_endpoint(&event.destination)?.0.to_string()),
+        "query" => FieldValue::Str(event.dns.as_ref()?.query_name()?.to_string()),
+        "query_type" => FieldValue::Str(dns::type_name(event.dns.as_ref()?.query_type()?)),
+        "registered_domain" => FieldValue::Str(event.dns.as_ref()?.questions.first()?.registered_domain.clone()?),
+        "resolved_ip" => FieldValue::Str(event.dns.as_ref()?.resolved_ip()?.to_string()),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn endpoint_ip(endpoint: &str) -> &str {
+    split_endpoint(endpoint)
+        .map(|(ip, _)| ip)
+        .or_else(|| split_arp_endpoint(endpoint).map(|(_, ip)| ip))
+        .unwrap_or(endpoint)
+}
+
+/// A FieldFilter with its regex compiled, evaluated against events in memory.
+struct CompiledFilter {
+    filter: FieldFilter,
+    regex: Option<Regex>,
+    ranges: Vec<(f64, f64)>,
+}
+
+impl CompiledFilter {
+    fn new(filter: &FieldFilter) -> Self {
+        let regex = match filter.op {
+            FilterOp::Regex => filter.value.as_str().and_then(|r| Regex::new(r).ok()),
+            _ => None,
+        };
+        CompiledFilter {
+            filter: filter.clone(),
+            regex,
+            ranges: rules::ranges(&filter.value),
+        }
+    }
+
+    // Missing fields behave like they do in Mongo: they only satisfy the
+    // negative operators.
+    fn matches(&self, event: &NetworkEvent) -> bool {
+        let value = match event_field(event, &self.filter.field) {
+            Some(value) => value,
+            None => return matches!(self.filter.op, FilterOp::Ne | FilterOp::NotIn | FilterOp::NotInRanges),
+        };
+        let expected = &self.filter.value;
+        let in_list = || {
+            expected
+                .as_sequence()
+                .map(|items| items.iter().any(|item| yaml_equals(item, &value)))
+                .unwrap_or(false)
+        };
+        let compare = |f: fn(f64, f64) -> bool| match (value.as_f64(), expected.as_f64()) {
+            (Some(a), Some(b)) => f(a, b),
+            _ => false,
+        };
+        let in_ranges = || {
+            value
+                .as_f64()
+                .map(|v| self.ranges.iter().any(|(lo, hi)| v >= *lo && v <= *hi))
+                .unwrap_or(false)
+        };
+
+        match self.filter.op {
+            FilterOp::Eq => yaml_equals(expected, &value),
+            FilterOp::Ne => !yaml_equals(expected, &value),
+            FilterOp::In => in_list(),
+            FilterOp::NotIn => !in_list(),
+            FilterOp::Gt => compare(|a, b| a > b),
+            FilterOp::Gte => compare(|a, b| a >= b),
+            FilterOp::Lt => compare(|a, b| a < b),
+            FilterOp::Lte => compare(|a, b| a <= b),
+            FilterOp::Regex => self.regex.as_ref().is_some_and(|r| r.is_match(&value.to_string())),
+            FilterOp::InRanges => in_ranges(),
+            FilterOp::NotInRanges => !in_ranges(),
+        }
+    }
+}
+
+fn yaml_equals(expected: &serde_yaml::Value, value: &FieldValue) -> bool {
+    match (expected, value) {
+        (serde_yaml::Value::String(a), FieldValue::Str(b)) => a == b,
+        (serde_yaml::Value::Number(a), FieldValue::Num(b)) => a.as_f64() == Some(*b),
+        (serde_yaml::Value::Number(a), FieldValue::Str(b)) => a.to_string() == *b,
+        (serde_yaml::Value::Bool(a), FieldValue::Str(b)) => a.to_string() == *b,
+        _ => false,
+    }
+}
+
+/// Minimal HyperLogLog for approximate distinct counts.
+#[derive(Clone)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; 1 << HLL_PRECISION],
+        }
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rest = hash << HLL_PRECISION;
+        let rank = (rest.leading_zeros() + 1).min(64 - HLL_PRECISION + 1) as u8;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (a, b) in self.registers.iter_mut().zip(&other.registers) {
+            *a = (*a).max(*b);
+        }
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+
+        // Linear counting is far more accurate while most registers are empty
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+struct WindowBucket {
+    index: i64,
+    count: u64,
+    sum: f64,
+    distinct: Option<HyperLogLog>,
+}
+
+#[derive(Default)]
+struct GroupState {
+    buckets: VecDeque<WindowBucket>,
+    collected: HashMap<String, f64>,
+    fired_value: Option<f64>,
+    last_seen: f64,
+}
+
+/// Evaluates one declarative rule over a sliding window of events.
+pub struct RuleDetector {
+    rule: Rule,
+    filters: Vec<CompiledFilter>,
+    required_filters: Vec<CompiledFilter>,
+    bucket_width: f64,
+    groups: HashMap<Vec<String>, GroupState>,
+    // Values of `requires.field` recently seen in the other protocol
+    required_seen: HashMap<String, f64>,
+    // Groups over threshold still waiting for their `requires` event, keyed
+    // by the value of `requires.equals`
+    pending: HashMap<String, Vec<Vec<String>>>,
+}
+
+impl RuleDetector {
+    pub fn new(rule: Rule) -> Self {
+        let filters = rule.filter.iter().map(CompiledFilter::new).collect();
+        let required_filters = rule
+            .requires
+            .iter()
+            .flat_map(|r| r.filter.iter().map(CompiledFilter::new))
+            .collect();
+        let bucket_width = (rule.window / BUCKETS_PER_WINDOW).max(1.0);
+
+        RuleDetector {
+            rule,
+            filters,
+            required_filters,
+            bucket_width,
+            groups: HashMap::new(),
+            required_seen: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Records a `requires` match and fires the groups that were waiting on it.
+    fn observe_required(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        let requires = match &self.rule.requires {
+            Some(requires) if requires.protocol == event.protocol => requires,
+            _ => return,
+        };
+        if !self.required_filters.iter().all(|f| f.matches(event)) {
+            return;
+        }
+        let Some(value) = event_field(event, &requires.field).map(|v| v.to_string()) else {
+            return;
+        };
+        self.required_seen.insert(value.clone(), event.timestamp);
+
+        let now = event.timestamp;
+        for key in self.pending.remove(&value).unwrap_or_default() {
+            let Some(state) = self.groups.get(&key) else { continue };
+            if state.last_seen < now - self.rule.window {
+                continue;
+            }
+            let value = self.window_value(state);
+            if value > self.rule.threshold && !state.fired_value.is_some_and(|fired| value < fired * REFIRE_FACTOR) {
+                self.fire(&key, value, now, detections);
+            }
+        }
+    }
+
+    fn fire(&mut self, key: &[String], value: f64, now: f64, detections: &mut Vec<Detection>) {
+        let state = &self.groups[key];
+        let values = self.rule.collect.as_ref().map(|_| {
+            let mut values: Vec<String> = state.collected.keys().cloned().collect();
+            values.sort();
+            values
+        });
+        if let Some(detection) = self.rule.detection(key.to_vec(), value, values, now) {
+            detections.push(detection);
+        }
+        self.groups.get_mut(key).expect("group exists").fired_value = Some(value);
+    }
+
+    fn window_value(&self, state: &GroupState) -> f64 {
+        match self.rule.aggregate.kind {
+            AggregateKind::Count => state.buckets.iter().map(|b| b.count as f64).sum(),
+            AggregateKind::Sum => state.buckets.iter().map(|b| b.sum).sum(),
+            AggregateKind::DistinctCount => {
+                let mut merged = HyperLogLog::new();
+                for sketch in state.buckets.iter().filter_map(|b| b.distinct.as_ref()) {
+                    merged.merge(sketch);
+                }
+                merged.estimate().round()
+            }
+        }
+    }
+
+    fn expire(&mut self, now: f64) {
+        let oldest = now - self.rule.window;
+        let width = self.bucket_width;
+        self.groups.retain(|_, state| {
+            while state.buckets.front().is_some_and(|b| (b.index as f64 + 1.0) * width <= oldest) {
+                state.buckets.pop_front();
+            }
+            state.collected.retain(|_, seen| *seen >= oldest);
+            if state.buckets.is_empty() {
+                return false;
+            }
+            state.last_seen >= oldest
+        });
+        self.required_seen.retain(|_, seen| *seen >= oldest);
+        let groups = &self.groups;
+        self.pending.retain(|_, keys| {
+            keys.retain(|key| groups.contains_key(key));
+            !keys.is_empty()
+        });
+    }
+}
+
+impl StreamDetector for RuleDetector {
+    fn observe(&mut self, event: &NetworkEvent, detections: &mut Vec<Detection>) {
+        self.observe_required(event, detections);
+        if event.protocol != self.rule.protocol || !self.filters.iter().all(|f| f.matches(event)) {
+            return;
+        }
+
+        let now = event.timestamp;
+        let key: Vec<String> = self
+            .rule
+            .group_by
+            .iter()
+            .map(|f| event_field(event, f).map(|v| v.to_string()).unwrap_or_default())
+            .collect();
+        let aggregate_value = self.rule.aggregate.field.as_deref().and_then(|f| event_field(event, f));
+        let collected = self.rule.collect.as_deref().and_then(|f| event_field(event, f));
+        let bucket_index = (now / self.bucket_width).floor() as i64;
+        let oldest_index = ((now - self.rule.window) / self.bucket_width).floor() as i64;
+
+        let state = self.groups.entry(key.clone()).or_default();
+        state.last_seen = now;
+        while state.buckets.front().is_some_and(|b| b.index < oldest_index) {
+            state.buckets.pop_front();
+        }
+        if state.buckets.back().map(|b| b.index) != Some(bucket_index) {
+            state.buckets.push_back(WindowBucket {
+                index: bucket_index,
+                count: 0,
+                sum: 0.0,
+                distinct: None,
+            });
+        }
+        let bucket = state.buckets.back_mut().expect("bucket was just pushed");
+        bucket.count += 1;
+        match (self.rule.aggregate.kind, &aggregate_value) {
+            (AggregateKind::Sum, Some(value)) => bucket.sum += value.as_f64().unwrap_or(0.0),
+            (AggregateKind::DistinctCount, Some(value)) => {
+                bucket.distinct.get_or_insert_with(HyperLogLog::new).insert(&value.to_string())
+            }
+            _ => {}
+        }
+        if let Some(value) = collected {
+            if state.collected.len() < MAX_COLLECTED || state.collected.contains_key(&value.to_string()) {
+                state.collected.insert(value.to_string(), now);
+            }
+        }
+
+        let state = &self.groups[&key];
+        let value = self.window_value(state);
+        if value <= self.rule.threshold {
+            self.groups.get_mut(&key).expect("group exists").fired_value = None;
+            return;
+        }
+        if state.fired_value.is_some_and(|fired| value < fired * REFIRE_FACTOR) {
+            return;
+        }
+        if let Some(requires) = &self.rule.requires {
+            let position = self.rule.group_by.iter().position(|f| *f == requires.equals);
+            let required = position.map(|i| key[i].as_str()).unwrap_or_default();
+            if !self.required_seen.contains_key(required) {
+                // Fire when the required event turns up within the window
+                let waiting = self.pending.entry(required.to_string()).or_default();
+                if !waiting.contains(&key) {
+                    waiting.push(key);
+                }
+                return;
+            }
+        }
+        self.fire(&key, value, now, detections);
+    }
+
+    fn tick(&mut self, now: f64, _detections: &mut Vec<Detection>) {
+        self.expire(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniff::dns::{DnsMessage, DnsQuestion, DnsRecord, TYPE_A};
+
+    const RULE: &str = r#"
+id: http_after_lookup
+name: HTTP After Lookup
+protocol: DNS
+window: 3600
+group_by: [dest_ip, query, resolved_ip]
+aggregate: { type: count }
+threshold: 0
+requires:
+  protocol: TCP
+  field: dest_ip
+  equals: resolved_ip
+  filter:
+    - field: dest_port
+      op: in
+      value: [80]
+severity: medium
+message: "{{query}} resolved to {{resolved_ip}}"
+"#;
+
+    fn event(protocol: &str, source: &str, destination: &str, timestamp: f64) -> NetworkEvent {
+        NetworkEvent {
+            protocol: protocol.to_string(),
+            source: source.to_string(),
+            destination: destination.to_string(),
+            payload_size: 0,
+            timestamp,
+            source_mac: None,
+            arp: None,
+            dns: None,
+            mdns: None,
+            netbios: None,
+            dhcp: None,
+            tcp_syn: None,
+        }
+    }
+
+    fn answer(query: &str, address: &str, timestamp: f64) -> NetworkEvent {
+        let mut event = event("DNS", "10.0.0.53:53", "10.0.0.5:50000", timestamp);
+        event.dns = Some(DnsMessage {
+            id: 1,
+            is_response: true,
+            opcode: 0,
+            rcode: 0,
+            questions: vec![DnsQuestion { name: query.to_string(), qtype: TYPE_A, registered_domain: None }],
+            answers: vec![DnsRecord { name: query.to_string(), rtype: TYPE_A, ttl: 60, data: address.to_string(), data_len: 4 }],
+            authorities: Vec::new(),
+        });
+        event
+    }
+
+    fn detector() -> RuleDetector {
+        RuleDetector::new(serde_yaml::from_str(RULE).expect("valid rule"))
+    }
+
+    #[test]
+    fn requirement_seen_first_fires_on_lookup() {
+        let mut detector = detector();
+        let mut detections = Vec::new();
+        detector.observe(&event("TCP", "10.0.0.5:50001", "203.0.113.7:80", 100.0), &mut detections);
+        detector.observe(&answer("example.test", "203.0.113.7", 101.0), &mut detections);
+        assert_eq!(detections.len(), 1);
+    }
+
+    #[test]
+    fn lookup_seen_first_fires_on_requirement() {
+        let mut detector = detector();
+        let mut detections = Vec::new();
+        detector.observe(&answer("example.test", "203.0.113.7", 100.0), &mut detections);
+        assert!(detections.is_empty());
+
+        // A connection elsewhere or to another port does not satisfy it
+        detector.observe(&event("TCP", "10.0.0.5:50001", "198.51.100.1:80", 101.0), &mut detections);
+        detector.observe(&event("TCP", "10.0.0.5:50002", "203.0.113.7:443", 102.0), &mut detections);
+        assert!(detections.is_empty());
+
+        detector.observe(&event("TCP", "10.0.0.5:50003", "203.0.113.7:80", 103.0), &mut detections);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].activity.details, "example.test resolved to 203.0.113.7");
+
+        // Fired once; later connections do not repeat it
+        detector.observe(&event("TCP", "10.0.0.5:50004", "203.0.113.7:80", 104.0), &mut detections);
+        assert_eq!(detections.len(), 1);
+    }
+
+    #[test]
+    fn requirement_after_window_does_not_fire() {
+        let mut detector = detector();
+        let mut detections = Vec::new();
+        detector.observe(&answer("example.test", "203.0.113.7", 100.0), &mut detections);
+        detector.tick(4000.0, &mut detections);
+        detector.observe(&event("TCP", "10.0.0.5:50001", "203.0.113.7:80", 4000.0), &mut detections);
+        assert!(detections.is_empty());
+    }
+}
@@ -0,0 +1,368 @@
+// Alert suppression rules.
+//
+// A rule matches alerts on any combination of activity type, source CIDR,
+// destination CIDR, destination port and a UTC time-of-day range such as
+// "22:00-06:00"; fields left out match anything. Every rule needs a comment
+// saying why it exists and an expiry time, after which it stops matching.
+// Rules live in the `suppression_rules` collection and are managed with
+// `sniff suppress` or /api/suppressions. The alert handler reloads them every
+// RELOAD_SECS and counts each suppressed alert on the rule that matched it
+// (`hits`, `last_hit`) instead of dropping it silently.
+
+use crate::detection::Detection;
+use crate::intel::{cidr_contains, parse_network};
+use crate::stream::split_endpoint;
+use futures::StreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::{Collection, Database};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::net::IpAddr;
+
+const RELOAD_SECS: f64 = 30.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuppressionRule {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activity_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_cidr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest_cidr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// "HH:MM-HH:MM" in UTC; the range may wrap past midnight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_of_day: Option<String>,
+    /// Unix time after which the rule no longer applies
+    pub expires_at: f64,
+    pub comment: String,
+    #[serde(default)]
+    pub created_at: f64,
+    #[serde(default)]
+    pub hits: i64,
+    #[serde(default)]
+    pub last_hit: Option<f64>,
+}
+
+impl SuppressionRule {
+    /// Checks a new rule before it is stored.
+    pub fn validate(&self, now: f64) -> Result<(), String> {
+        if self.comment.trim().is_empty() {
+            return Err("a comment explaining the suppression is required".to_string());
+        }
+        if self.expires_at <= now {
+            return Err("expiry must be in the future".to_string());
+        }
+        for cidr in [&self.source_cidr, &self.dest_cidr].into_iter().flatten() {
+            if parse_network(cidr).is_none() {
+                return Err(format!("invalid CIDR: {}", cidr));
+            }
+        }
+        if let Some(range) = &self.time_of_day {
+            parse_time_range(range).ok_or_else(|| format!("invalid time of day range (want HH:MM-HH:MM): {}", range))?;
+        }
+        if self.activity_type.is_none() && self.source_cidr.is_none() && self.dest_cidr.is_none() && self.port.is_none() {
+            return Err("a rule needs an activity type, CIDR or port; a time range alone would hide every alert".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, detection: &Detection, now: f64) -> bool {
+        if now > self.expires_at {
+            return false;
+        }
+        if self.activity_type.as_ref().is_some_and(|t| !t.eq_ignore_ascii_case(&detection.activity.activity_type)) {
+            return false;
+        }
+        let (source, destination, port) = alert_endpoints(detection);
+        if let Some(cidr) = &self.source_cidr {
+            if !source.is_some_and(|ip| cidr_contains(cidr, ip)) {
+                return false;
+            }
+        }
+        if let Some(cidr) = &self.dest_cidr {
+            if !destination.is_some_and(|ip| cidr_contains(cidr, ip)) {
+                return false;
+            }
+        }
+        if self.port.is_some() && port != self.port {
+            return false;
+        }
+        match self.time_of_day.as_deref().and_then(parse_time_range) {
+            Some((start, end)) => {
+                let minute = (detection.activity.timestamp as i64).div_euclid(60).rem_euclid(24 * 60) as u32;
+                if start <= end {
+                    (start..end).contains(&minute)
+                } else {
+                    minute >= start || minute < end
+                }
+            }
+            None => true,
+        }
+    }
+
+    /// One line for `sniff suppress list`.
+    pub fn describe(&self) -> String {
+        let mut criteria = Vec::new();
+        if let Some(activity_type) = &self.activity_type {
+            criteria.push(format!("type={}", activity_type));
+        }
+        if let Some(cidr) = &self.source_cidr {
+            criteria.push(format!("src={}", cidr));
+        }
+        if let Some(cidr) = &self.dest_cidr {
+            criteria.push(format!("dst={}", cidr));
+        }
+        if let Some(port) = self.port {
+            criteria.push(format!("port={}", port));
+        }
+        if let Some(range) = &self.time_of_day {
+            criteria.push(format!("time={} UTC", range));
+        }
+        criteria.join(" ")
+    }
+}
+
+/// The alert's source address and its destination address and port, from the
+/// rule's group_by variables or the indicator when it is an address.
+fn alert_endpoints(detection: &Detection) -> (Option<IpAddr>, Option<IpAddr>, Option<u16>) {
+    let activity = &detection.activity;
+    let source = activity.source.parse().ok().or_else(|| split_endpoint(&activity.source)?.0.parse().ok());
+
+    let (indicator_ip, indicator_port) = match split_endpoint(&activity.indicator) {
+        Some((ip, port)) => (ip.parse().ok(), Some(port)),
+        None => (activity.indicator.parse().ok(), None),
+    };
+    let destination = detection.vars.get("dest_ip").and_then(|ip| ip.parse().ok()).or(indicator_ip);
+    let port = detection.vars.get("dest_port").and_then(|port| port.parse().ok()).or(indicator_port);
+    (source, destination, port)
+}
+
+/// An expiry given as a duration from now ("12h", "7d", "30m") or a date
+/// ("2026-12-31", midnight UTC) or RFC 3339 time.
+pub fn parse_expiry(expiry: &str, now: f64) -> Result<f64, String> {
+    let expiry = expiry.trim();
+    let unit = match expiry.chars().last() {
+        Some('m') => 60.0,
+        Some('h') => 3600.0,
+        Some('d') => 86400.0,
+        Some('w') => 7.0 * 86400.0,
+        _ => 0.0,
+    };
+    if unit > 0.0 {
+        if let Ok(count) = expiry[..expiry.len() - 1].parse::<f64>() {
+            return Ok(now + count * unit);
+        }
+    }
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(expiry) {
+        return Ok(time.timestamp() as f64);
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(expiry, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp() as f64);
+    }
+    Err(format!("invalid expiry (want e.g. 7d, 12h, 2026-12-31): {}", expiry))
+}
+
+/// "22:00-06:00" as minutes after midnight.
+fn parse_time_range(range: &str) -> Option<(u32, u32)> {
+    let (start, end) = range.split_once('-')?;
+    Some((parse_time(start)?, parse_time(end)?))
+}
+
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// The active rules, as the alert handler sees them.
+pub struct Suppressions {
+    collection: Collection<SuppressionRule>,
+    rules: Vec<SuppressionRule>,
+    loaded_at: f64,
+}
+
+impl Suppressions {
+    pub fn new(db: &Database) -> Self {
+        Suppressions {
+            collection: db.collection("suppression_rules"),
+            rules: Vec::new(),
+            loaded_at: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Returns the rule suppressing `detection`, if any, after counting the
+    /// hit on it.
+    pub async fn check(&mut self, detection: &Detection, now: f64) -> Option<SuppressionRule> {
+        if now - self.loaded_at >= RELOAD_SECS {
+            match self.reload(now).await {
+                Ok(()) => self.loaded_at = now,
+                Err(e) => eprintln!("Error loading suppression rules: {}", e),
+            }
+        }
+
+        let rule = self.rules.iter().find(|rule| rule.matches(detection, now))?.clone();
+        if let Some(id) = rule.id {
+            let update = doc! { "$inc": { "hits": 1_i64 }, "$set": { "last_hit": now } };
+            if let Err(e) = self.collection.update_one(doc! { "_id": id }, update).await {
+                eprintln!("Error counting suppressed alert: {}", e);
+            }
+        }
+        Some(rule)
+    }
+
+    async fn reload(&mut self, now: f64) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.rules = list(&self.collection, Some(now)).await?;
+        Ok(())
+    }
+}
+
+/// Rules in the collection, only unexpired ones when `active_at` is given.
+pub async fn list(collection: &Collection<SuppressionRule>, active_at: Option<f64>) -> Result<Vec<SuppressionRule>, Box<dyn Error + Send + Sync>> {
+    let filter = match active_at {
+        Some(now) => doc! { "expires_at": { "$gt": now } },
+        None => doc! {},
+    };
+    let mut cursor = collection.find(filter).sort(doc! { "created_at": 1 }).await?;
+    let mut rules = Vec::new();
+    while let Some(rule) = cursor.next().await {
+        rules.push(rule?);
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::{Evidence, SuspiciousActivity};
+    use sniff::llm::verdict::Severity;
+    use std::collections::HashMap;
+
+    const NOW: f64 = 1_780_000_000.0;
+    // Midnight UTC
+    const DAY: f64 = 1_779_926_400.0;
+
+    fn rule() -> SuppressionRule {
+        SuppressionRule {
+            id: None,
+            activity_type: None,
+            source_cidr: None,
+            dest_cidr: None,
+            port: None,
+            time_of_day: None,
+            expires_at: NOW + 86400.0,
+            comment: "backup server".to_string(),
+            created_at: NOW,
+            hits: 0,
+            last_hit: None,
+        }
+    }
+
+    fn detection(source: &str, indicator: &str, timestamp: f64) -> Detection {
+        Detection {
+            activity: SuspiciousActivity {
+                activity_type: "Port Scan".to_string(),
+                source: source.to_string(),
+                details: String::new(),
+                indicator: indicator.to_string(),
+                magnitude: 1.0,
+                severity: Severity::Medium,
+                score: 0.0,
+                confidence: 0.5,
+                evidence: Evidence::default(),
+                rule_id: "test".to_string(),
+                timestamp,
+            },
+            actions: Vec::new(),
+            vars: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn validate_requires_comment_expiry_and_criteria() {
+        let valid = SuppressionRule { activity_type: Some("Port Scan".to_string()), ..rule() };
+        assert_eq!(valid.validate(NOW), Ok(()));
+
+        let uncommented = SuppressionRule { comment: "  ".to_string(), ..valid.clone() };
+        assert!(uncommented.validate(NOW).unwrap_err().contains("comment"));
+        let expired = SuppressionRule { expires_at: NOW, ..valid.clone() };
+        assert!(expired.validate(NOW).unwrap_err().contains("expiry"));
+
+        let bad_cidr = SuppressionRule { source_cidr: Some("10.0.0.0/33".to_string()), ..valid.clone() };
+        assert!(bad_cidr.validate(NOW).unwrap_err().contains("CIDR"));
+        let bad_range = SuppressionRule { time_of_day: Some("22:00-24:00".to_string()), ..valid };
+        assert!(bad_range.validate(NOW).unwrap_err().contains("time of day"));
+
+        // A time range alone would hide everything overnight
+        let time_only = SuppressionRule { time_of_day: Some("22:00-06:00".to_string()), ..rule() };
+        assert!(time_only.validate(NOW).is_err());
+        assert!(rule().validate(NOW).is_err());
+        let port_only = SuppressionRule { port: Some(22), ..rule() };
+        assert_eq!(port_only.validate(NOW), Ok(()));
+    }
+
+    #[test]
+    fn matches_cidrs_and_port() {
+        let rule = SuppressionRule {
+            activity_type: Some("port scan".to_string()),
+            source_cidr: Some("10.0.0.0/24".to_string()),
+            dest_cidr: Some("192.168.1.0/24".to_string()),
+            port: Some(22),
+            ..rule()
+        };
+        assert!(rule.matches(&detection("10.0.0.5:40000", "192.168.1.9:22", NOW), NOW));
+        assert!(rule.matches(&detection("10.0.0.5", "192.168.1.9:22", NOW), NOW));
+        assert!(!rule.matches(&detection("10.0.1.5", "192.168.1.9:22", NOW), NOW));
+        assert!(!rule.matches(&detection("10.0.0.5", "192.168.2.9:22", NOW), NOW));
+        assert!(!rule.matches(&detection("10.0.0.5", "192.168.1.9:2222", NOW), NOW));
+        assert!(!rule.matches(&detection("10.0.0.5", "192.168.1.9", NOW), NOW));
+        // Expired rules stop matching
+        assert!(!rule.matches(&detection("10.0.0.5", "192.168.1.9:22", NOW), NOW + 2.0 * 86400.0));
+
+        // Group-by variables take precedence over the indicator
+        let mut grouped = detection("10.0.0.5", "scan", NOW);
+        grouped.vars.insert("dest_ip".to_string(), "192.168.1.9".to_string());
+        grouped.vars.insert("dest_port".to_string(), "22".to_string());
+        assert!(rule.matches(&grouped, NOW));
+    }
+
+    #[test]
+    fn time_of_day_wraps_past_midnight() {
+        let rule = SuppressionRule {
+            activity_type: Some("Port Scan".to_string()),
+            time_of_day: Some("22:00-06:00".to_string()),
+            ..rule()
+        };
+        let at = |hours: f64, minutes: f64| detection("10.0.0.5", "192.168.1.9:22", DAY + hours * 3600.0 + minutes * 60.0);
+        assert!(rule.matches(&at(22.0, 0.0), NOW));
+        assert!(rule.matches(&at(23.0, 59.0), NOW));
+        assert!(rule.matches(&at(0.0, 0.0), NOW));
+        assert!(rule.matches(&at(5.0, 59.0), NOW));
+        assert!(!rule.matches(&at(6.0, 0.0), NOW));
+        assert!(!rule.matches(&at(12.0, 0.0), NOW));
+        assert!(!rule.matches(&at(21.0, 59.0), NOW));
+
+        let daytime = SuppressionRule { time_of_day: Some("09:00-17:30".to_string()), ..rule };
+        assert!(daytime.matches(&at(9.0, 0.0), NOW));
+        assert!(daytime.matches(&at(17.0, 29.0), NOW));
+        assert!(!daytime.matches(&at(17.0, 30.0), NOW));
+        assert!(!daytime.matches(&at(8.0, 59.0), NOW));
+    }
+
+    #[test]
+    fn expiry_durations_and_dates() {
+        assert_eq!(parse_expiry("30m", NOW), Ok(NOW + 1800.0));
+        assert_eq!(parse_expiry("12h", NOW), Ok(NOW + 43_200.0));
+        assert_eq!(parse_expiry(" 7d ", NOW), Ok(NOW + 604_800.0));
+        assert_eq!(parse_expiry("2w", NOW), Ok(NOW + 1_209_600.0));
+        assert_eq!(parse_expiry("2026-12-31", NOW), Ok(1_798_675_200.0));
+        assert_eq!(parse_expiry("2026-06-01T12:30:00Z", NOW), Ok(1_780_317_000.0));
+        assert_eq!(parse_expiry("2026-06-01T14:30:00+02:00", NOW), Ok(1_780_317_000.0));
+        assert!(parse_expiry("soon", NOW).is_err());
+        assert!(parse_expiry("xd", NOW).is_err());
+        assert!(parse_expiry("", NOW).is_err());
+    }
+}